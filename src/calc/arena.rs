@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::calc::{
+    ast::{Ast, Node, iterate},
+    context::{Context, deep},
+    function::{Function, constant},
+    parser::{Builder, build},
+    token::{Operator, Token},
+    value::Value,
+};
+
+// 节点在 ArenaAst::nodes 中的下标
+pub type NodeId = u32;
+
+// 函数的参数和列表的元素在 ArenaAst::lists 中连续存放
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    start: u32,
+    len: u32,
+}
+
+// 和 Node 一一对应，子节点是下标而不是 Box，变量名是 ArenaAst::names 中的下标
+#[derive(Debug, PartialEq, Clone)]
+pub enum ArenaNode {
+    Add(NodeId, NodeId),
+    Sub(NodeId, NodeId),
+    Mul(NodeId, NodeId),
+    Div(NodeId, NodeId),
+    Pow(NodeId, NodeId),
+    Neg(NodeId),
+    Num(Decimal),
+    Var(u32),
+    Matrix(Span),
+    Call(Function, Span),
+    // 符号计算的函数（diff、solve、expand 等）需要语法树形式的参数，构建时转换一次，
+    // 参数是 ArenaAst::trees 中的下标
+    Symbolic(Function, u32),
+    Sum(u32, NodeId, NodeId, NodeId),
+    Prod(u32, NodeId, NodeId, NodeId),
+    Eq(NodeId, NodeId),
+}
+
+// 所有的节点保存在同一个 Vec 中的语法树，构建时不需要为每个节点分配内存。
+// 子节点总是在父节点之前，最后一个节点是根
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ArenaAst {
+    nodes: Vec<ArenaNode>,
+    lists: Vec<NodeId>,
+    // 同名的变量只保存一次，index 是名字到下标的索引
+    names: Vec<String>,
+    index: HashMap<String, u32>,
+    trees: Vec<Vec<Node>>,
+}
+
+impl ArenaAst {
+    // 由逆波兰式构建，检查和错误信息与 Parser::parse 相同
+    pub fn build(tokens: Vec<Token>, max_depth: usize) -> Result<ArenaAst, String> {
+        let mut arena = ArenaAst::default();
+        build(tokens, &mut arena, max_depth)?;
+        Ok(arena)
+    }
+
+    fn root(&self) -> NodeId {
+        self.nodes.len() as NodeId - 1
+    }
+
+    fn node(&self, id: NodeId) -> &ArenaNode {
+        &self.nodes[id as usize]
+    }
+
+    fn push(&mut self, node: ArenaNode) -> NodeId {
+        self.nodes.push(node);
+        self.root()
+    }
+
+    fn span(&mut self, ids: Vec<NodeId>) -> Span {
+        let span = Span {
+            start: self.lists.len() as u32,
+            len: ids.len() as u32,
+        };
+        self.lists.extend(ids);
+        span
+    }
+
+    fn list(&self, span: Span) -> &[NodeId] {
+        &self.lists[span.start as usize..(span.start + span.len) as usize]
+    }
+
+    fn name(&self, name: u32) -> &str {
+        &self.names[name as usize]
+    }
+
+    // 转换成 Box<Node> 的树
+    fn to_node(&self, id: NodeId) -> Node {
+        use ArenaNode::*;
+        let node = |id: &NodeId| Box::new(self.to_node(*id));
+        let nodes = |span: &Span| {
+            self.list(*span)
+                .iter()
+                .map(|id| self.to_node(*id))
+                .collect()
+        };
+        deep(|| match self.node(id) {
+            Add(lhs, rhs) => Node::Add(node(lhs), node(rhs)),
+            Sub(lhs, rhs) => Node::Sub(node(lhs), node(rhs)),
+            Mul(lhs, rhs) => Node::Mul(node(lhs), node(rhs)),
+            Div(lhs, rhs) => Node::Div(node(lhs), node(rhs)),
+            Pow(lhs, rhs) => Node::Pow(node(lhs), node(rhs)),
+            Eq(lhs, rhs) => Node::Eq(node(lhs), node(rhs)),
+            Neg(val) => Node::Neg(node(val)),
+            Num(num) => Node::Num(*num),
+            Var(name) => Node::Var(self.name(*name).to_string()),
+            Matrix(elems) => Node::Matrix(nodes(elems)),
+            Call(func, args) => Node::Call(*func, nodes(args)),
+            Symbolic(func, args) => Node::Call(*func, self.trees[*args as usize].clone()),
+            Sum(var, from, to, body) => Node::Sum(
+                self.name(*var).to_string(),
+                node(from),
+                node(to),
+                node(body),
+            ),
+            Prod(var, from, to, body) => Node::Prod(
+                self.name(*var).to_string(),
+                node(from),
+                node(to),
+                node(body),
+            ),
+        })
+    }
+
+    // 只保留最外面 depth 层的变量、方程和列表，其余的节点换成 0，用于检查函数参数的形式
+    fn shape(&self, id: NodeId, depth: usize) -> Node {
+        let shape = |id: &NodeId| self.shape(*id, depth - 1);
+        match self.node(id) {
+            ArenaNode::Var(name) => Node::Var(self.name(*name).to_string()),
+            ArenaNode::Eq(lhs, rhs) if depth > 0 => {
+                Node::Eq(Box::new(shape(lhs)), Box::new(shape(rhs)))
+            }
+            ArenaNode::Matrix(elems) if depth > 0 => {
+                Node::Matrix(self.list(*elems).iter().map(shape).collect())
+            }
+            _ => Node::Num(Decimal::ZERO),
+        }
+    }
+
+    pub fn to_ast(&self) -> Ast {
+        Ast(self.to_node(self.root()))
+    }
+
+    // 和 Node::eval 的语义相同
+    fn eval_node(&self, id: NodeId, ctx: &mut Context) -> Result<Value, String> {
+        deep(|| self.eval_at(id, ctx))
+    }
+
+    fn eval_at(&self, id: NodeId, ctx: &mut Context) -> Result<Value, String> {
+        use ArenaNode::*;
+        let mut binary = |op: Operator, lhs: NodeId, rhs: NodeId| {
+            let lhs = self.eval_node(lhs, ctx)?;
+            Value::operate(op, lhs, self.eval_node(rhs, ctx)?)
+        };
+        match self.node(id) {
+            Add(lhs, rhs) => binary(Operator::Add, *lhs, *rhs),
+            Sub(lhs, rhs) => binary(Operator::Sub, *lhs, *rhs),
+            Mul(lhs, rhs) => binary(Operator::Mul, *lhs, *rhs),
+            Div(lhs, rhs) => binary(Operator::Div, *lhs, *rhs),
+            Pow(lhs, rhs) => binary(Operator::Pow, *lhs, *rhs),
+            Neg(val) => self.eval_node(*val, ctx)?.neg(),
+            Num(val) => Ok(Value::Num(*val)),
+            Var(name) => {
+                let name = self.name(*name);
+                ctx.var(name)
+                    .cloned()
+                    .or_else(|| constant(name).map(Value::Num))
+                    .ok_or_else(|| format!("Unknown variable: '{name}'"))
+            }
+            Matrix(elems) => Value::list(self.eval_all(*elems, ctx)?),
+            Symbolic(func, args) => func.call_lazy(&self.trees[*args as usize], ctx),
+            // repeat、sample 和 integrate 直接对 arena 中的参数反复求值
+            Call(func, args) if func.is_iterative() => {
+                let args = self.list(*args);
+                let var = match (func, self.node(args[1])) {
+                    (Function::Integrate, Var(var)) => self.name(*var),
+                    _ => "",
+                };
+                let mut eval = |i: usize, ctx: &mut Context| self.eval_node(args[i], ctx);
+                func.call_iterative(var, args.len(), &mut eval, ctx)
+            }
+            Call(func, args) => func.call(self.eval_all(*args, ctx)?, ctx),
+            Sum(var, from, to, body) | Prod(var, from, to, body) => {
+                let op = if matches!(self.node(id), Sum(..)) {
+                    Operator::Add
+                } else {
+                    Operator::Mul
+                };
+                let from = self.eval_node(*from, ctx)?;
+                let to = self.eval_node(*to, ctx)?;
+                iterate(op, self.name(*var), from, to, ctx, |ctx| {
+                    self.eval_node(*body, ctx)
+                })
+            }
+            Eq(..) => Err(format!(
+                "Equation '{}' can only be used as an argument of 'solve'",
+                self.to_node(id)
+            )),
+        }
+    }
+
+    fn eval_all(&self, span: Span, ctx: &mut Context) -> Result<Vec<Value>, String> {
+        self.list(span)
+            .iter()
+            .map(|id| self.eval_node(*id, ctx))
+            .collect()
+    }
+
+    pub fn eval(&self, ctx: &mut Context) -> Result<Value, String> {
+        self.eval_node(self.root(), ctx)
+    }
+}
+
+impl Builder for ArenaAst {
+    type Node = NodeId;
+
+    fn num(&mut self, num: Decimal) -> NodeId {
+        self.push(ArenaNode::Num(num))
+    }
+
+    fn var(&mut self, name: String) -> NodeId {
+        let index = match self.index.get(&name) {
+            Some(index) => *index,
+            None => {
+                let index = self.names.len() as u32;
+                self.names.push(name.clone());
+                self.index.insert(name, index);
+                index
+            }
+        };
+        self.push(ArenaNode::Var(index))
+    }
+
+    fn neg(&mut self, val: NodeId) -> NodeId {
+        self.push(ArenaNode::Neg(val))
+    }
+
+    fn binary(&mut self, op: Operator, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(match op {
+            Operator::Add => ArenaNode::Add(lhs, rhs),
+            Operator::Sub => ArenaNode::Sub(lhs, rhs),
+            Operator::Mul => ArenaNode::Mul(lhs, rhs),
+            Operator::Div => ArenaNode::Div(lhs, rhs),
+            Operator::Pow => ArenaNode::Pow(lhs, rhs),
+            Operator::Eq => ArenaNode::Eq(lhs, rhs),
+            Operator::Neg | Operator::Sqrt => unreachable!(),
+        })
+    }
+
+    fn is_eq(&self, node: &NodeId) -> bool {
+        matches!(self.node(*node), ArenaNode::Eq(..))
+    }
+
+    fn call(&mut self, func: Function, args: Vec<NodeId>) -> Result<NodeId, String> {
+        // 参数形式的检查只在 Node::call 中实现一份，检查只涉及参数最外面两层的变量、方程和列表
+        let shapes = args.iter().map(|id| self.shape(*id, 2)).collect();
+        Node::call(func, shapes)?;
+        Ok(match (func, &args[..]) {
+            (Function::Sum | Function::Prod, [var, from, to, body]) => {
+                let ArenaNode::Var(var) = *self.node(*var) else {
+                    unreachable!()
+                };
+                self.push(if func == Function::Sum {
+                    ArenaNode::Sum(var, *from, *to, *body)
+                } else {
+                    ArenaNode::Prod(var, *from, *to, *body)
+                })
+            }
+            _ if func.is_lazy() && !func.is_iterative() => {
+                let trees = args.iter().map(|id| self.to_node(*id)).collect();
+                self.trees.push(trees);
+                self.push(ArenaNode::Symbolic(func, self.trees.len() as u32 - 1))
+            }
+            _ => {
+                let span = self.span(args);
+                self.push(ArenaNode::Call(func, span))
+            }
+        })
+    }
+
+    fn list(&mut self, elems: Vec<NodeId>) -> NodeId {
+        let span = self.span(elems);
+        self.push(ArenaNode::Matrix(span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{context::DEFAULT_MAX_DEPTH, parser::Parser, tokenizer::Numerals};
+
+    #[test]
+    fn test_layout() {
+        let arena =
+            Parser::parse_arena("x*x + gcd(x, 4)", Numerals::Ascii, DEFAULT_MAX_DEPTH).unwrap();
+        assert_eq!(arena.names, ["x"]);
+        assert_eq!(arena.lists, [3, 4]);
+        assert_eq!(
+            arena.nodes,
+            [
+                ArenaNode::Var(0),
+                ArenaNode::Var(0),
+                ArenaNode::Mul(0, 1),
+                ArenaNode::Var(0),
+                ArenaNode::Num(Decimal::from(4)),
+                ArenaNode::Call(Function::Gcd, Span { start: 0, len: 2 }),
+                ArenaNode::Add(2, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_names_and_trees() {
+        let arena =
+            Parser::parse_arena("a + b*a + c - b", Numerals::Ascii, DEFAULT_MAX_DEPTH).unwrap();
+        assert_eq!(arena.names, ["a", "b", "c"]);
+        let vars: Vec<&ArenaNode> =
+            arena.nodes.iter().filter(|node| matches!(node, ArenaNode::Var(_))).collect();
+        let var = |index| ArenaNode::Var(index);
+        assert_eq!(vars, [&var(0), &var(1), &var(0), &var(2), &var(1)]);
+
+        // 符号计算的参数在构建时转换成语法树，integrate 的参数仍然留在 arena 中
+        let expr = "diff(x^2, x) + integrate(t, t, 0, 1)";
+        let arena = Parser::parse_arena(expr, Numerals::Ascii, DEFAULT_MAX_DEPTH).unwrap();
+        let Ast(Node::Add(diff, _)) = &Parser::parse(expr).unwrap() else {
+            unreachable!()
+        };
+        let Node::Call(Function::Diff, args) = &**diff else {
+            unreachable!()
+        };
+        assert_eq!(arena.trees.len(), 1);
+        assert_eq!(&arena.trees[0], args);
+        assert!(arena.nodes.contains(&ArenaNode::Symbolic(Function::Diff, 0)));
+        let integrate = |node: &ArenaNode| matches!(node, ArenaNode::Call(Function::Integrate, _));
+        assert!(arena.nodes.iter().any(integrate));
+    }
+
+    #[test]
+    fn test_same_as_box_tree() {
+        for expr in [
+            "1 + 2*3 - 4/5",
+            "-x^2 + sqrt(2)*sin(pi/4)",
+            "[[1, 2], [3, 4]]^2*[[1], [2]]",
+            "gcd(12, 18, 8) + choose(10, 3)",
+            "sum(k, 1, 10, k^2) + prod(k, 1, 5, k + 1)",
+            "sum(i, 1, 4, sum(j, i, 4, i*j))",
+            "sum(k, 1, 3, diff(t^k, t, 2))",
+            "integrate(t^2, t, 0, 1)",
+            "solve([t + u = 3, t - u = 1], [t, u])",
+            "repeat(3, rand()) + [randint(1, 6), normal(0, 1)]",
+            "expand((y + 1)^2)",
+            "sum(k, 1, 3, k) + k",
+            "1 + (x = 2)",
+            "1/0",
+        ] {
+            let ast = Parser::parse(expr).unwrap();
+            let arena = Parser::parse_arena(expr, Numerals::Ascii, DEFAULT_MAX_DEPTH).unwrap();
+            assert_eq!(arena.to_ast(), ast, "{expr}");
+            let expected = ast.eval(&mut Context::with_seed(1));
+            assert_eq!(arena.eval(&mut Context::with_seed(1)), expected, "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        // 和 Parser::parse 相同的错误
+        for expr in [
+            "sum(1, 1, n, k)",
+            "diff(1, 2)",
+            "solve(x = 1, x, 0)",
+            "sqrt(1, 2)",
+            "(x = 1) = 2",
+            "1 +",
+            "solve([x + y = 1, x = 2], [x, x])",
+            "solve([x + y = 1, 2], [x, y])",
+            "solve([x + y = 1], [x, 2])",
+            "integrate(x, 1, 0, 1)",
+        ] {
+            assert_eq!(
+                Parser::parse_arena(expr, Numerals::Ascii, DEFAULT_MAX_DEPTH).unwrap_err(),
+                Parser::parse(expr).unwrap_err(),
+                "{expr}"
+            );
+        }
+    }
+}
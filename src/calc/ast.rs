@@ -1,35 +1,312 @@
-use rust_decimal::{Decimal, MathematicalOps};
+use rust_decimal::Decimal;
 
+use crate::calc::{
+    context::{Context, deep},
+    function::{Function, constant},
+    integer::{to_decimal, to_integer},
+    linear, simplify,
+    token::Operator,
+    value::Value,
+};
+
+// 序列化的格式见 schema.rs，最外层带有格式的版本号
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::calc::schema::Document",
+        try_from = "crate::calc::schema::Document"
+    )
+)]
 pub struct Ast(pub Node);
 
-#[derive(Debug, PartialEq, Clone)]
+// Clone、PartialEq、Drop 和 Serialize 是手写的，很高的语法树（例如很长的链）不会耗尽调用栈
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
 pub enum Node {
     Add(Box<Node>, Box<Node>),
     Sub(Box<Node>, Box<Node>),
     Mul(Box<Node>, Box<Node>),
     Div(Box<Node>, Box<Node>),
     Pow(Box<Node>, Box<Node>),
+    Neg(Box<Node>),
+    #[cfg_attr(feature = "serde", serde(with = "rust_decimal::serde::str"))]
     Num(Decimal),
+    Var(String),
+    // 方括号列表 [a, b, ...]，求值为矩阵
+    Matrix(Vec<Node>),
+    Call(Function, Vec<Node>),
+    // sum(k, a, b, body) 和 prod(k, a, b, body)：k 从 a 到 b 依次取整数，k 只在 body 中可见
+    Sum(String, Box<Node>, Box<Node>, Box<Node>),
+    Prod(String, Box<Node>, Box<Node>, Box<Node>),
+    // 方程 lhs = rhs，不能直接求值，只能作为 solve 的参数
+    Eq(Box<Node>, Box<Node>),
 }
 
 impl Node {
-    pub fn eval(&self) -> Decimal {
+    // 构建函数调用的节点，sum 和 prod 的第一个参数是绑定的变量，需要转换成专门的节点，
+    // diff、integrate 和 collect 的第二个参数必须是变量
+    pub fn call(func: Function, args: Vec<Node>) -> Result<Node, String> {
+        func.check_arity(args.len())?;
+        match func {
+            Function::Sum | Function::Prod => {
+                let Node::Var(var) = &args[0] else {
+                    return Err(format!(
+                        "Function '{func}' expects a variable as its first argument"
+                    ));
+                };
+                let var = var.clone();
+                let mut args = args.into_iter().skip(1);
+                let mut arg = || Box::new(args.next().unwrap());
+                let (from, to, body) = (arg(), arg(), arg());
+                Ok(if func == Function::Sum {
+                    Node::Sum(var, from, to, body)
+                } else {
+                    Node::Prod(var, from, to, body)
+                })
+            }
+            Function::Diff | Function::Integrate | Function::Collect
+                if !matches!(args[1], Node::Var(_)) =>
+            {
+                Err(format!(
+                    "Function '{func}' expects a variable as its second argument"
+                ))
+            }
+            // solve(lhs = rhs, x) 或者 solve(lhs = rhs, x, a, b) 求方程的数值解，solve(A, b) 解线性方程组
+            Function::Solve if matches!(args[0], Node::Eq(..)) => {
+                if !matches!(args[1], Node::Var(_)) {
+                    Err(format!(
+                        "Function '{func}' expects a variable as its second argument"
+                    ))
+                } else if args.len() == 3 {
+                    Err(format!(
+                        "Function '{func}' expects both ends of the interval, found only one"
+                    ))
+                } else {
+                    Ok(Node::Call(func, args))
+                }
+            }
+            // solve([eq1, eq2, ...], [x, y, ...]) 解线性方程组，未知数不能重复
+            Function::Solve if linear::is_system(&args[0]) => {
+                let Node::Matrix(eqs) = &args[0] else {
+                    unreachable!()
+                };
+                if !eqs.iter().all(|eq| matches!(eq, Node::Eq(..))) {
+                    return Err(format!(
+                        "Function '{func}' expects a list of equations as its first argument"
+                    ));
+                }
+                let vars = match &args[1..] {
+                    [Node::Matrix(vars)] if vars.iter().all(|var| matches!(var, Node::Var(_))) => {
+                        vars
+                    }
+                    [_] => {
+                        return Err(format!(
+                            "Function '{func}' expects a list of variables as its second argument"
+                        ));
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Function '{func}' expects 2 argument(s) for a system of equations, found {}",
+                            args.len()
+                        ));
+                    }
+                };
+                let repeated = (1..vars.len()).find(|i| vars[..*i].contains(&vars[*i]));
+                if let Some(Node::Var(var)) = repeated.map(|i| &vars[i]) {
+                    return Err(format!("Variable '{var}' appears more than once in '{func}'"));
+                }
+                Ok(Node::Call(func, args))
+            }
+            Function::Solve if args.len() != 2 => Err(format!(
+                "Function '{func}' expects 2 argument(s) for a linear system, found {}",
+                args.len()
+            )),
+            _ => Ok(Node::Call(func, args)),
+        }
+    }
+
+    // 取出这个节点，原来的位置留下 0。Node 实现了 Drop，不能按值解构，需要子节点的所有权时用它
+    pub fn take(&mut self) -> Node {
+        std::mem::replace(self, Node::Num(Decimal::ZERO))
+    }
+
+    // 左结合的运算符，链 1 + 2 + 3 沿着左边的运算数展开
+    pub fn as_chain(&self) -> Option<(Operator, &Node, &Node)> {
+        use Node::*;
+        match self {
+            Add(lhs, rhs) => Some((Operator::Add, lhs, rhs)),
+            Sub(lhs, rhs) => Some((Operator::Sub, lhs, rhs)),
+            Mul(lhs, rhs) => Some((Operator::Mul, lhs, rhs)),
+            Div(lhs, rhs) => Some((Operator::Div, lhs, rhs)),
+            _ => None,
+        }
+    }
+
+    pub fn eval(&self, ctx: &mut Context) -> Result<Value, String> {
+        use Node::*;
+        deep(|| match self {
+            // 链从左到右迭代地求值，链的长度不受调用栈的限制
+            Add(..) | Sub(..) | Mul(..) | Div(..) => {
+                let mut node = self;
+                let mut rhs = Vec::new();
+                while let Some((op, lhs, next)) = node.as_chain() {
+                    rhs.push((op, next));
+                    node = lhs;
+                }
+                let mut acc = node.eval(ctx)?;
+                for (op, next) in rhs.into_iter().rev() {
+                    acc = Value::operate(op, acc, next.eval(ctx)?)?;
+                }
+                Ok(acc)
+            }
+            Pow(lhs, rhs) => Value::operate(Operator::Pow, lhs.eval(ctx)?, rhs.eval(ctx)?),
+            Neg(val) => val.eval(ctx)?.neg(),
+            Num(val) => Ok(Value::Num(*val)),
+            Var(name) => ctx
+                .var(name)
+                .cloned()
+                .or_else(|| constant(name).map(Value::Num))
+                .ok_or_else(|| format!("Unknown variable: '{name}'")),
+            Matrix(elems) => Value::list(eval_all(elems, ctx)?),
+            Call(func, args) if func.is_lazy() => func.call_lazy(args, ctx),
+            Call(func, args) => func.call(eval_all(args, ctx)?, ctx),
+            Sum(var, from, to, body) | Prod(var, from, to, body) => {
+                let op = if matches!(self, Sum(..)) { Operator::Add } else { Operator::Mul };
+                let (from, to) = (from.eval(ctx)?, to.eval(ctx)?);
+                iterate(op, var, from, to, ctx, |ctx| body.eval(ctx))
+            }
+            Eq(..) => Err(format!(
+                "Equation '{self}' can only be used as an argument of 'solve'"
+            )),
+        })
+    }
+
+    // 把有子节点的子节点移到 stack 中，留下的叶子直接释放
+    fn detach(&mut self, stack: &mut Vec<Node>) {
         use Node::*;
+        let mut push = |node: &mut Node| {
+            if !matches!(node, Num(_) | Var(_)) {
+                stack.push(node.take());
+            }
+        };
         match self {
-            Add(lhs, rhs) => lhs.eval() + rhs.eval(),
-            Sub(lhs, rhs) => lhs.eval() - rhs.eval(),
-            Mul(lhs, rhs) => lhs.eval() * rhs.eval(),
-            Div(lhs, rhs) => lhs.eval() / rhs.eval(),
-            Pow(lhs, rhs) => lhs.eval().powd(rhs.eval()),
-            Num(val) => *val
+            Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) | Pow(lhs, rhs)
+            | Eq(lhs, rhs) => {
+                push(lhs);
+                push(rhs);
+            }
+            Neg(val) => push(val),
+            Num(_) | Var(_) => {}
+            Matrix(nodes) | Call(_, nodes) => nodes.iter_mut().for_each(push),
+            Sum(_, from, to, body) | Prod(_, from, to, body) => {
+                push(from);
+                push(to);
+                push(body);
+            }
         }
     }
 }
 
+// 派生的析构会递归地释放子树，这里用显式的栈逐个释放
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.detach(&mut stack);
+        while let Some(mut node) = stack.pop() {
+            node.detach(&mut stack);
+        }
+    }
+}
+
+impl Clone for Node {
+    fn clone(&self) -> Node {
+        use Node::*;
+        deep(|| match self {
+            Add(lhs, rhs) => Add(lhs.clone(), rhs.clone()),
+            Sub(lhs, rhs) => Sub(lhs.clone(), rhs.clone()),
+            Mul(lhs, rhs) => Mul(lhs.clone(), rhs.clone()),
+            Div(lhs, rhs) => Div(lhs.clone(), rhs.clone()),
+            Pow(lhs, rhs) => Pow(lhs.clone(), rhs.clone()),
+            Neg(val) => Neg(val.clone()),
+            Num(val) => Num(*val),
+            Var(name) => Var(name.clone()),
+            Matrix(elems) => Matrix(elems.clone()),
+            Call(func, args) => Call(*func, args.clone()),
+            Sum(var, from, to, body) => Sum(var.clone(), from.clone(), to.clone(), body.clone()),
+            Prod(var, from, to, body) => Prod(var.clone(), from.clone(), to.clone(), body.clone()),
+            Eq(lhs, rhs) => Eq(lhs.clone(), rhs.clone()),
+        })
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        use Node::*;
+        deep(|| match (self, other) {
+            (Add(a, b), Add(c, d))
+            | (Sub(a, b), Sub(c, d))
+            | (Mul(a, b), Mul(c, d))
+            | (Div(a, b), Div(c, d))
+            | (Pow(a, b), Pow(c, d))
+            | (Eq(a, b), Eq(c, d)) => a == c && b == d,
+            (Neg(a), Neg(b)) => a == b,
+            (Num(a), Num(b)) => a == b,
+            (Var(a), Var(b)) => a == b,
+            (Matrix(a), Matrix(b)) => a == b,
+            (Call(f, a), Call(g, b)) => f == g && a == b,
+            (Sum(k, a, b, c), Sum(l, d, e, f)) | (Prod(k, a, b, c), Prod(l, d, e, f)) => {
+                k == l && a == d && b == e && c == f
+            }
+            _ => false,
+        })
+    }
+}
+
+// sum 和 prod 的求值，op 是累积的运算，空的求和为 0，空的求积为 1。body 在绑定了 var 的上下文中求出每一项
+pub fn iterate(
+    op: Operator,
+    var: &str,
+    from: Value,
+    to: Value,
+    ctx: &mut Context,
+    mut body: impl FnMut(&mut Context) -> Result<Value, String>,
+) -> Result<Value, String> {
+    let func = if op == Operator::Add { Function::Sum } else { Function::Prod };
+    let from = to_integer(func, from)?;
+    let to = to_integer(func, to)?;
+    if from > to {
+        return Ok(Value::Num(if op == Operator::Add { Decimal::ZERO } else { Decimal::ONE }));
+    }
+    ctx.use_iterations(from.abs_diff(to) + 1)?;
+    let mut res: Option<Value> = None;
+    for k in from..=to {
+        let term = ctx.scoped(var, Value::Num(to_decimal(k)?), &mut body)?;
+        res = Some(match res {
+            Some(acc) => Value::operate(op, acc, term)?,
+            None => term,
+        });
+    }
+    Ok(res.unwrap())
+}
+
+fn eval_all(nodes: &[Node], ctx: &mut Context) -> Result<Vec<Value>, String> {
+    nodes.iter().map(|node| node.eval(ctx)).collect()
+}
+
 impl Ast {
-    pub fn eval(&self) -> Decimal {
-        self.0.eval()
+    pub fn eval(&self, ctx: &mut Context) -> Result<Value, String> {
+        self.0.eval(ctx)
+    }
+
+    // 化简后的表达式，用于显示更简洁的公式，或者在多次求值之前预先折叠常数
+    pub fn simplify(&self) -> Ast {
+        Ast(simplify::simplify(self.0.clone()))
     }
 }
 
@@ -50,27 +327,40 @@ mod tests {
         Num(Decimal::from_str_exact(s).unwrap())
     }
 
+    // 辅助函数：使用固定种子的上下文求值
+    fn eval(ast: &Ast) -> Result<Value, String> {
+        ast.eval(&mut Context::with_seed(0))
+    }
+
+    // 辅助函数：求值并取出数值结果
+    fn eval_num(ast: &Ast) -> Decimal {
+        match eval(ast) {
+            Ok(Value::Num(num)) => num,
+            res => panic!("expected a number, found {res:?}"),
+        }
+    }
+
     #[test]
     fn test_basic_operations() {
         // 加法
         let ast = Ast(Add(Box::new(num(2)), Box::new(num(3))));
-        assert_eq!(ast.eval(), dec!(5));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(5))));
         
         // 减法
         let ast = Ast(Sub(Box::new(num(5)), Box::new(num(3))));
-        assert_eq!(ast.eval(), dec!(2));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(2))));
         
         // 乘法
         let ast = Ast(Mul(Box::new(num(4)), Box::new(num(3))));
-        assert_eq!(ast.eval(), dec!(12));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(12))));
         
         // 除法
         let ast = Ast(Div(Box::new(num(10)), Box::new(num(4))));
-        assert_eq!(ast.eval(), dec!(2.5));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(2.5))));
         
         // 幂运算
         let ast = Ast(Pow(Box::new(num(2)), Box::new(num(3))));
-        assert_eq!(ast.eval(), dec!(8));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(8))));
     }
 
     #[test]
@@ -80,7 +370,7 @@ mod tests {
             Box::new(Add(Box::new(num(2)), Box::new(num(3)))),
             Box::new(num(4))
         ));
-        assert_eq!(ast.eval(), dec!(20));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(20))));
         
         // 多层嵌套: 2^3 + 4 * (5 - 3)
         let ast = Ast(Add(
@@ -90,7 +380,7 @@ mod tests {
                 Box::new(Sub(Box::new(num(5)), Box::new(num(3))))
             ))
         ));
-        assert_eq!(ast.eval(), dec!(16));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(16))));
     }
 
     #[test]
@@ -100,40 +390,49 @@ mod tests {
             Box::new(Num(dec!(0.1))),
             Box::new(Num(dec!(0.2)))
         ));
-        assert_eq!(ast.eval(), dec!(0.3));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(0.3))));
         
         // 小数除法
         let ast = Ast(Div(
             Box::new(num(1)),
             Box::new(num(8))
         ));
-        assert_eq!(ast.eval(), dec!(0.125));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(0.125))));
         
         // 小数幂运算
         let ast = Ast(Pow(
             Box::new(Num(dec!(4.0))),
             Box::new(Num(dec!(0.5)))
         ));
-        assert!(ast.eval() - dec!(2.0) < dec!(0.000000001));
+        assert!(eval_num(&ast) - dec!(2.0) < dec!(0.000000001));
     }
 
     #[test]
     fn test_edge_cases() {
         // 零的负指数幂
+        let ast = Ast(Pow(Box::new(num(0)), Box::new(num(-1))));
+        assert_eq!(eval(&ast), Err("Division by zero".to_string()));
+        let ast = Ast(Pow(Box::new(num(0)), Box::new(dec("-0.5"))));
+        assert_eq!(eval(&ast), Err("Division by zero".to_string()));
+        let ast = Ast(Pow(Box::new(num(0)), Box::new(num(0))));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(1))));
         
         // 负数的分数幂
         let ast = Ast(Pow(
             Box::new(num(-8)),
             Box::new(Num(dec!(1) / dec!(3))))
         );
-        assert!(ast.eval() - dec!(-2) < dec!(0.00000001));
+        assert!(eval_num(&ast) - dec!(-2) < dec!(0.00000001));
+        // 分母是偶数的时候不是实数
+        let ast = Ast(Pow(Box::new(num(-2)), Box::new(dec("0.5"))));
+        assert_eq!(eval(&ast), Err("Cannot raise negative number '-2' to power '0.5'".to_string()));
         
         // 大数运算
         let ast = Ast(Mul(
             Box::new(Num(dec!(100_0000_0000_0000))),
             Box::new(Num(dec!(100_0000_0000_0000)))
         ));
-        assert_eq!(ast.eval(), dec!(1_0000_0000_0000_0000_0000_0000_0000));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(1_0000_0000_0000_0000_0000_0000_0000))));
     }
 
     #[test]
@@ -143,14 +442,14 @@ mod tests {
             Box::new(Sub(Box::new(num(10)), Box::new(num(5)))),
             Box::new(num(2))
         ));
-        assert_eq!(ast.eval(), dec!(3));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(3))));
         
         // 右结合: 2^3^2
         let ast = Ast(Pow(
             Box::new(num(2)),
             Box::new(Pow(Box::new(num(3)), Box::new(num(2))))
         ));
-        assert_eq!(ast.eval(), dec!(512));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(512))));
     }
 
     #[test]
@@ -160,21 +459,21 @@ mod tests {
             Box::new(num(-5)),
             Box::new(num(8))
         ));
-        assert_eq!(ast.eval(), dec!(3));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(3))));
         
         // 负数的乘法
         let ast = Ast(Mul(
             Box::new(num(-4)),
             Box::new(num(3))
         ));
-        assert_eq!(ast.eval(), dec!(-12));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(-12))));
         
         // 负数的幂运算
         let ast = Ast(Pow(
             Box::new(num(-2)),
             Box::new(num(3))
         ));
-        assert_eq!(ast.eval(), dec!(-8));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(-8))));
     }
 
     #[test]
@@ -187,7 +486,7 @@ mod tests {
             )),
             Box::new(Pow(Box::new(num(2)), Box::new(num(2))))
         ));
-        assert_eq!(ast.eval(), dec!(4.5));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(4.5))));
         
         // 带负数的复杂表达式: -2 * (3 + -4)^2
         let ast = Ast(Mul(
@@ -197,7 +496,7 @@ mod tests {
                 Box::new(num(2))
             ))
         ));
-        assert_eq!(ast.eval(), dec!(-2));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(-2))));
     }
     
     #[test]
@@ -216,6 +515,86 @@ mod tests {
             )),
             Box::new(num(2))
         ));
-        assert_eq!(ast.eval(), dec!(1));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(1))));
+    }
+
+    #[test]
+    fn test_sum_and_prod() {
+        // sum(k, 1, 100, k^2)
+        let ast = Ast(Sum(
+            "k".to_string(),
+            Box::new(num(1)),
+            Box::new(num(100)),
+            Box::new(Pow(Box::new(Var("k".to_string())), Box::new(num(2)))),
+        ));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(338350))));
+
+        // prod(i, 1, 10, i)
+        let ast = Ast(Prod(
+            "i".to_string(),
+            Box::new(num(1)),
+            Box::new(num(10)),
+            Box::new(Var("i".to_string())),
+        ));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(3628800))));
+
+        // 空的求和与求积
+        let ast = Ast(Sum("k".to_string(), Box::new(num(5)), Box::new(num(1)), Box::new(num(7))));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(0))));
+        let ast = Ast(Prod("k".to_string(), Box::new(num(5)), Box::new(num(1)), Box::new(num(7))));
+        assert_eq!(eval(&ast), Ok(Value::Num(dec!(1))));
+    }
+
+    #[test]
+    fn test_bound_variable_scope() {
+        // sum(k, 1, 3, k) + k：k 在 sum 外面不可见
+        let ast = Ast(Add(
+            Box::new(Sum(
+                "k".to_string(),
+                Box::new(num(1)),
+                Box::new(num(3)),
+                Box::new(Var("k".to_string())),
+            )),
+            Box::new(Var("k".to_string())),
+        ));
+        assert_eq!(eval(&ast), Err("Unknown variable: 'k'".to_string()));
+
+        // 迭代次数超过上限
+        let ast = Ast(Sum(
+            "k".to_string(),
+            Box::new(num(1)),
+            Box::new(num(1000)),
+            Box::new(Var("k".to_string())),
+        ));
+        let mut ctx = Context::with_seed(0);
+        ctx.max_iterations = 100;
+        assert_eq!(
+            ast.eval(&mut ctx),
+            Err("Iteration count 1000 exceeds the limit of 100".to_string())
+        );
+    }
+
+    #[test]
+    fn test_nested_iterations() {
+        use crate::calc::parser::Parser;
+
+        // 嵌套的循环按总的迭代次数计算，外层的一百万次加上内层第一轮的一百万次就超过了上限
+        let ast = Parser::parse("sum(i, 1, 1000000, sum(j, 1, 1000000, 1))").unwrap();
+        assert_eq!(
+            eval(&ast),
+            Err("Iteration count 2000000 exceeds the limit of 1000000".to_string())
+        );
+        let mut ctx = Context::with_seed(0);
+        ctx.max_iterations = 1000;
+        let ast = Parser::parse("sum(i, 1, 10, repeat(100, 1))").unwrap();
+        assert_eq!(
+            ast.eval(&mut ctx),
+            Err("Iteration count 1010 exceeds the limit of 1000".to_string())
+        );
+        // 迭代次数在顶层的求值开始之前清零，否则会继续累积
+        let ast = Parser::parse("sum(i, 1, 10, sum(j, 1, 90, 1))").unwrap();
+        assert!(ast.eval(&mut ctx).is_err());
+        ctx.reset_iterations();
+        assert_eq!(ast.eval(&mut ctx), Ok(Value::Num(dec!(900))));
     }
-}
\ No newline at end of file
+}
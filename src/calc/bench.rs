@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+
+use crate::calc::{bytecode::Vm, context::Context, parser::Parser, value::Value};
+
+// 执行 f(0), f(1), ..., f(count - 1)，返回总的耗时和最后一次的结果
+fn time<T>(
+    count: u128,
+    mut f: impl FnMut(u128) -> Result<T, String>,
+) -> Result<(Duration, T), String> {
+    let start = Instant::now();
+    let mut res = f(0)?;
+    for n in 1..count {
+        res = f(n)?;
+    }
+    Ok((start.elapsed(), res))
+}
+
+// 一行耗时的报告，base 是对比的基准
+fn report(name: &str, time: Duration, count: u128, base: Option<Duration>) -> String {
+    let per = time.div_f64(count as f64);
+    let line = format!("  {name}: {time:.2?} ({per:.2?} each)");
+    let Some(base) = base else {
+        return line;
+    };
+    let ratio = base.as_secs_f64() / time.as_secs_f64().max(f64::MIN_POSITIVE);
+    if ratio >= 1.0 {
+        format!("{line}, {ratio:.2}x faster")
+    } else {
+        format!("{line}, {:.2}x slower", 1.0 / ratio)
+    }
+}
+
+// 把同一个表达式解析和求值 count 次，比较 Box<Node> 的语法树、arena 中的语法树和字节码虚拟机的耗时。
+// 第 n 次求值时所有的自由变量都取 n，每种方式使用相同的随机数种子，最后一次的结果必须相同
+pub fn bench(expr: &str, count: u128, ctx: &Context) -> Result<String, String> {
+    if count == 0 {
+        return Err("Expected at least one evaluation".to_string());
+    }
+    ctx.check_iterations(count)?;
+    let ast = Parser::parse_with(expr, ctx.numerals, ctx.max_depth)?;
+    let arena = Parser::parse_arena(expr, ctx.numerals, ctx.max_depth)?;
+    if arena.to_ast() != ast {
+        return Err(format!("Arena tree differs from the parsed tree '{ast}'"));
+    }
+    let program = ast.compile()?;
+    let vars = program.vars();
+    let args = |n: u128| vec![Value::Num(Decimal::from(n)); vars.len()];
+    let bindings = |n: u128| vars.iter().map(String::as_str).zip(args(n));
+
+    let mut tree_ctx = ctx.clone();
+    let (tree_time, tree_res) = time(count, |n| {
+        tree_ctx.reset_iterations();
+        tree_ctx.scoped_all(bindings(n), |ctx| ast.eval(ctx))
+    })?;
+    let mut arena_ctx = ctx.clone();
+    let (arena_time, arena_res) = time(count, |n| {
+        arena_ctx.reset_iterations();
+        arena_ctx.scoped_all(bindings(n), |ctx| arena.eval(ctx))
+    })?;
+    let mut vm_ctx = ctx.clone();
+    let mut vm = Vm::new(&program);
+    let (vm_time, vm_res) = time(count, |n| {
+        vm_ctx.reset_iterations();
+        vm.run(&program, &args(n), &mut vm_ctx)
+    })?;
+    for (name, res) in [("Arena", &arena_res), ("Bytecode", &vm_res)] {
+        if *res != tree_res {
+            return Err(format!(
+                "{name} result '{res}' differs from tree walking result '{tree_res}'"
+            ));
+        }
+    }
+
+    let (parse_time, _) = time(count, |_| {
+        Parser::parse_with(expr, ctx.numerals, ctx.max_depth)
+    })?;
+    let (parse_arena_time, _) = time(count, |_| {
+        Parser::parse_arena(expr, ctx.numerals, ctx.max_depth)
+    })?;
+    Ok([
+        format!("Result: {tree_res}"),
+        "Evaluation:".to_string(),
+        report("Tree walking", tree_time, count, None),
+        report("Arena", arena_time, count, Some(tree_time)),
+        report("Bytecode", vm_time, count, Some(tree_time)),
+        "Parsing:".to_string(),
+        report("Box tree", parse_time, count, None),
+        report("Arena", parse_arena_time, count, Some(parse_time)),
+    ]
+    .join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        let ctx = Context::with_seed(0);
+        let report = bench("x^2 + sum(k, 1, 10, k*x) + rand()", 100, &ctx).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[0].starts_with("Result: 15246."), "{report}");
+        assert_eq!(lines[1], "Evaluation:");
+        assert!(lines[2].starts_with("  Tree walking: "), "{report}");
+        assert!(lines[2].ends_with(" each)"), "{report}");
+        assert!(lines[3].starts_with("  Arena: "), "{report}");
+        assert!(lines[4].starts_with("  Bytecode: "), "{report}");
+        assert!(
+            lines[4].ends_with("x faster") || lines[4].ends_with("x slower"),
+            "{report}"
+        );
+        assert_eq!(lines[5], "Parsing:");
+        assert!(lines[6].starts_with("  Box tree: "), "{report}");
+        assert!(lines[7].starts_with("  Arena: "), "{report}");
+
+        let expr = "1/(x - 5)";
+        assert_eq!(bench(expr, 10, &ctx), Err("Division by zero".to_string()));
+        assert_eq!(
+            bench(expr, 0, &ctx),
+            Err("Expected at least one evaluation".to_string())
+        );
+        assert_eq!(
+            bench(expr, 10_000_000, &ctx),
+            Err("Iteration count 10000000 exceeds the limit of 1000000".to_string())
+        );
+        assert_eq!(
+            bench("1 +", 10, &ctx),
+            Err(Parser::parse("1 +").unwrap_err())
+        );
+    }
+}
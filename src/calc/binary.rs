@@ -0,0 +1,484 @@
+use rust_decimal::Decimal;
+
+use crate::calc::{
+    ast::{Ast, Node},
+    context::deep,
+    function::Function,
+    parser::Depth,
+    tokenizer::is_ident,
+};
+
+// 语法树的二进制格式：魔数 "CALC"、1 字节的版本号，然后是先序遍历的节点。
+// 每个节点以 1 字节的标签开头，后面依次是它的字段：数是 Decimal::serialize 的 16 字节，
+// 变量名是长度加 UTF-8 字节，长度和元素个数是 LEB128 变长整数，函数是 FUNCTIONS 中的下标。
+// 格式有不兼容的改动时增加版本号
+const MAGIC: &[u8; 4] = b"CALC";
+pub const VERSION: u8 = 1;
+
+const ADD: u8 = 0;
+const SUB: u8 = 1;
+const MUL: u8 = 2;
+const DIV: u8 = 3;
+const POW: u8 = 4;
+const NEG: u8 = 5;
+const NUM: u8 = 6;
+const VAR: u8 = 7;
+const MATRIX: u8 = 8;
+const CALL: u8 = 9;
+const SUM: u8 = 10;
+const PROD: u8 = 11;
+const EQ: u8 = 12;
+
+// 函数的编号，新的函数只能追加在末尾
+const FUNCTIONS: [Function; 34] = [
+    Function::Transpose,
+    Function::Det,
+    Function::Inv,
+    Function::Solve,
+    Function::Gcd,
+    Function::Lcm,
+    Function::ModPow,
+    Function::ModInv,
+    Function::IsPrime,
+    Function::Factor,
+    Function::Choose,
+    Function::Perm,
+    Function::Multinomial,
+    Function::Rand,
+    Function::RandInt,
+    Function::Normal,
+    Function::Mean,
+    Function::Std,
+    Function::Repeat,
+    Function::Sample,
+    Function::Sqrt,
+    Function::Exp,
+    Function::Ln,
+    Function::Sin,
+    Function::Cos,
+    Function::Tan,
+    Function::Diff,
+    Function::Integrate,
+    Function::Expand,
+    Function::Collect,
+    Function::PolyDiv,
+    Function::Roots,
+    Function::Sum,
+    Function::Prod,
+];
+
+fn write_len(mut len: usize, out: &mut Vec<u8>) {
+    while len >= 0x80 {
+        out.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+fn write_name(name: &str, out: &mut Vec<u8>) {
+    write_len(name.len(), out);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn encode(node: &Node, out: &mut Vec<u8>) {
+    use Node::*;
+    let binary = |tag: u8, lhs: &Node, rhs: &Node, out: &mut Vec<u8>| {
+        out.push(tag);
+        encode(lhs, out);
+        encode(rhs, out);
+    };
+    deep(|| match node {
+        Add(lhs, rhs) => binary(ADD, lhs, rhs, out),
+        Sub(lhs, rhs) => binary(SUB, lhs, rhs, out),
+        Mul(lhs, rhs) => binary(MUL, lhs, rhs, out),
+        Div(lhs, rhs) => binary(DIV, lhs, rhs, out),
+        Pow(lhs, rhs) => binary(POW, lhs, rhs, out),
+        Eq(lhs, rhs) => binary(EQ, lhs, rhs, out),
+        Neg(val) => {
+            out.push(NEG);
+            encode(val, out);
+        }
+        Num(num) => {
+            out.push(NUM);
+            out.extend_from_slice(&num.serialize());
+        }
+        Var(name) => {
+            out.push(VAR);
+            write_name(name, out);
+        }
+        Matrix(elems) => {
+            out.push(MATRIX);
+            write_len(elems.len(), out);
+            elems.iter().for_each(|elem| encode(elem, out));
+        }
+        Call(func, args) => {
+            out.push(CALL);
+            out.push(FUNCTIONS.iter().position(|f| f == func).unwrap() as u8);
+            write_len(args.len(), out);
+            args.iter().for_each(|arg| encode(arg, out));
+        }
+        Sum(var, from, to, body) | Prod(var, from, to, body) => {
+            out.push(if matches!(node, Sum(..)) { SUM } else { PROD });
+            write_name(var, out);
+            encode(from, out);
+            encode(to, out);
+            encode(body, out);
+        }
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // 允许的最大嵌套深度，语法树的高度也有上限，防止构造的数据耗尽调用栈
+    max_depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(format!(
+                "Truncated syntax tree data at byte {}",
+                self.bytes.len()
+            ));
+        };
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        let start = self.pos;
+        let mut len = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            let bits = usize::from(byte & 0x7f);
+            if bits
+                .checked_shl(shift)
+                .is_none_or(|val| val >> shift != bits)
+            {
+                break;
+            }
+            len |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+        }
+        Err(format!("Invalid length at byte {start}"))
+    }
+
+    // 和分词器一样，变量名必须是标识符
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        let start = self.pos;
+        let bytes = self.take(len)?;
+        let name = String::from_utf8(bytes.to_vec())
+            .map_err(|_| format!("Invalid UTF-8 name at byte {start}"))?;
+        if !is_ident(&name) {
+            return Err(format!("Invalid variable name '{name}' at byte {start}"));
+        }
+        Ok(name)
+    }
+
+    // Decimal::deserialize 不检查数据，标志位中只有符号和不超过 28 的小数位数是合法的
+    fn num(&mut self) -> Result<Decimal, String> {
+        let start = self.pos;
+        let bytes: [u8; 16] = self.take(16)?.try_into().unwrap();
+        if bytes[0] != 0 || bytes[1] != 0 || bytes[2] > 28 || bytes[3] & 0x7f != 0 {
+            return Err(format!("Invalid number at byte {start}"));
+        }
+        Ok(Decimal::deserialize(bytes))
+    }
+
+    // depth 是从根节点到这里的深度
+    fn node(&mut self, depth: Depth) -> Result<Node, String> {
+        deep(|| self.read_node(depth))
+    }
+
+    fn read_node(&mut self, depth: Depth) -> Result<Node, String> {
+        use Node::*;
+        depth.check(self.max_depth)?;
+        let start = self.pos;
+        Ok(match self.byte()? {
+            ADD => Add(self.lhs(depth)?, self.child(depth)?),
+            SUB => Sub(self.lhs(depth)?, self.child(depth)?),
+            MUL => Mul(self.lhs(depth)?, self.child(depth)?),
+            DIV => Div(self.lhs(depth)?, self.child(depth)?),
+            POW => Pow(self.child(depth)?, self.child(depth)?),
+            NEG => Neg(self.child(depth)?),
+            // 和解析器一样，方程只能有一个等号
+            EQ => {
+                let (lhs, rhs) = (self.child(depth)?, self.child(depth)?);
+                if matches!(*lhs, Eq(..)) || matches!(*rhs, Eq(..)) {
+                    return Err(format!("Expected a single '=' in an equation at byte {start}"));
+                }
+                Eq(lhs, rhs)
+            }
+            NUM => Num(self.num()?),
+            VAR => Var(self.name()?),
+            // 元素个数来自输入，不能用来预先分配内存
+            MATRIX => {
+                let elems = self.nodes(depth)?;
+                if elems.is_empty() {
+                    return Err(format!("Empty matrix at byte {start}"));
+                }
+                Matrix(elems)
+            }
+            CALL => {
+                let id = self.byte()?;
+                let func = *FUNCTIONS
+                    .get(usize::from(id))
+                    .ok_or_else(|| format!("Unknown function id {id} at byte {}", start + 1))?;
+                let args = self.nodes(depth)?;
+                // 和解析器一样检查参数，sum 和 prod 由单独的标签表示
+                if matches!(func, Function::Sum | Function::Prod) {
+                    return Err(format!("Unexpected call of '{func}' at byte {start}"));
+                }
+                Node::call(func, args)?
+            }
+            tag @ (SUM | PROD) => {
+                let var = self.name()?;
+                let (from, to, body) = (self.child(depth)?, self.child(depth)?, self.child(depth)?);
+                if tag == SUM {
+                    Sum(var, from, to, body)
+                } else {
+                    Prod(var, from, to, body)
+                }
+            }
+            tag => return Err(format!("Unknown node tag {tag} at byte {start}")),
+        })
+    }
+
+    fn child(&mut self, depth: Depth) -> Result<Box<Node>, String> {
+        self.node(depth.child(false)).map(Box::new)
+    }
+
+    // 左结合运算符的左边，不增加嵌套的层数
+    fn lhs(&mut self, depth: Depth) -> Result<Box<Node>, String> {
+        self.node(depth.child(true)).map(Box::new)
+    }
+
+    fn nodes(&mut self, depth: Depth) -> Result<Vec<Node>, String> {
+        let len = self.len()?;
+        let mut nodes = Vec::new();
+        for _ in 0..len {
+            nodes.push(self.node(depth.child(false))?);
+        }
+        Ok(nodes)
+    }
+}
+
+impl Ast {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        encode(&self.0, &mut out);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8], max_depth: usize) -> Result<Ast, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("Not a syntax tree: missing 'CALC' header".to_string());
+        }
+        let mut reader = Reader {
+            bytes,
+            pos: MAGIC.len(),
+            max_depth,
+        };
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported binary format version {version}, expected {VERSION}"
+            ));
+        }
+        let node = reader.node(Depth::LEAF)?;
+        if reader.pos != bytes.len() {
+            return Err(format!("Unexpected trailing data at byte {}", reader.pos));
+        }
+        Ok(Ast(node))
+    }
+}
+
+// REPL 中用十六进制显示和输入二进制数据，输入时忽略空白
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .map(|digit| digit as u8)
+                .ok_or_else(|| format!("Invalid hex digit: '{c}'"))
+        })
+        .collect::<Result<_, _>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return Err("Expected an even number of hex digits".to_string());
+    }
+    Ok(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{
+        context::{DEFAULT_MAX_DEPTH, Rng},
+        parser::Parser,
+    };
+
+    #[test]
+    fn test_encoding() {
+        let ast = Parser::parse("-x + 1.5").unwrap();
+        let mut expected = b"CALC\x01".to_vec();
+        expected.extend([ADD, NEG, VAR, 1, b'x', NUM]);
+        expected.extend(Decimal::new(15, 1).serialize());
+        assert_eq!(ast.to_bytes(), expected);
+        let ast = Parser::parse("sin(x)").unwrap();
+        assert_eq!(ast.to_bytes(), b"CALC\x01\x09\x17\x01\x07\x01x");
+        // 长度超过 127 时使用多个字节
+        let mut out = Vec::new();
+        write_len(300, &mut out);
+        assert_eq!(out, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for expr in [
+            "1 - (2 - 3)*x^2",
+            "0.1234567890123456789012345678 - 79228162514264337593543950335",
+            "sum(k, 1, n, [k, 2])/rand()",
+            "prod(k, 1, 10, k)",
+            "solve(x^2 = 2, x, 0, 2)",
+            "solve([x + y = 1, x - y = 0], [x, y])",
+            "gcd(12, 18, 8, 6)",
+            "[[1, 2], [3, 4]]",
+        ] {
+            let ast = Parser::parse(expr).unwrap();
+            assert_eq!(Ast::from_bytes(&ast.to_bytes(), DEFAULT_MAX_DEPTH), Ok(ast), "{expr}");
+        }
+        let name = "x".repeat(200);
+        let ast = Parser::parse(&name).unwrap();
+        assert_eq!(Ast::from_bytes(&ast.to_bytes(), DEFAULT_MAX_DEPTH), Ok(ast));
+    }
+
+    #[test]
+    fn test_errors() {
+        let bytes = |body: &[u8]| [b"CALC\x01".as_slice(), body].concat();
+        let err = |bytes: &[u8]| Ast::from_bytes(bytes, DEFAULT_MAX_DEPTH).unwrap_err();
+        assert_eq!(err(b"CAL"), "Not a syntax tree: missing 'CALC' header");
+        assert_eq!(err(b"CALC"), "Truncated syntax tree data at byte 4");
+        assert_eq!(
+            err(b"CALC\x02\x07\x01x"),
+            "Unsupported binary format version 2, expected 1"
+        );
+        assert_eq!(
+            err(&bytes(&[ADD, VAR, 1, b'x'])),
+            "Truncated syntax tree data at byte 9"
+        );
+        assert_eq!(
+            err(&bytes(&[VAR, 5, b'x'])),
+            "Truncated syntax tree data at byte 8"
+        );
+        assert_eq!(err(&bytes(&[VAR, 1, 0xff])), "Invalid UTF-8 name at byte 7");
+        assert_eq!(err(&bytes(&[VAR, 0])), "Invalid variable name '' at byte 7");
+        assert_eq!(err(&bytes(&[VAR, 2, b'1', b'x'])), "Invalid variable name '1x' at byte 7");
+        assert_eq!(
+            err(&bytes(&[VAR, 3, b'x', b'+', b'y'])),
+            "Invalid variable name 'x+y' at byte 7"
+        );
+        let mut sum = [SUM, 1, b'-'].to_vec();
+        sum.extend([VAR, 1, b'k'].repeat(3));
+        assert_eq!(err(&bytes(&sum)), "Invalid variable name '-' at byte 7");
+        // 方程的两边不能再是方程，其他位置的方程和解析器一样留到求值时报错
+        assert_eq!(
+            err(&bytes(&[EQ, EQ, VAR, 1, b'x', VAR, 1, b'y', VAR, 1, b'z'])),
+            "Expected a single '=' in an equation at byte 5"
+        );
+        assert_eq!(
+            err(&bytes(&[ADD, VAR, 1, b'x', EQ, VAR, 1, b'y', EQ, VAR, 1, b'z', VAR, 1, b'w'])),
+            "Expected a single '=' in an equation at byte 9"
+        );
+        assert_eq!(err(&bytes(&[MATRIX, 0])), "Empty matrix at byte 5");
+        assert_eq!(err(&bytes(&[MATRIX, 1, MATRIX, 0])), "Empty matrix at byte 7");
+        assert_eq!(err(&bytes(&[99])), "Unknown node tag 99 at byte 5");
+        assert_eq!(
+            err(&bytes(&[VAR, 1, b'x', 0])),
+            "Unexpected trailing data at byte 8"
+        );
+        assert_eq!(
+            err(&bytes(&[CALL, 200, 0])),
+            "Unknown function id 200 at byte 6"
+        );
+        assert_eq!(
+            err(&bytes(&[CALL, 20, 0])),
+            "Function 'sqrt' expects 1 argument(s), found 0"
+        );
+        assert_eq!(
+            err(&bytes(&[
+                CALL, 32, 4, VAR, 1, b'k', VAR, 1, b'k', VAR, 1, b'k', VAR, 1, b'k'
+            ])),
+            "Unexpected call of 'sum' at byte 5"
+        );
+        assert_eq!(
+            err(&bytes(&[
+                VAR, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01
+            ])),
+            "Invalid length at byte 6"
+        );
+        let mut num = [NUM].to_vec();
+        num.extend([0, 0, 29, 0]);
+        num.extend([0; 12]);
+        assert_eq!(err(&bytes(&num)), "Invalid number at byte 6");
+        // 很深的嵌套不会耗尽调用栈
+        let deep = bytes(&[NEG; 100_000]);
+        assert_eq!(err(&deep), "Expression nesting exceeds 500 levels");
+        // 元素个数很大但数据不足时不会预先分配内存
+        assert_eq!(
+            err(&bytes(&[MATRIX, 0xff, 0xff, 0xff, 0xff, 0x0f])),
+            "Truncated syntax tree data at byte 11"
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        let ast = Parser::parse("sin(x)").unwrap();
+        let hex = to_hex(&ast.to_bytes());
+        assert_eq!(hex, "43414c4301091701070178");
+        assert_eq!(from_hex("4341 4C43\n0109170107017 8"), Ok(ast.to_bytes()));
+        assert_eq!(from_hex("43g1"), Err("Invalid hex digit: 'g'".to_string()));
+        assert_eq!(
+            from_hex("434"),
+            Err("Expected an even number of hex digits".to_string())
+        );
+    }
+
+    #[test]
+    fn test_corrupt_data() {
+        // 随机破坏编码后的数据，解码只会返回错误，不会 panic
+        let ast =
+            Parser::parse("sum(k, 1, n, [k, 2.5])/gcd(x, 12) - solve(x^2 = 2, x, 0, 2)").unwrap();
+        let bytes = ast.to_bytes();
+        let mut rng = Rng::new(45);
+        for _ in 0..20_000 {
+            let mut corrupt = bytes.clone();
+            match rng.below(3) {
+                0 => corrupt.truncate(rng.below(bytes.len() as u128) as usize),
+                _ => {
+                    let i = rng.below(bytes.len() as u128) as usize;
+                    corrupt[i] = rng.below(256) as u8;
+                }
+            }
+            let _ = Ast::from_bytes(&corrupt, DEFAULT_MAX_DEPTH);
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use rust_decimal::Decimal;
+
+use crate::calc::{
+    ast::{Ast, Node},
+    context::{Context, deep},
+    function::{Function, constant},
+    integer::{to_decimal, to_integer},
+    linear,
+    token::Operator,
+    value::Value,
+};
+
+// 栈式虚拟机的指令。和 get_rpn 得到的逆波兰式一样，运算数在前、运算在后，
+// 变量在编译时换成槽位的下标，求值时不再需要按名字查找
+#[derive(Debug, PartialEq, Clone)]
+enum Instr {
+    Push(Decimal),
+    Load(usize),
+    Op(Operator),
+    Neg,
+    // 把栈顶的 n 个值组成列表
+    List(usize),
+    Call(Function, usize),
+    // 惰性函数仍然按语法树求值，参数是 Program::lazy 的下标
+    Lazy(usize),
+    // sum 和 prod 的开始：弹出上下界，把下界存入循环变量的槽位；区间为空时压入 0 或 1 并跳到 end
+    Loop {
+        func: Function,
+        slot: usize,
+        end: usize,
+    },
+    // 把栈顶的值累积到循环的结果中，循环变量没有到达上界时加一并跳回 body
+    Next {
+        body: usize,
+    },
+}
+
+// 惰性函数的调用，求值时把编译时可见的变量绑定到上下文中
+#[derive(Debug, Clone)]
+struct Lazy {
+    func: Function,
+    args: Vec<Node>,
+    scope: Vec<(String, usize)>,
+}
+
+// 编译后的表达式。前 vars.len() 个槽位是输入的变量，其余的槽位是 sum 和 prod 的循环变量
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Instr>,
+    lazy: Vec<Lazy>,
+    vars: Vec<String>,
+    slots: usize,
+    // 求值过程中栈的最大深度，虚拟机按这个大小预先分配栈
+    depth: usize,
+}
+
+// 表达式中的自由变量，按第一次出现的顺序排列。常数 pi 和 e 不算变量，
+// sum、prod、diff、integrate、collect 和 solve 绑定的变量只在它们的表达式中可见，也不算自由变量
+fn free_vars(node: &Node, bound: &mut Vec<String>, vars: &mut Vec<String>) {
+    use Node::*;
+    deep(|| match node {
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Div(lhs, rhs)
+        | Pow(lhs, rhs)
+        | Eq(lhs, rhs) => {
+            free_vars(lhs, bound, vars);
+            free_vars(rhs, bound, vars);
+        }
+        Neg(val) => free_vars(val, bound, vars),
+        Num(_) => {}
+        Var(name) => {
+            if !bound.contains(name) && !vars.contains(name) && constant(name).is_none() {
+                vars.push(name.clone());
+            }
+        }
+        Matrix(args) => args.iter().for_each(|arg| free_vars(arg, bound, vars)),
+        Call(
+            func @ (Function::Diff | Function::Integrate | Function::Collect | Function::Solve),
+            args,
+        ) if *func != Function::Solve
+            || matches!(args[0], Eq(..))
+            || linear::is_system(&args[0]) =>
+        {
+            // 第二个参数是绑定的变量或者变量的列表，只在第一个参数中可见
+            let names = match &args[1] {
+                Matrix(names) => &names[..],
+                name => std::slice::from_ref(name),
+            };
+            let len = bound.len();
+            bound.extend(names.iter().filter_map(|name| match name {
+                Var(name) => Some(name.clone()),
+                _ => None,
+            }));
+            free_vars(&args[0], bound, vars);
+            bound.truncate(len);
+            args[2..].iter().for_each(|arg| free_vars(arg, bound, vars));
+        }
+        Call(_, args) => args.iter().for_each(|arg| free_vars(arg, bound, vars)),
+        Sum(var, from, to, body) | Prod(var, from, to, body) => {
+            free_vars(from, bound, vars);
+            free_vars(to, bound, vars);
+            bound.push(var.clone());
+            free_vars(body, bound, vars);
+            bound.pop();
+        }
+    })
+}
+
+struct Compiler {
+    code: Vec<Instr>,
+    lazy: Vec<Lazy>,
+    // 可见的变量和它们的槽位，内层的循环变量在后面
+    scope: Vec<(String, usize)>,
+    slots: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Compiler {
+    // 添加一条指令，pop 和 push 是它从栈上弹出和压入的值的个数
+    fn emit(&mut self, instr: Instr, pop: usize, push: usize) {
+        self.code.push(instr);
+        self.depth = self.depth - pop + push;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        deep(|| self.compile_node(node))
+    }
+
+    fn compile_node(&mut self, node: &Node) -> Result<(), String> {
+        use Node::*;
+        let mut binary = |op: Operator, lhs: &Node, rhs: &Node| {
+            self.compile(lhs)?;
+            self.compile(rhs)?;
+            self.emit(Instr::Op(op), 2, 1);
+            Ok(())
+        };
+        match node {
+            Add(lhs, rhs) => binary(Operator::Add, lhs, rhs),
+            Sub(lhs, rhs) => binary(Operator::Sub, lhs, rhs),
+            Mul(lhs, rhs) => binary(Operator::Mul, lhs, rhs),
+            Div(lhs, rhs) => binary(Operator::Div, lhs, rhs),
+            Pow(lhs, rhs) => binary(Operator::Pow, lhs, rhs),
+            Neg(val) => {
+                self.compile(val)?;
+                self.emit(Instr::Neg, 1, 1);
+                Ok(())
+            }
+            Num(num) => {
+                self.emit(Instr::Push(*num), 0, 1);
+                Ok(())
+            }
+            Var(name) => {
+                let slot = self.scope.iter().rev().find(|(var, _)| var == name);
+                match (slot, constant(name)) {
+                    (Some((_, slot)), _) => self.emit(Instr::Load(*slot), 0, 1),
+                    (None, Some(num)) => self.emit(Instr::Push(num), 0, 1),
+                    // 自由变量都在编译之前分配了槽位
+                    (None, None) => unreachable!(),
+                }
+                Ok(())
+            }
+            Matrix(elems) => {
+                for elem in elems {
+                    self.compile(elem)?;
+                }
+                self.emit(Instr::List(elems.len()), elems.len(), 1);
+                Ok(())
+            }
+            Call(func, args) if func.is_lazy() => {
+                self.lazy.push(Lazy {
+                    func: *func,
+                    args: args.clone(),
+                    scope: self.scope.clone(),
+                });
+                self.emit(Instr::Lazy(self.lazy.len() - 1), 0, 1);
+                Ok(())
+            }
+            Call(func, args) => {
+                for arg in args {
+                    self.compile(arg)?;
+                }
+                self.emit(Instr::Call(*func, args.len()), args.len(), 1);
+                Ok(())
+            }
+            Sum(var, from, to, body) | Prod(var, from, to, body) => {
+                let func = if matches!(node, Sum(..)) {
+                    Function::Sum
+                } else {
+                    Function::Prod
+                };
+                self.compile(from)?;
+                self.compile(to)?;
+                let start = self.code.len();
+                let slot = self.slots;
+                self.slots += 1;
+                // end 在编译完循环体之后回填
+                self.emit(Instr::Loop { func, slot, end: 0 }, 2, 0);
+                self.scope.push((var.clone(), slot));
+                self.compile(body)?;
+                self.scope.pop();
+                self.emit(Instr::Next { body: start + 1 }, 1, 1);
+                let end = self.code.len();
+                self.code[start] = Instr::Loop { func, slot, end };
+                Ok(())
+            }
+            Eq(..) => Err(format!(
+                "Equation '{node}' can only be used as an argument of 'solve'"
+            )),
+        }
+    }
+}
+
+impl Ast {
+    // 编译成字节码，自由变量按 Program::vars 的顺序在求值时传入
+    pub fn compile(&self) -> Result<Program, String> {
+        let mut vars = Vec::new();
+        free_vars(&self.0, &mut Vec::new(), &mut vars);
+        let mut compiler = Compiler {
+            code: Vec::new(),
+            lazy: Vec::new(),
+            scope: vars.iter().cloned().zip(0..).collect(),
+            slots: vars.len(),
+            depth: 0,
+            max_depth: 0,
+        };
+        compiler.compile(&self.0)?;
+        Ok(Program {
+            code: compiler.code,
+            lazy: compiler.lazy,
+            vars,
+            slots: compiler.slots,
+            depth: compiler.max_depth,
+        })
+    }
+}
+
+impl Program {
+    pub fn vars(&self) -> &[String] {
+        &self.vars
+    }
+}
+
+// 正在执行的 sum 或 prod
+struct Frame {
+    op: Operator,
+    slot: usize,
+    k: i128,
+    to: i128,
+    acc: Option<Value>,
+}
+
+// 虚拟机在多次求值之间复用栈和槽位，求值时不再分配内存（矩阵和函数调用的参数除外）
+pub struct Vm {
+    stack: Vec<Value>,
+    slots: Vec<Value>,
+    loops: Vec<Frame>,
+}
+
+impl Vm {
+    pub fn new(program: &Program) -> Self {
+        Vm {
+            stack: Vec::with_capacity(program.depth),
+            slots: vec![Value::Num(Decimal::ZERO); program.slots],
+            loops: Vec::new(),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        // 编译时保证了栈的深度
+        self.stack.pop().unwrap()
+    }
+
+    // args 是自由变量的值，顺序和 Program::vars 相同
+    pub fn run(
+        &mut self,
+        program: &Program,
+        args: &[Value],
+        ctx: &mut Context,
+    ) -> Result<Value, String> {
+        if args.len() != program.vars.len() {
+            return Err(format!(
+                "Expected {} variable value(s), found {}",
+                program.vars.len(),
+                args.len()
+            ));
+        }
+        // 上一次求值出错时可能留下了中间结果
+        self.stack.clear();
+        self.loops.clear();
+        self.slots.resize(program.slots, Value::Num(Decimal::ZERO));
+        self.slots[..args.len()].clone_from_slice(args);
+        let mut pc = 0;
+        while let Some(instr) = program.code.get(pc) {
+            pc += 1;
+            match instr {
+                Instr::Push(num) => self.stack.push(Value::Num(*num)),
+                Instr::Load(slot) => self.stack.push(self.slots[*slot].clone()),
+                Instr::Op(op) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(Value::operate(*op, lhs, rhs)?);
+                }
+                Instr::Neg => {
+                    let val = self.pop().neg()?;
+                    self.stack.push(val);
+                }
+                Instr::List(len) => {
+                    let elems = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(Value::list(elems)?);
+                }
+                Instr::Call(func, len) => {
+                    let args = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(func.call(args, ctx)?);
+                }
+                Instr::Lazy(index) => {
+                    let lazy = &program.lazy[*index];
+                    let vars = lazy
+                        .scope
+                        .iter()
+                        .map(|(var, slot)| (var.as_str(), self.slots[*slot].clone()));
+                    let res = ctx.scoped_all(vars, |ctx| lazy.func.call_lazy(&lazy.args, ctx))?;
+                    self.stack.push(res);
+                }
+                Instr::Loop { func, slot, end } => {
+                    let to = self.pop();
+                    let from = to_integer(*func, self.pop())?;
+                    let to = to_integer(*func, to)?;
+                    let op = if *func == Function::Sum {
+                        Operator::Add
+                    } else {
+                        Operator::Mul
+                    };
+                    if from > to {
+                        let empty = if op == Operator::Add {
+                            Decimal::ZERO
+                        } else {
+                            Decimal::ONE
+                        };
+                        self.stack.push(Value::Num(empty));
+                        pc = *end;
+                        continue;
+                    }
+                    ctx.use_iterations(from.abs_diff(to) + 1)?;
+                    self.slots[*slot] = Value::Num(to_decimal(from)?);
+                    self.loops.push(Frame {
+                        op,
+                        slot: *slot,
+                        k: from,
+                        to,
+                        acc: None,
+                    });
+                }
+                Instr::Next { body } => {
+                    let term = self.pop();
+                    let frame = self.loops.last_mut().unwrap();
+                    frame.acc = Some(match frame.acc.take() {
+                        Some(acc) => Value::operate(frame.op, acc, term)?,
+                        None => term,
+                    });
+                    if frame.k < frame.to {
+                        frame.k += 1;
+                        self.slots[frame.slot] = Value::Num(to_decimal(frame.k)?);
+                        pc = *body;
+                    } else {
+                        let frame = self.loops.pop().unwrap();
+                        self.stack.push(frame.acc.unwrap());
+                    }
+                }
+            }
+        }
+        Ok(self.pop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::parser::Parser;
+    use rust_decimal::dec;
+
+    fn run(expr: &str, args: &[Value]) -> Result<Value, String> {
+        let program = Parser::parse(expr).unwrap().compile()?;
+        Vm::new(&program).run(&program, args, &mut Context::with_seed(0))
+    }
+
+    #[test]
+    fn test_compile() {
+        let program = Parser::parse("3*(4 + x) - y^2").unwrap().compile().unwrap();
+        assert_eq!(program.vars(), ["x", "y"]);
+        assert_eq!(
+            program.code,
+            [
+                Instr::Push(dec!(3)),
+                Instr::Push(dec!(4)),
+                Instr::Load(0),
+                Instr::Op(Operator::Add),
+                Instr::Op(Operator::Mul),
+                Instr::Load(1),
+                Instr::Push(dec!(2)),
+                Instr::Op(Operator::Pow),
+                Instr::Op(Operator::Sub),
+            ]
+        );
+        assert_eq!(program.depth, 3);
+        // 循环变量、求导变量和常数不是自由变量
+        let program = Parser::parse("sum(k, 1, n, k*pi) + diff(t^2*a, t, b) + k")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(program.vars(), ["n", "a", "b", "k"]);
+        assert_eq!(program.slots, 5);
+        let program = Parser::parse("solve([x + y = c, x - y = 0], [x, y]) + expand((z + 1)^2)")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(program.vars(), ["c", "z"]);
+    }
+
+    #[test]
+    fn test_matches_tree_walking() {
+        let x = Value::Num(dec!(2.5));
+        let y = Value::Num(dec!(-3));
+        for expr in [
+            "1 + 2*3 - 4/5",
+            "3*(4 + x) - y^2",
+            "-x^2 + sqrt(x)*sin(y)",
+            "[x, y, [1, 2]]",
+            "[[1, 2], [3, 4]]^2*x",
+            "gcd(12, 18, 8) + choose(10, 3)",
+            "sum(k, 1, 10, k^2) + prod(k, 1, 5, k + x)",
+            "sum(i, 1, 4, sum(j, i, 4, i*j + y))",
+            "sum(x, 1, 3, x) + x",
+            "sum(k, 5, 1, k) + prod(k, 5, 1, k)",
+            "sum(k, 1, 3, diff(t^k, t, x))",
+            "integrate(t*y, t, 0, x)",
+            "solve(t^2 = x, t, 0, 10)",
+            "solve([[1, 2], [3, 4]], [x, y])",
+            "solve([t + u = x, t - u = y], [t, u])",
+            "repeat(3, rand()) + [randint(1, 6), normal(0, 1)]",
+        ] {
+            let ast = Parser::parse(expr).unwrap();
+            let program = ast.compile().unwrap();
+            let args = &[x.clone(), y.clone()][..program.vars().len()];
+            let mut ctx = Context::with_seed(1);
+            let expected = ctx.scoped_all(
+                program
+                    .vars()
+                    .iter()
+                    .map(String::as_str)
+                    .zip(args.iter().cloned()),
+                |ctx| ast.eval(ctx),
+            );
+            let res = Vm::new(&program).run(&program, args, &mut Context::with_seed(1));
+            assert_eq!(res, expected, "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let x = Value::Num(dec!(0));
+        assert_eq!(
+            run("1/x", std::slice::from_ref(&x)),
+            Err("Division by zero".to_string())
+        );
+        assert_eq!(
+            run("x + y", std::slice::from_ref(&x)),
+            Err("Expected 2 variable value(s), found 1".to_string())
+        );
+        assert_eq!(
+            run("sum(k, 1, 2.5, k)", &[]),
+            Err("Function 'sum' expects integer arguments, found '2.5'".to_string())
+        );
+        assert_eq!(
+            run("sum(k, 1, 10^7, k)", &[]),
+            Err("Iteration count 10000000 exceeds the limit of 1000000".to_string())
+        );
+        assert_eq!(
+            run("1 + (x = 2)", &[]),
+            Err("Equation 'x = 2' can only be used as an argument of 'solve'".to_string())
+        );
+        // 出错之后虚拟机可以继续使用
+        let program = Parser::parse("sum(k, 1, 3, 6/(k - x))")
+            .unwrap()
+            .compile()
+            .unwrap();
+        let mut vm = Vm::new(&program);
+        let mut ctx = Context::with_seed(0);
+        let res = vm.run(&program, &[Value::Num(dec!(2))], &mut ctx);
+        assert_eq!(res, Err("Division by zero".to_string()));
+        let res = vm.run(&program, &[Value::Num(dec!(0))], &mut ctx);
+        assert_eq!(res, Ok(Value::Num(dec!(11))));
+    }
+}
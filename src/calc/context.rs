@@ -0,0 +1,207 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+
+use crate::calc::{tokenizer::Numerals, value::Value};
+
+// SplitMix64 伪随机数生成器，状态只有一个 u64，相同的种子总是产生相同的序列
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // [0, bound) 之间均匀分布的整数，拒绝采样避免取模带来的偏差
+    pub fn below(&mut self, bound: u128) -> u128 {
+        let zone = u128::MAX - u128::MAX % bound;
+        loop {
+            let x = ((self.next_u64() as u128) << 64) | self.next_u64() as u128;
+            if x < zone {
+                return x % bound;
+            }
+        }
+    }
+
+    // [0, 1) 之间均匀分布的小数，精度为 2^-64
+    pub fn unit(&mut self) -> Decimal {
+        Decimal::from(self.next_u64()) / Decimal::from(u64::MAX as u128 + 1)
+    }
+}
+
+// 默认的最大迭代次数，避免 sum(k, 1, 10^20, k) 这样的表达式卡住 REPL
+pub const DEFAULT_MAX_ITERATIONS: u128 = 1_000_000;
+
+// 默认的语法树最大嵌套深度
+pub const DEFAULT_MAX_DEPTH: usize = 500;
+
+// 剩余的栈少于 RED_ZONE 时，deep 在堆上分配一段 STACK_SEGMENT 大小的新栈继续递归
+pub const RED_ZONE: usize = 1 << 20;
+pub const STACK_SEGMENT: usize = 16 << 20;
+
+// 求值时需要携带的状态
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub rng: Rng,
+    // 一次求值中 sum、prod、repeat 等迭代结构允许的最大迭代次数，嵌套的循环按总次数计算
+    pub max_iterations: u128,
+    // 这一次求值已经用掉的迭代次数
+    iterations: u128,
+    // 解析表达式时允许的最大嵌套深度
+    pub max_depth: usize,
+    // 解析中缀表达式时接受的数字和运算符
+    pub numerals: Numerals,
+    // 变量绑定，按作用域从外到内排列，查找的时候从后往前找，内层的变量会遮蔽外层的同名变量
+    vars: Vec<(String, Value)>,
+}
+
+impl Context {
+    // 以当前时间作为随机数种子
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        Context::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Context {
+            rng: Rng::new(seed),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            iterations: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            numerals: Numerals::Ascii,
+            vars: Vec::new(),
+        }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn check_iterations(&self, count: u128) -> Result<(), String> {
+        if count > self.max_iterations {
+            Err(format!(
+                "Iteration count {count} exceeds the limit of {}",
+                self.max_iterations
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // 记录即将进行的 count 次迭代，和这一次求值中已经进行的迭代一起检查
+    pub fn use_iterations(&mut self, count: u128) -> Result<(), String> {
+        let total = self.iterations.saturating_add(count);
+        self.check_iterations(total)?;
+        self.iterations = total;
+        Ok(())
+    }
+
+    // 顶层的求值开始之前调用，重新计算迭代次数
+    pub fn reset_iterations(&mut self) {
+        self.iterations = 0;
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Value> {
+        self.vars
+            .iter()
+            .rev()
+            .find(|(var, _)| var == name)
+            .map(|(_, val)| val)
+    }
+
+    // 在一个新的作用域里绑定变量并执行 f，无论 f 是否出错，离开时都会解除绑定
+    pub fn scoped<T>(&mut self, name: &str, val: Value, f: impl FnOnce(&mut Context) -> T) -> T {
+        self.vars.push((name.to_string(), val));
+        let res = f(self);
+        self.vars.pop();
+        res
+    }
+
+    // 同时绑定多个变量，后面的变量遮蔽前面的同名变量
+    pub fn scoped_all<'a, T>(
+        &mut self,
+        vars: impl IntoIterator<Item = (&'a str, Value)>,
+        f: impl FnOnce(&mut Context) -> T,
+    ) -> T {
+        let len = self.vars.len();
+        self.vars
+            .extend(vars.into_iter().map(|(name, val)| (name.to_string(), val)));
+        let res = f(self);
+        self.vars.truncate(len);
+        res
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context::new()
+    }
+}
+
+// 语法树上的递归（求值、打印、化简、求导等）都从这里进入下一层，栈快用完时自动扩展，
+// 所以语法树的高度只受内存限制
+pub fn deep<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_seeded_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let xs: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        let ys: Vec<u64> = (0..5).map(|_| b.next_u64()).collect();
+        assert_eq!(xs, ys);
+        assert_ne!(Rng::new(43).next_u64(), xs[0]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let x = rng.unit();
+            assert!(x >= dec!(0) && x < dec!(1));
+            assert!(rng.below(6) < 6);
+        }
+        assert_eq!(rng.below(1), 0);
+    }
+
+    #[test]
+    fn test_scoped_vars() {
+        let mut ctx = Context::with_seed(0);
+        assert_eq!(ctx.var("x"), None);
+        let inner = ctx.scoped("x", Value::Num(dec!(1)), |ctx| {
+            ctx.scoped("x", Value::Num(dec!(2)), |ctx| ctx.var("x").cloned())
+        });
+        assert_eq!(inner, Some(Value::Num(dec!(2))));
+        assert_eq!(ctx.var("x"), None);
+    }
+
+    #[test]
+    fn test_iteration_limit() {
+        let mut ctx = Context::with_seed(0);
+        ctx.max_iterations = 10;
+        assert_eq!(ctx.check_iterations(10), Ok(()));
+        assert_eq!(
+            ctx.check_iterations(11),
+            Err("Iteration count 11 exceeds the limit of 10".to_string())
+        );
+    }
+}
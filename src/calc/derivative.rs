@@ -0,0 +1,387 @@
+use rust_decimal::Decimal;
+
+use crate::calc::{ast::Node, context::deep, function::Function, simplify::simplify};
+
+fn bx(node: Node) -> Box<Node> {
+    Box::new(node)
+}
+
+fn num(n: i64) -> Node {
+    Node::Num(Decimal::from(n))
+}
+
+fn add(lhs: Node, rhs: Node) -> Node {
+    Node::Add(bx(lhs), bx(rhs))
+}
+
+fn sub(lhs: Node, rhs: Node) -> Node {
+    Node::Sub(bx(lhs), bx(rhs))
+}
+
+// 常数的导数 0 乘以任何因子都是 0。化简时不会消去 0 * sin(x) 这样可能没有定义的项，
+// 这里直接去掉，乘积法则里和 var 无关的一项就不会留在结果里
+fn mul(lhs: Node, rhs: Node) -> Node {
+    match (&lhs, &rhs) {
+        (Node::Num(n), _) | (_, Node::Num(n)) if n.is_zero() => num(0),
+        _ => Node::Mul(bx(lhs), bx(rhs)),
+    }
+}
+
+fn div(lhs: Node, rhs: Node) -> Node {
+    Node::Div(bx(lhs), bx(rhs))
+}
+
+fn pow(lhs: Node, rhs: Node) -> Node {
+    Node::Pow(bx(lhs), bx(rhs))
+}
+
+fn call(func: Function, arg: Node) -> Node {
+    Node::Call(func, vec![arg])
+}
+
+// 表达式是否依赖变量 var，sum 和 prod 的循环变量、积分变量以及在某一点求值的求导变量只在函数体内可见，
+// 会遮蔽外面的同名变量
+pub fn contains_var(node: &Node, var: &str) -> bool {
+    use Node::*;
+    deep(|| match node {
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Div(lhs, rhs)
+        | Pow(lhs, rhs)
+        | Eq(lhs, rhs) => contains_var(lhs, var) || contains_var(rhs, var),
+        Neg(val) => contains_var(val, var),
+        Num(_) => false,
+        Var(name) => name == var,
+        Call(func, args) if binds_var(*func, args) => {
+            let Var(bound) = &args[1] else {
+                unreachable!()
+            };
+            args[2..].iter().any(|arg| contains_var(arg, var))
+                || (bound != var && contains_var(&args[0], var))
+        }
+        Matrix(elems) | Call(_, elems) => elems.iter().any(|elem| contains_var(elem, var)),
+        Sum(k, from, to, body) | Prod(k, from, to, body) => {
+            contains_var(from, var)
+                || contains_var(to, var)
+                || (k != var && contains_var(body, var))
+        }
+    })
+}
+
+// integrate(body, t, a, b) 和 diff(body, t, a) 中的 t 只在 body 中可见，
+// 而 diff(body, t) 的结果仍然是 t 的函数
+fn binds_var(func: Function, args: &[Node]) -> bool {
+    func == Function::Integrate || (func == Function::Diff && args.len() == 3)
+}
+
+// 表达式中自由出现的变量，按名字排序，没有重复
+pub fn free_vars(node: &Node) -> Vec<String> {
+    let mut vars = Vec::new();
+    collect_vars(node, &mut vars);
+    vars.sort();
+    vars.dedup();
+    vars
+}
+
+fn collect_vars(node: &Node, vars: &mut Vec<String>) {
+    use Node::*;
+    deep(|| match node {
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Div(lhs, rhs)
+        | Pow(lhs, rhs)
+        | Eq(lhs, rhs) => {
+            collect_vars(lhs, vars);
+            collect_vars(rhs, vars);
+        }
+        Neg(val) => collect_vars(val, vars),
+        Num(_) => {}
+        Var(name) => vars.push(name.clone()),
+        Call(func, args) if binds_var(*func, args) => {
+            let Var(bound) = &args[1] else {
+                unreachable!()
+            };
+            for arg in &args[2..] {
+                collect_vars(arg, vars);
+            }
+            let mut inner = Vec::new();
+            collect_vars(&args[0], &mut inner);
+            vars.extend(inner.into_iter().filter(|var| var != bound));
+        }
+        Matrix(elems) | Call(_, elems) => {
+            for elem in elems {
+                collect_vars(elem, vars);
+            }
+        }
+        Sum(k, from, to, body) | Prod(k, from, to, body) => {
+            collect_vars(from, vars);
+            collect_vars(to, vars);
+            let mut inner = Vec::new();
+            collect_vars(body, &mut inner);
+            vars.extend(inner.into_iter().filter(|var| var != k));
+        }
+    })
+}
+
+// 把表达式中自由出现的变量 var 替换成 with
+pub fn substitute(node: &Node, var: &str, with: &Node) -> Node {
+    use Node::*;
+    let sub = |node: &Node| bx(substitute(node, var, with));
+    deep(|| match node {
+        Add(lhs, rhs) => Add(sub(lhs), sub(rhs)),
+        Sub(lhs, rhs) => Sub(sub(lhs), sub(rhs)),
+        Mul(lhs, rhs) => Mul(sub(lhs), sub(rhs)),
+        Div(lhs, rhs) => Div(sub(lhs), sub(rhs)),
+        Pow(lhs, rhs) => Pow(sub(lhs), sub(rhs)),
+        Eq(lhs, rhs) => Eq(sub(lhs), sub(rhs)),
+        Neg(val) => Neg(sub(val)),
+        Num(_) => node.clone(),
+        Var(name) if name == var => with.clone(),
+        Var(_) => node.clone(),
+        Matrix(elems) => Matrix(elems.iter().map(|elem| *sub(elem)).collect()),
+        // diff(body, x) 中的 x 被替换成 a，相当于求导数在 a 处的值 diff(body, x, a)
+        Call(Function::Diff, args) if args.len() == 2 && args[1] == Var(var.to_string()) => {
+            Call(Function::Diff, vec![args[0].clone(), args[1].clone(), with.clone()])
+        }
+        Call(func, args) if binds_var(*func, args) => {
+            let mut args = args.clone();
+            for arg in &mut args[2..] {
+                *arg = *sub(arg);
+            }
+            if args[1] != Var(var.to_string()) {
+                args[0] = *sub(&args[0]);
+            }
+            Call(*func, args)
+        }
+        Call(func, args) => Call(*func, args.iter().map(|arg| *sub(arg)).collect()),
+        Sum(k, from, to, body) | Prod(k, from, to, body) => {
+            let body = if k == var { body.clone() } else { sub(body) };
+            if matches!(node, Sum(..)) {
+                Sum(k.clone(), sub(from), sub(to), body)
+            } else {
+                Prod(k.clone(), sub(from), sub(to), body)
+            }
+        }
+    })
+}
+
+// 表达式对变量 var 的导数，结果经过化简
+pub fn derivative(node: &Node, var: &str) -> Result<Node, String> {
+    Ok(simplify(diff(node, var)?))
+}
+
+fn diff(node: &Node, var: &str) -> Result<Node, String> {
+    use Node::*;
+    if !contains_var(node, var) {
+        return Ok(num(0));
+    }
+    deep(|| Ok(match node {
+        Add(u, v) => add(diff(u, var)?, diff(v, var)?),
+        Sub(u, v) => sub(diff(u, var)?, diff(v, var)?),
+        // 方程两边同时求导
+        Eq(u, v) => Eq(bx(diff(u, var)?), bx(diff(v, var)?)),
+        Neg(u) => Neg(bx(diff(u, var)?)),
+        // (uv)' = u'v + uv'
+        Mul(u, v) => add(
+            mul(diff(u, var)?, (**v).clone()),
+            mul((**u).clone(), diff(v, var)?),
+        ),
+        // 分母是常数时 (u / c)' = u' / c，否则 (u / v)' = (u'v - uv') / v^2
+        Div(u, v) if !contains_var(v, var) => div(diff(u, var)?, (**v).clone()),
+        Div(u, v) => div(
+            sub(
+                mul(diff(u, var)?, (**v).clone()),
+                mul((**u).clone(), diff(v, var)?),
+            ),
+            pow((**v).clone(), num(2)),
+        ),
+        // 指数是常数：(u^n)' = n * u^(n - 1) * u'
+        Pow(u, n) if !contains_var(n, var) => mul(
+            mul(
+                (**n).clone(),
+                pow((**u).clone(), sub((**n).clone(), num(1))),
+            ),
+            diff(u, var)?,
+        ),
+        // 底数是常数：(a^v)' = a^v * ln(a) * v'
+        Pow(a, v) if !contains_var(a, var) => mul(
+            mul(node.clone(), call(Function::Ln, (**a).clone())),
+            diff(v, var)?,
+        ),
+        // 一般情况：(u^v)' = u^v * (v' * ln(u) + v * u' / u)
+        Pow(u, v) => mul(
+            node.clone(),
+            add(
+                mul(diff(v, var)?, call(Function::Ln, (**u).clone())),
+                div(mul((**v).clone(), diff(u, var)?), (**u).clone()),
+            ),
+        ),
+        Matrix(elems) => Matrix(
+            elems
+                .iter()
+                .map(|elem| diff(elem, var))
+                .collect::<Result<_, _>>()?,
+        ),
+        Call(func, args) => diff_call(*func, args, var)?,
+        // 上下限和 var 无关时，求和的导数是每一项导数的和
+        Sum(k, from, to, body) if !contains_var(from, var) && !contains_var(to, var) => {
+            Sum(k.clone(), from.clone(), to.clone(), bx(diff(body, var)?))
+        }
+        // 求积的导数：(Π f)' = Π f * Σ (f' / f)
+        Prod(k, from, to, body) if !contains_var(from, var) && !contains_var(to, var) => mul(
+            node.clone(),
+            Sum(
+                k.clone(),
+                from.clone(),
+                to.clone(),
+                bx(div(diff(body, var)?, (**body).clone())),
+            ),
+        ),
+        Sum(..) | Prod(..) => {
+            let func = if matches!(node, Sum(..)) {
+                "sum"
+            } else {
+                "prod"
+            };
+            return Err(format!(
+                "Cannot differentiate '{func}' whose bounds depend on '{var}'"
+            ));
+        }
+        Num(_) | Var(_) => num(1),
+    }))
+}
+
+// 函数调用的导数，使用链式法则
+fn diff_call(func: Function, args: &[Node], var: &str) -> Result<Node, String> {
+    use Function::*;
+    let res = match func {
+        // diff(u, y) 作为子表达式出现时，先求出对 y 的导数再对 var 求导
+        Diff if args.len() == 2 => {
+            let Node::Var(y) = &args[1] else {
+                unreachable!()
+            };
+            return diff(&derivative(&args[0], y)?, var);
+        }
+        // diff(u, y, p) 是导数在 y = p 处的值，先代入 p 再对 var 求导
+        Diff => {
+            let Node::Var(y) = &args[1] else {
+                unreachable!()
+            };
+            return diff(&substitute(&derivative(&args[0], y)?, y, &args[2]), var);
+        }
+        // 积分上下限求导（莱布尼茨法则）：
+        // d/dx ∫[a, b] f(t, x) dt = f(b, x) * b' - f(a, x) * a' + ∫[a, b] ∂f/∂x dt
+        Integrate => {
+            let Node::Var(t) = &args[1] else {
+                unreachable!()
+            };
+            let (body, a, b) = (&args[0], &args[2], &args[3]);
+            let upper = mul(substitute(body, t, b), diff(b, var)?);
+            let lower = mul(substitute(body, t, a), diff(a, var)?);
+            let mut res = sub(upper, lower);
+            if t != var && contains_var(body, var) {
+                let mut inner = args.to_vec();
+                inner[0] = diff(body, var)?;
+                res = add(res, Node::Call(Integrate, inner));
+            }
+            return Ok(res);
+        }
+        Sqrt | Exp | Ln | Sin | Cos | Tan => {
+            let u = args[0].clone();
+            let outer = match func {
+                // sqrt(u)' = 1 / (2 * sqrt(u))
+                Sqrt => div(num(1), mul(num(2), call(Sqrt, u))),
+                Exp => call(Exp, u),
+                Ln => div(num(1), u),
+                Sin => call(Cos, u),
+                Cos => Node::Neg(bx(call(Sin, u))),
+                // tan(u)' = 1 / cos(u)^2
+                _ => div(num(1), pow(call(Cos, u), num(2))),
+            };
+            mul(outer, diff(&args[0], var)?)
+        }
+        _ => return Err(format!("Cannot differentiate function '{func}'")),
+    };
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{ast::Ast, context::Context, parser::Parser, value::Value};
+    use rust_decimal::dec;
+
+    fn d(expr: &str) -> Result<String, String> {
+        let Ast(node) = Parser::parse(expr)?;
+        derivative(&node, "x").map(|res| res.to_string())
+    }
+
+    #[test]
+    fn test_polynomials() {
+        assert_eq!(d("x^3 + 2*x"), Ok("3*x^2 + 2".to_string()));
+        assert_eq!(d("5"), Ok("0".to_string()));
+        assert_eq!(d("y * x"), Ok("y".to_string()));
+        assert_eq!(d("(x + 1) * (x - 1)"), Ok("2*x".to_string()));
+        assert_eq!(d("1 / x"), Ok("-1/x^2".to_string()));
+        assert_eq!(d("x / 2"), Ok("0.5".to_string()));
+        assert_eq!(d("-x^2"), Ok("-2*x".to_string()));
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(d("sin(x^2)"), Ok("2*cos(x^2)*x".to_string()));
+        assert_eq!(d("exp(3*x)"), Ok("3*exp(3*x)".to_string()));
+        assert_eq!(d("ln(x)"), Ok("1/x".to_string()));
+        assert_eq!(d("cos(x)"), Ok("-sin(x)".to_string()));
+        assert_eq!(d("2^x"), Ok("2^x*ln(2)".to_string()));
+        assert_eq!(d("sqrt(y)"), Ok("0".to_string()));
+        assert_eq!(
+            d("gcd(x, 2)"),
+            Err("Cannot differentiate function 'gcd'".to_string())
+        );
+        assert_eq!(d("diff(x^3, x)"), Ok("6*x".to_string()));
+    }
+
+    #[test]
+    fn test_sum_and_prod() {
+        assert_eq!(
+            d("sum(k, 1, 3, k*x^k)"),
+            Ok("sum(k, 1, 3, k^2*x^(k - 1))".to_string())
+        );
+        // 循环变量遮蔽了 x
+        assert_eq!(d("sum(x, 1, 3, x)"), Ok("0".to_string()));
+        assert_eq!(
+            d("sum(k, 1, x, k)"),
+            Err("Cannot differentiate 'sum' whose bounds depend on 'x'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bound_variables() {
+        // 微积分基本定理
+        assert_eq!(d("integrate(t^2, t, 0, x)"), Ok("x^2".to_string()));
+        assert_eq!(d("integrate(t, t, x, 2*x)"), Ok("3*x".to_string()));
+        assert_eq!(
+            d("integrate(x*t, t, 0, 1)"),
+            Ok("integrate(t, t, 0, 1)".to_string())
+        );
+        // 积分变量遮蔽了 x
+        assert_eq!(d("integrate(x, x, 0, 1)"), Ok("0".to_string()));
+        // diff(t^3, t, x) = 3x^2
+        assert_eq!(d("diff(t^3, t, x)"), Ok("6*x".to_string()));
+    }
+
+    #[test]
+    fn test_eval_derivative() {
+        let mut ctx = Context::with_seed(0);
+        let res = Parser::parse("diff(x^3 + 2*x, x, 2)")
+            .unwrap()
+            .eval(&mut ctx);
+        assert_eq!(res, Ok(Value::Num(dec!(14))));
+        // x^x 的导数 x^x * (ln(x) + 1) 在 x = 1 处为 1
+        let res = Parser::parse("diff(x^x, x, 1)").unwrap().eval(&mut ctx);
+        assert_eq!(res, Ok(Value::Num(dec!(1))));
+    }
+}
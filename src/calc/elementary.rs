@@ -0,0 +1,110 @@
+use rust_decimal::{Decimal, MathematicalOps, dec};
+
+// rust_decimal 自带的 exp、sin、cos 只有 9 到 12 位有效数字，数值积分和解方程需要更高的精度，
+// 这里先做区间规约，再用泰勒级数算到 Decimal 的精度
+
+// 级数的项小于这个值时停止
+const EPSILON: Decimal = Decimal::from_parts(1, 0, 0, false, 28);
+
+// 泰勒级数 Σ x^k / k!，从第 start 项开始，每隔 step 项取一项，交替改变符号（sign = -1）或者不改变（sign = 1）
+fn series(x: Decimal, start: u32, step: u32, sign: Decimal) -> Decimal {
+    let mut term = x.powi(start as i64);
+    for k in 1..=start {
+        term /= Decimal::from(k);
+    }
+    let mut sum = term;
+    let mut k = start;
+    while term.abs() > EPSILON {
+        for _ in 0..step {
+            k += 1;
+            term = term * x / Decimal::from(k);
+        }
+        term *= sign;
+        sum += term;
+    }
+    sum
+}
+
+// e^x = 2^n * e^r，其中 r = x - n * ln(2)，|r| <= ln(2) / 2
+pub fn exp(x: Decimal) -> Option<Decimal> {
+    if x.is_sign_negative() {
+        // 结果小于 Decimal 的最小精度时为 0
+        return Some(exp(-x).map_or(Decimal::ZERO, |y| Decimal::ONE / y));
+    }
+    let ln2 = Decimal::TWO.checked_ln()?;
+    let n = x.checked_div(ln2)?.round();
+    let r = x.checked_sub(n.checked_mul(ln2)?)?;
+    let n = i64::try_from(n).ok()?;
+    Decimal::TWO
+        .checked_powi(n)?
+        .checked_mul(series(r, 0, 1, Decimal::ONE))
+}
+
+// 2π = TWO_PI_HIGH + TWO_PI_LOW * 10^-14。TWO_PI_HIGH 只有 14 位有效数字，圈数不超过 14 位时
+// turns * TWO_PI_HIGH 和 x 减去它都是精确的，TWO_PI_LOW 补上 2π 剩下的 28 位有效数字
+const TWO_PI_HIGH: Decimal = dec!(6.2831853071795);
+const TWO_PI_LOW: Decimal = dec!(8.647692528676655900576839434);
+const MAX_TURNS: Decimal = dec!(99999999999999);
+
+// 把 x 规约到 [-π, π] 附近。圈数太多时 x 的整数部分已经超出了能精确规约的范围，
+// 得到的余数没有意义，返回 None
+fn reduce(x: Decimal) -> Option<Decimal> {
+    let turns = x.checked_div(Decimal::TWO_PI)?.round();
+    if turns.abs() > MAX_TURNS {
+        return None;
+    }
+    let low = turns.checked_mul(TWO_PI_LOW)? / Decimal::from(100_000_000_000_000_i64);
+    x.checked_sub(turns * TWO_PI_HIGH)?.checked_sub(low)
+}
+
+pub fn sin(x: Decimal) -> Option<Decimal> {
+    Some(series(reduce(x)?, 1, 2, Decimal::NEGATIVE_ONE).round_dp(27).normalize())
+}
+
+pub fn cos(x: Decimal) -> Option<Decimal> {
+    Some(series(reduce(x)?, 0, 2, Decimal::NEGATIVE_ONE).round_dp(27).normalize())
+}
+
+pub fn tan(x: Decimal) -> Option<Decimal> {
+    sin(x)?.checked_div(cos(x)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Decimal, b: Decimal) -> bool {
+        (a - b).abs() <= dec!(0.000000000000000000001) * b.abs().max(Decimal::ONE)
+    }
+
+    #[test]
+    fn test_exp() {
+        assert_eq!(exp(dec!(0)), Some(dec!(1)));
+        assert!(close(exp(dec!(1)).unwrap(), Decimal::E));
+        assert!(close(exp(dec!(9)).unwrap(), dec!(8103.083927575384007709996689)));
+        assert!(close(exp(dec!(-9)).unwrap(), dec!(0.0001234098040866795494976367)));
+        assert!(close(exp(dec!(60)).unwrap(), dec!(114200738981568428366295718.3)));
+        assert_eq!(exp(dec!(70)), None);
+        assert_eq!(exp(dec!(-70)), Some(dec!(0)));
+        // x / ln(2) 超出 Decimal 的范围时报告溢出而不是 panic
+        assert_eq!(exp(dec!(60000000000000000000000000000)), None);
+        assert_eq!(exp(dec!(-60000000000000000000000000000)), Some(dec!(0)));
+    }
+
+    #[test]
+    fn test_trigonometric() {
+        assert_eq!(sin(dec!(0)), Some(dec!(0)));
+        assert!(close(sin(dec!(1)).unwrap(), dec!(0.8414709848078965066525023216)));
+        assert!(close(cos(dec!(1)).unwrap(), dec!(0.5403023058681397174009366074)));
+        assert!(close(sin(Decimal::PI / Decimal::TWO).unwrap(), dec!(1)));
+        assert!(close(cos(Decimal::PI).unwrap(), dec!(-1)));
+        assert!(close(sin(dec!(100)).unwrap(), dec!(-0.5063656411097587936565576105)));
+        assert!(close(tan(dec!(1)).unwrap(), dec!(1.5574077246549022305069748075)));
+        // 很大的参数也要精确地规约，超出范围的报告错误而不是返回错误的值
+        assert!(close(sin(dec!(100000000000000)).unwrap(), dec!(-0.2094083074964523026947459958)));
+        assert!(close(cos(dec!(10000000000000)).unwrap(), dec!(0.9573637169008399352820267057)));
+        assert_eq!(sin(dec!(79000000000000000000000000000)), None);
+        assert_eq!(cos(dec!(-79000000000000000000000000000)), None);
+        assert_eq!(tan(dec!(1000000000000000)), None);
+    }
+}
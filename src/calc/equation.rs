@@ -0,0 +1,393 @@
+use std::fmt::Display;
+
+use rust_decimal::{Decimal, dec};
+
+use crate::calc::{ast::Node, context::Context, derivative, function::Function, value::Value};
+
+// 没有给出区间时搜索的默认区间
+pub const DEFAULT_INTERVAL: (Decimal, Decimal) = (dec!(-100), dec!(100));
+// 把区间等分成这么多段，在每一段的端点上求值来寻找变号的位置
+const SAMPLES: usize = 400;
+// 每个根最多迭代的次数
+const MAX_ITERATIONS: usize = 200;
+
+// 求解方程时可能出现的错误
+#[derive(Debug, PartialEq, Clone)]
+pub enum SolveError {
+    // 区间的左端点不小于右端点
+    InvalidInterval(Decimal, Decimal),
+    // 区间太宽，端点之差超出了 Decimal 的范围
+    IntervalTooWide(Decimal, Decimal),
+    // 方程在区间内的任何一点都无法求值，附带第一次求值的错误
+    Undefined(String),
+    // 区间内没有找到根：方程，区间两端
+    NoRoot(String, Decimal, Decimal),
+    // 方程在区间内的每个采样点上都成立，例如 x = x：方程，区间两端
+    Identity(String, Decimal, Decimal),
+    // 迭代次数用完了还没有收敛：方程，最后的近似值，迭代次数
+    NoConvergence(String, Decimal, usize),
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolveError::InvalidInterval(lo, hi) => {
+                write!(f, "Expected a < b in the interval [a, b], found [{lo}, {hi}]")
+            }
+            SolveError::IntervalTooWide(lo, hi) => {
+                write!(f, "The interval [{lo}, {hi}] is too wide to search")
+            }
+            SolveError::Undefined(err) => write!(f, "{err}"),
+            SolveError::NoRoot(eq, lo, hi) => write!(f, "No root of '{eq}' found in [{lo}, {hi}]"),
+            SolveError::Identity(eq, lo, hi) => {
+                write!(f, "Every number in [{lo}, {hi}] is a solution of '{eq}'")
+            }
+            SolveError::NoConvergence(eq, near, iterations) => write!(
+                f,
+                "Solving '{eq}' did not converge after {iterations} iterations near '{near}'"
+            ),
+        }
+    }
+}
+
+// 方程 lhs = rhs 化为 f(x) = lhs - rhs = 0 之后的求值器
+struct Equation<'a> {
+    text: String,
+    var: &'a str,
+    f: Node,
+    // 导数，无法求导的时候使用割线法
+    df: Option<Node>,
+    ctx: &'a mut Context,
+    // 第一次求值失败的错误信息
+    error: Option<String>,
+}
+
+impl Equation<'_> {
+    // 在 x 处求值，定义域以外的点（例如 ln(-1)）返回 None
+    fn eval(&mut self, node_is_df: bool, x: Decimal) -> Option<Decimal> {
+        let node = if node_is_df { self.df.as_ref()? } else { &self.f };
+        let res = self
+            .ctx
+            .scoped(self.var, Value::Num(x), |ctx| node.eval(ctx))
+            .and_then(|val| val.into_num(Function::Solve));
+        match res {
+            Ok(y) => Some(y),
+            Err(err) => {
+                self.error.get_or_insert(err);
+                None
+            }
+        }
+    }
+
+    fn f(&mut self, x: Decimal) -> Option<Decimal> {
+        self.eval(false, x)
+    }
+
+    fn df(&mut self, x: Decimal) -> Option<Decimal> {
+        self.eval(true, x)
+    }
+}
+
+// 收敛的判断标准：区间宽度小于 x 的 10^-20 倍
+fn tolerance(x: Decimal) -> Decimal {
+    Decimal::new(1, 20) * x.abs().max(Decimal::ONE)
+}
+
+// 区间的宽度和中点，端点接近 Decimal 的范围时可能溢出
+fn width(lo: Decimal, hi: Decimal) -> Result<Decimal, SolveError> {
+    hi.checked_sub(lo)
+        .ok_or(SolveError::IntervalTooWide(lo, hi))
+}
+
+fn midpoint(lo: Decimal, hi: Decimal) -> Result<Decimal, SolveError> {
+    lo.checked_add(width(lo, hi)? / Decimal::TWO)
+        .ok_or(SolveError::IntervalTooWide(lo, hi))
+}
+
+fn opposite(a: Decimal, b: Decimal) -> bool {
+    a.is_sign_negative() != b.is_sign_negative()
+}
+
+// 在变号区间 [lo, hi] 内寻找根：优先使用牛顿法，没有导数的时候使用割线法，
+// 新的近似值跑出区间或者区间收缩得太慢的时候退回二分法，保证每一步区间都会缩小
+fn refine(
+    eq: &mut Equation,
+    mut lo: Decimal,
+    mut hi: Decimal,
+    mut f_lo: Decimal,
+    mut f_hi: Decimal,
+) -> Result<Option<Decimal>, SolveError> {
+    let bound = f_lo.abs().max(f_hi.abs());
+    let mut x = if f_lo.abs() < f_hi.abs() { lo } else { hi };
+    let mut f_x = if x == lo { f_lo } else { f_hi };
+    for _ in 0..MAX_ITERATIONS {
+        let before = width(lo, hi)?;
+        if f_x.is_zero() || before <= tolerance(x) {
+            // 在间断点（例如 1/x 的 0 点）两侧也会变号，收敛到这样的点时函数值并不小
+            return Ok((f_x.abs() <= bound).then_some(x));
+        }
+        let newton = eq
+            .df(x)
+            .filter(|d| !d.is_zero())
+            .and_then(|d| f_x.checked_div(d))
+            .and_then(|step| x.checked_sub(step));
+        let secant = if f_hi == f_lo {
+            None
+        } else {
+            before
+                .checked_mul(f_lo)
+                .zip(f_hi.checked_sub(f_lo))
+                .and_then(|(d, df)| d.checked_div(df))
+                .and_then(|d| lo.checked_sub(d))
+        };
+        let mid = midpoint(lo, hi)?;
+        let next = newton
+            .or(secant)
+            .filter(|next| *next > lo && *next < hi)
+            .unwrap_or(mid);
+        let Some(f_next) = eq.f(next) else {
+            // 区间内部有无法求值的点，这不是一个连续函数的根
+            return Ok(None);
+        };
+        if opposite(f_lo, f_next) {
+            (hi, f_hi) = (next, f_next);
+        } else {
+            (lo, f_lo) = (next, f_next);
+        }
+        (x, f_x) = (next, f_next);
+        // 区间收缩得不够快，补一步二分
+        if width(lo, hi)? > before / Decimal::TWO && !f_x.is_zero() {
+            let Some(f_mid) = eq.f(mid) else {
+                return Ok(None);
+            };
+            if mid > lo && mid < hi {
+                if opposite(f_lo, f_mid) {
+                    (hi, f_hi) = (mid, f_mid);
+                } else {
+                    (lo, f_lo) = (mid, f_mid);
+                }
+                if f_mid.abs() < f_x.abs() {
+                    (x, f_x) = (mid, f_mid);
+                }
+            }
+        }
+    }
+    Err(SolveError::NoConvergence(eq.text.clone(), x, MAX_ITERATIONS))
+}
+
+// 从函数值的绝对值的局部极小点出发做牛顿迭代，寻找 x^2 = 0 这种不变号的重根，
+// 不收敛说明这里只是一个极小值而不是根
+fn touch(eq: &mut Equation, lo: Decimal, hi: Decimal, mut x: Decimal) -> Option<Decimal> {
+    for _ in 0..MAX_ITERATIONS {
+        let f_x = eq.f(x)?;
+        if f_x.is_zero() {
+            return Some(x);
+        }
+        let step = f_x.checked_div(eq.df(x)?)?;
+        if step.abs() <= tolerance(x) {
+            return Some(x);
+        }
+        x = x.checked_sub(step).filter(|x| *x >= lo && *x <= hi)?;
+    }
+    None
+}
+
+// xs[first..=last] 上的函数值都是 0。只有一个点时它就是根；连续多个点为零通常是函数值
+// 太小、下溢成了 0（例如 x^100 在 ±0.5 处），这一段只算一个根：从区间端点开始的一段取端点，
+// 否则取中点，并且要求这个点的函数值确实为零
+fn plateau(
+    eq: &mut Equation,
+    xs: &[Decimal],
+    first: usize,
+    last: usize,
+) -> Result<Option<Decimal>, SolveError> {
+    let x = if first == last || first == 0 {
+        xs[first]
+    } else if last == xs.len() - 1 {
+        xs[last]
+    } else {
+        midpoint(xs[first], xs[last])?
+    };
+    Ok(eq.f(x).filter(Decimal::is_zero).map(|_| x))
+}
+
+// 求出方程 eq 在 [lo, hi] 中关于 var 的所有实根，从小到大排列
+pub fn solve(
+    eq: &Node,
+    var: &str,
+    lo: Decimal,
+    hi: Decimal,
+    ctx: &mut Context,
+) -> Result<Vec<Decimal>, SolveError> {
+    if lo >= hi {
+        return Err(SolveError::InvalidInterval(lo, hi));
+    }
+    let Node::Eq(lhs, rhs) = eq else {
+        unreachable!()
+    };
+    let f = Node::Sub(lhs.clone(), rhs.clone());
+    let df = derivative::derivative(&f, var).ok();
+    let mut eq = Equation {
+        text: eq.to_string(),
+        var,
+        f,
+        df,
+        ctx,
+        error: None,
+    };
+
+    let step = width(lo, hi)? / Decimal::from(SAMPLES);
+    let xs = (0..=SAMPLES)
+        .map(|i| match i {
+            SAMPLES => Some(hi),
+            _ => step
+                .checked_mul(Decimal::from(i))
+                .and_then(|offset| lo.checked_add(offset)),
+        })
+        .collect::<Option<Vec<Decimal>>>()
+        .ok_or(SolveError::IntervalTooWide(lo, hi))?;
+    let ys: Vec<Option<Decimal>> = xs.iter().map(|x| eq.f(*x)).collect();
+    if ys.iter().all(Option::is_none) {
+        return Err(SolveError::Undefined(eq.error.unwrap()));
+    }
+    if ys.iter().flatten().all(Decimal::is_zero) {
+        return Err(SolveError::Identity(eq.text, lo, hi));
+    }
+
+    let mut roots = Vec::new();
+    let mut zeros = 0; // 已经处理过的连续为零的采样点
+    for i in 0..=SAMPLES {
+        let Some(y) = ys[i] else {
+            continue;
+        };
+        if y.is_zero() {
+            if i >= zeros {
+                zeros = i + 1;
+                while ys.get(zeros) == Some(&Some(Decimal::ZERO)) {
+                    zeros += 1;
+                }
+                roots.extend(plateau(&mut eq, &xs, i, zeros - 1)?);
+            }
+            continue;
+        }
+        let next = ys.get(i + 1).copied().flatten();
+        let prev = i.checked_sub(1).and_then(|j| ys[j]);
+        match next {
+            Some(next) if !next.is_zero() && opposite(y, next) => {
+                roots.extend(refine(&mut eq, xs[i], xs[i + 1], y, next)?);
+            }
+            _ => {
+                // |f| 的局部极小点，两侧都不变号
+                let is_min = |other: Option<Decimal>| {
+                    other.is_some_and(|other| !opposite(y, other) && y.abs() <= other.abs())
+                };
+                if is_min(prev) && is_min(next) {
+                    roots.extend(touch(&mut eq, xs[i - 1], xs[i + 1], xs[i]));
+                }
+            }
+        }
+    }
+
+    roots.sort();
+    roots.dedup_by(|a, b| (*a - *b).abs() <= tolerance(*a) * Decimal::from(10_000));
+    if roots.is_empty() {
+        return Err(SolveError::NoRoot(eq.text, lo, hi));
+    }
+    Ok(roots.into_iter().map(|x| x.round_dp(24).normalize()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{ast::Ast, parser::Parser};
+
+    fn roots(expr: &str, lo: Decimal, hi: Decimal) -> Result<Vec<Decimal>, SolveError> {
+        let Ast(eq) = Parser::parse(expr).unwrap();
+        solve(&eq, "x", lo, hi, &mut Context::with_seed(0))
+    }
+
+    fn close(actual: &[Decimal], expected: &[Decimal]) -> bool {
+        actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < dec!(0.000000000001))
+    }
+
+    #[test]
+    fn test_simple_roots() {
+        let res = roots("x^2 - 2 = 0", dec!(-10), dec!(10)).unwrap();
+        assert!(close(&res, &[dec!(-1.41421356237309504880), dec!(1.41421356237309504880)]));
+        assert_eq!(roots("2*x + 1 = 4", dec!(-10), dec!(10)), Ok(vec![dec!(1.5)]));
+        // 端点恰好是根
+        assert_eq!(roots("x = 1", dec!(1), dec!(2)), Ok(vec![dec!(1)]));
+    }
+
+    #[test]
+    fn test_multiple_and_double_roots() {
+        let res = roots("(x - 1)*(x - 2)*(x - 3) = 0", dec!(0), dec!(10)).unwrap();
+        assert!(close(&res, &[dec!(1), dec!(2), dec!(3)]));
+        // 不变号的重根
+        let res = roots("(3*x - 1)^2 = 0", dec!(-1), dec!(1)).unwrap();
+        assert!(close(&res, &[dec!(0.33333333333333333333)]));
+        let res = roots("sin(x) = 0", dec!(1), dec!(7)).unwrap();
+        assert!(close(&res, &[dec!(3.14159265358979323846), dec!(6.28318530717958647692)]));
+        // x^100 在 ±0.5 处下溢成 0，连续为零的采样点只算一个根
+        assert_eq!(roots("x^100 = 0", dec!(-100), dec!(100)), Ok(vec![dec!(0)]));
+        assert_eq!(roots("x^101 = 0", dec!(-100), dec!(100)), Ok(vec![dec!(0)]));
+        assert_eq!(roots("x^100 = 0", dec!(0), dec!(1)), Ok(vec![dec!(0)]));
+    }
+
+    #[test]
+    fn test_wide_intervals() {
+        // 区间的宽度接近 Decimal::MAX 时仍然可以搜索
+        let res = roots("x = 1", Decimal::ZERO, Decimal::MAX).unwrap();
+        assert!(close(&res, &[dec!(1)]), "{res:?}");
+        let (lo, hi) = (dec!(-30000000000000000000000000000), dec!(30000000000000000000000000000));
+        let res = roots("x = 1", lo, hi).unwrap();
+        assert!(close(&res, &[dec!(1)]), "{res:?}");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            roots("x^2 + 1 = 0", dec!(-10), dec!(10)),
+            Err(SolveError::NoRoot("x^2 + 1 = 0".to_string(), dec!(-10), dec!(10)))
+        );
+        // 处处成立的方程不是把每个采样点都当成根
+        assert_eq!(
+            roots("x = x", dec!(-10), dec!(10)).map_err(|err| err.to_string()),
+            Err("Every number in [-10, 10] is a solution of 'x = x'".to_string())
+        );
+        assert_eq!(
+            roots("0 = 0", dec!(-10), dec!(10)),
+            Err(SolveError::Identity("0 = 0".to_string(), dec!(-10), dec!(10)))
+        );
+        // 1/x 在 0 两侧变号，但 0 不是根
+        assert_eq!(
+            roots("1/x = 0", dec!(-1), dec!(1)).map_err(|err| err.to_string()),
+            Err("No root of '1/x = 0' found in [-1, 1]".to_string())
+        );
+        assert_eq!(
+            roots("x = y", dec!(-1), dec!(1)),
+            Err(SolveError::Undefined("Unknown variable: 'y'".to_string()))
+        );
+        assert_eq!(
+            roots("x = 0", dec!(1), dec!(1)),
+            Err(SolveError::InvalidInterval(dec!(1), dec!(1)))
+        );
+        // 端点之差超出 Decimal 的范围
+        let (lo, hi) = (dec!(-70000000000000000000000000000), dec!(70000000000000000000000000000));
+        assert_eq!(
+            roots("x = 1", lo, hi).map_err(|err| err.to_string()),
+            Err(format!("The interval [{lo}, {hi}] is too wide to search"))
+        );
+        assert_eq!(
+            roots("x = 1", Decimal::MIN, Decimal::MAX),
+            Err(SolveError::IntervalTooWide(Decimal::MIN, Decimal::MAX))
+        );
+        // 只在区间的一部分有定义
+        let res = roots("ln(x) = 1", dec!(-5), dec!(5)).unwrap();
+        assert!(close(&res, &[dec!(2.71828182845904523536)]));
+    }
+}
@@ -0,0 +1,620 @@
+use std::{fmt::Display, ops::RangeInclusive};
+
+use rust_decimal::{Decimal, MathematicalOps};
+
+use crate::calc::{
+    ast::{Ast, Node},
+    context::Context,
+    derivative, elementary, equation, integral,
+    integer::{self, natural_to_decimal, to_decimal, to_integer, to_natural},
+    linear,
+    matrix::Matrix,
+    polynomial, stats,
+    value::Value,
+};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Function {
+    Transpose,
+    Det,
+    Inv,
+    Solve,
+    Gcd,
+    Lcm,
+    ModPow,
+    ModInv,
+    IsPrime,
+    Factor,
+    Choose,
+    Perm,
+    Multinomial,
+    Rand,
+    RandInt,
+    Normal,
+    Mean,
+    Std,
+    // repeat(n, expr) 把 expr 求值 n 次，返回一行两列的矩阵 [均值, 标准差]，
+    // 第一个元素是均值，第二个是样本标准差（除以 n - 1）；sample(n, expr) 返回所有 n 个结果
+    Repeat,
+    Sample,
+    Sqrt,
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Tan,
+    // diff(expr, x) 返回 expr 对 x 的导数，diff(expr, x, a) 返回导数在 x = a 处的值
+    Diff,
+    // integrate(expr, x, a, b[, tol]) 计算 expr 对 x 从 a 到 b 的定积分
+    Integrate,
+    // 多项式：expand(p) 展开，collect(p, x) 按 x 的幂合并同类项，polydiv(p, d) 返回 [商, 余数]，
+    // roots(p) 返回所有实根。factor 的参数含有变量时分解多项式的因式
+    Expand,
+    Collect,
+    PolyDiv,
+    Roots,
+    // sum 和 prod 在构建 AST 的时候会变成 Node::Sum 和 Node::Prod，不会作为普通函数调用
+    Sum,
+    Prod,
+}
+
+// 内置的常数，变量查找不到的时候使用
+pub fn constant(name: &str) -> Option<Decimal> {
+    match name {
+        "pi" => Some(Decimal::PI),
+        "e" => Some(Decimal::E),
+        _ => None,
+    }
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Function> {
+        use Function::*;
+        match name {
+            "transpose" => Some(Transpose),
+            "det" => Some(Det),
+            "inv" => Some(Inv),
+            "solve" => Some(Solve),
+            "gcd" => Some(Gcd),
+            "lcm" => Some(Lcm),
+            "mod_pow" => Some(ModPow),
+            "mod_inv" => Some(ModInv),
+            "is_prime" => Some(IsPrime),
+            "factor" => Some(Factor),
+            "choose" => Some(Choose),
+            "perm" => Some(Perm),
+            "multinomial" => Some(Multinomial),
+            "rand" => Some(Rand),
+            "randint" => Some(RandInt),
+            "normal" => Some(Normal),
+            "mean" => Some(Mean),
+            "std" => Some(Std),
+            "repeat" => Some(Repeat),
+            "sample" => Some(Sample),
+            "sqrt" => Some(Sqrt),
+            "exp" => Some(Exp),
+            "ln" => Some(Ln),
+            "sin" => Some(Sin),
+            "cos" => Some(Cos),
+            "tan" => Some(Tan),
+            "diff" => Some(Diff),
+            "integrate" => Some(Integrate),
+            "expand" => Some(Expand),
+            "collect" => Some(Collect),
+            "polydiv" => Some(PolyDiv),
+            "roots" => Some(Roots),
+            "sum" => Some(Sum),
+            "prod" => Some(Prod),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use Function::*;
+        match self {
+            Transpose => "transpose",
+            Det => "det",
+            Inv => "inv",
+            Solve => "solve",
+            Gcd => "gcd",
+            Lcm => "lcm",
+            ModPow => "mod_pow",
+            ModInv => "mod_inv",
+            IsPrime => "is_prime",
+            Factor => "factor",
+            Choose => "choose",
+            Perm => "perm",
+            Multinomial => "multinomial",
+            Rand => "rand",
+            RandInt => "randint",
+            Normal => "normal",
+            Mean => "mean",
+            Std => "std",
+            Repeat => "repeat",
+            Sample => "sample",
+            Sqrt => "sqrt",
+            Exp => "exp",
+            Ln => "ln",
+            Sin => "sin",
+            Cos => "cos",
+            Tan => "tan",
+            Diff => "diff",
+            Integrate => "integrate",
+            Expand => "expand",
+            Collect => "collect",
+            PolyDiv => "polydiv",
+            Roots => "roots",
+            Sum => "sum",
+            Prod => "prod",
+        }
+    }
+
+    // 函数接受的参数个数
+    pub fn arity(self) -> RangeInclusive<usize> {
+        use Function::*;
+        match self {
+            Rand => 0..=0,
+            Transpose | Det | Inv | IsPrime | Factor | Mean | Std => 1..=1,
+            Sqrt | Exp | Ln | Sin | Cos | Tan => 1..=1,
+            Expand | Roots => 1..=1,
+            Collect | PolyDiv => 2..=2,
+            ModInv | Choose | Perm | RandInt | Normal | Repeat | Sample => 2..=2,
+            // solve(A, b) 或者 solve(lhs = rhs, x[, a, b])
+            Solve => 2..=4,
+            ModPow => 3..=3,
+            Gcd | Lcm => 2..=usize::MAX,
+            Multinomial => 1..=usize::MAX,
+            Diff => 2..=3,
+            Integrate => 4..=5,
+            Sum | Prod => 4..=4,
+        }
+    }
+
+    // 参数个数的描述，例如 "2" 或者 "1 to 3"
+    pub fn arity_text(self) -> String {
+        let arity = self.arity();
+        if arity.start() == arity.end() {
+            arity.start().to_string()
+        } else if *arity.end() == usize::MAX {
+            format!("at least {}", arity.start())
+        } else {
+            format!("{} to {}", arity.start(), arity.end())
+        }
+    }
+
+    pub fn check_arity(self, count: usize) -> Result<(), String> {
+        if self.arity().contains(&count) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Function '{self}' expects {} argument(s), found {count}",
+                self.arity_text()
+            ))
+        }
+    }
+
+    // 参数需要在函数内部按需求值的函数，例如 repeat 需要对第二个参数求值很多次
+    pub fn is_lazy(self) -> bool {
+        use Function::*;
+        matches!(
+            self,
+            Repeat | Sample | Diff | Integrate | Solve | Factor | Expand | Collect | PolyDiv | Roots
+        )
+    }
+
+    // 只是反复对参数求值的惰性函数，不需要语法树形式的参数，见 call_iterative
+    pub fn is_iterative(self) -> bool {
+        matches!(self, Function::Repeat | Function::Sample | Function::Integrate)
+    }
+
+    // 每次调用结果都可能不同的函数，化简时不能合并或者折叠
+    pub fn is_random(self) -> bool {
+        use Function::*;
+        matches!(self, Rand | RandInt | Normal | Repeat | Sample)
+    }
+
+    // 结果可能是矩阵的函数
+    pub fn returns_matrix(self) -> bool {
+        use Function::*;
+        matches!(
+            self,
+            Transpose | Inv | Solve | Factor | Repeat | Sample | PolyDiv | Roots
+        )
+    }
+
+    // 参数都是整数常数时结果是精确整数的函数，化简时可以直接折叠成常数
+    pub fn is_exact(self) -> bool {
+        use Function::*;
+        matches!(
+            self,
+            Gcd | Lcm | ModPow | ModInv | IsPrime | Choose | Perm | Multinomial
+        )
+    }
+
+    pub fn call_lazy(self, args: &[Node], ctx: &mut Context) -> Result<Value, String> {
+        use Function::*;
+        match self {
+            Repeat | Sample | Integrate => {
+                // 积分变量在构建 AST 的时候已经检查过了
+                let var = match (self, &args[1]) {
+                    (Integrate, Node::Var(var)) => var.as_str(),
+                    _ => "",
+                };
+                self.call_iterative(var, args.len(), &mut |i, ctx| args[i].eval(ctx), ctx)
+            }
+            Diff => {
+                // 第二个参数是变量，这在构建 AST 的时候已经检查过了
+                let Node::Var(var) = &args[1] else {
+                    unreachable!()
+                };
+                let res = derivative::derivative(&args[0], var)?;
+                match args.get(2) {
+                    Some(point) => {
+                        let point = point.eval(ctx)?;
+                        ctx.scoped(var, point, |ctx| res.eval(ctx))
+                    }
+                    None => Ok(Value::Expr(Ast(res))),
+                }
+            }
+            // 方程的参数在构建 AST 的时候已经检查过了
+            Solve if matches!(args[0], Node::Eq(..)) => {
+                let Node::Var(var) = &args[1] else {
+                    unreachable!()
+                };
+                let (lo, hi) = match args {
+                    [_, _, lo, hi] => (lo.eval(ctx)?.into_num(self)?, hi.eval(ctx)?.into_num(self)?),
+                    _ => equation::DEFAULT_INTERVAL,
+                };
+                let roots = equation::solve(&args[0], var, lo, hi, ctx).map_err(|err| err.to_string())?;
+                if let [root] = roots[..] {
+                    Ok(Value::Num(root))
+                } else {
+                    Ok(Value::Matrix(Matrix::from_rows(vec![roots])?))
+                }
+            }
+            // 方程组的参数在构建 AST 的时候已经检查过了
+            Solve if linear::is_system(&args[0]) => {
+                let (Node::Matrix(eqs), Node::Matrix(vars)) = (&args[0], &args[1]) else {
+                    unreachable!()
+                };
+                let vars: Vec<String> = vars
+                    .iter()
+                    .map(|var| match var {
+                        Node::Var(var) => var.clone(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                symbolic(linear::solve(eqs, &vars, ctx)?)
+            }
+            // 线性方程组 solve(A, b)，参数按普通函数的方式求值
+            Solve => {
+                let args = args.iter().map(|arg| arg.eval(ctx)).collect::<Result<_, _>>()?;
+                self.call(args, ctx)
+            }
+            // 参数含有变量时分解多项式，否则分解整数
+            Factor => {
+                let arg = polynomial::bind(&args[0], ctx);
+                if polynomial::variables(&arg).is_empty() {
+                    let arg = arg.eval(ctx)?;
+                    self.call(vec![arg], ctx)
+                } else {
+                    symbolic(polynomial::factor(&arg)?)
+                }
+            }
+            Expand => symbolic(polynomial::expand(&polynomial::bind(&args[0], ctx))?),
+            Collect => {
+                // 第二个参数是变量，这在构建 AST 的时候已经检查过了
+                let Node::Var(var) = &args[1] else {
+                    unreachable!()
+                };
+                symbolic(polynomial::collect(&polynomial::bind(&args[0], ctx), var)?)
+            }
+            PolyDiv => {
+                let p = polynomial::bind(&args[0], ctx);
+                let d = polynomial::bind(&args[1], ctx);
+                let (quot, rem) = polynomial::divide(&p, &d)?;
+                symbolic(Node::Matrix(vec![quot, rem]))
+            }
+            Roots => {
+                let p = polynomial::bind(&args[0], ctx);
+                let roots = polynomial::roots(&p, ctx)?;
+                match &roots[..] {
+                    [] => Err(format!("Polynomial '{p}' has no real roots")),
+                    [_] => symbolic(roots.into_iter().next().unwrap()),
+                    _ => symbolic(Node::Matrix(roots)),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // repeat、sample 和 integrate 的实现。eval(i, ctx) 对第 i 个参数求值，Box<Node> 的树和
+    // arena 中的树共用这一份实现，arena 中的参数直接按节点求值，不需要先转换成 Box<Node>。
+    // var 是 integrate 的积分变量
+    pub fn call_iterative(
+        self,
+        var: &str,
+        count: usize,
+        eval: &mut dyn FnMut(usize, &mut Context) -> Result<Value, String>,
+        ctx: &mut Context,
+    ) -> Result<Value, String> {
+        use Function::*;
+        match self {
+            Repeat | Sample => {
+                let n = to_natural(self, eval(0, ctx)?)?;
+                if n == 0 {
+                    return Err(format!("Function '{self}' expects at least one repetition"));
+                }
+                ctx.use_iterations(n)?;
+                let mut samples = Vec::new();
+                for _ in 0..n {
+                    samples.push(eval(1, ctx)?.into_num(self)?);
+                }
+                if self == Sample {
+                    Ok(Value::Matrix(Matrix::from_rows(vec![samples])?))
+                } else {
+                    // 返回 [均值, 标准差]
+                    let row = vec![stats::mean(&samples)?, stats::std(&samples)?];
+                    Ok(Value::Matrix(Matrix::from_rows(vec![row])?))
+                }
+            }
+            Integrate => {
+                let a = eval(2, ctx)?.into_num(self)?;
+                let b = eval(3, ctx)?.into_num(self)?;
+                let tol = match count {
+                    5 => eval(4, ctx)?.into_num(self)?,
+                    _ => integral::DEFAULT_TOLERANCE,
+                };
+                let mut body = |ctx: &mut Context| eval(0, ctx);
+                let (value, error) = integral::integrate(&mut body, var, a, b, tol, ctx)?;
+                Ok(Value::Approx(value, error))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn call(self, args: Vec<Value>, ctx: &mut Context) -> Result<Value, String> {
+        use Function::*;
+        let mut args = args.into_iter();
+        // 参数个数已经在构建 AST 的时候检查过了
+        let mut arg = || args.next().unwrap();
+        let mut int = || to_integer(self, arg());
+        match self {
+            Transpose => Ok(Value::Matrix(arg().into_matrix(self)?.transpose())),
+            Det => Ok(Value::Num(arg().into_matrix(self)?.det()?)),
+            Inv => Ok(Value::Matrix(arg().into_matrix(self)?.inverse()?)),
+            Solve => {
+                let a = arg().into_matrix(self)?;
+                let b = arg().into_matrix(self)?;
+                Ok(Value::Matrix(a.solve(&b)?))
+            }
+            Gcd | Lcm => {
+                let mut res = int()?;
+                for num in args {
+                    let num = to_integer(self, num)?;
+                    res = if self == Gcd {
+                        integer::gcd(res, num)
+                    } else {
+                        integer::lcm(res, num)?
+                    };
+                }
+                Ok(Value::Num(to_decimal(res)?))
+            }
+            ModPow => {
+                let (base, exp, m) = (int()?, int()?, int()?);
+                Ok(Value::Num(to_decimal(integer::mod_pow(base, exp, m)?)?))
+            }
+            ModInv => {
+                let (a, m) = (int()?, int()?);
+                Ok(Value::Num(to_decimal(integer::mod_inv(a, m)?)?))
+            }
+            // 没有布尔类型，素数返回 1，否则返回 0
+            IsPrime => Ok(Value::Num(if integer::is_prime(int()?) {
+                Decimal::ONE
+            } else {
+                Decimal::ZERO
+            })),
+            Factor => {
+                let factors = integer::factor(int()?)?;
+                let row = factors
+                    .into_iter()
+                    .map(to_decimal)
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Matrix(Matrix::from_rows(vec![row])?))
+            }
+            Choose | Perm => {
+                let (n, k) = (to_natural(self, arg())?, to_natural(self, arg())?);
+                let res = if self == Choose {
+                    integer::choose(n, k)?
+                } else {
+                    integer::perm(n, k)?
+                };
+                Ok(Value::Num(natural_to_decimal(res)?))
+            }
+            Multinomial => {
+                let ks = args
+                    .map(|k| to_natural(self, k))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Num(natural_to_decimal(integer::multinomial(&ks)?)?))
+            }
+            Rand => Ok(Value::Num(ctx.rng.unit())),
+            RandInt => {
+                let (a, b) = (int()?, int()?);
+                Ok(Value::Num(stats::randint(&mut ctx.rng, a, b)?))
+            }
+            Normal => {
+                let (mu, sigma) = (arg().into_num(self)?, arg().into_num(self)?);
+                Ok(Value::Num(stats::normal(&mut ctx.rng, mu, sigma)?))
+            }
+            Mean | Std => {
+                let mat = match arg().exact() {
+                    Value::Num(num) => Matrix::from_rows(vec![vec![num]])?,
+                    val => val.into_matrix(self)?,
+                };
+                let res = if self == Mean {
+                    stats::mean(mat.values())?
+                } else {
+                    stats::std(mat.values())?
+                };
+                Ok(Value::Num(res))
+            }
+            Sqrt | Exp | Ln | Sin | Cos | Tan => {
+                let x = arg().into_num(self)?;
+                // -0 带有负号，Decimal::sqrt 会把它当成负数，先换成 0
+                let x = if x.is_zero() { Decimal::ZERO } else { x };
+                let res = match self {
+                    Sqrt if x.is_sign_negative() => None,
+                    Sqrt => x.sqrt(),
+                    Exp => elementary::exp(x),
+                    Ln if x <= Decimal::ZERO => None,
+                    Ln => x.checked_ln(),
+                    Sin => elementary::sin(x),
+                    Cos => elementary::cos(x),
+                    _ => elementary::tan(x),
+                };
+                res.map(Value::Num)
+                    .ok_or_else(|| format!("Function '{self}' is undefined or overflows at '{x}'"))
+            }
+            Repeat | Sample | Diff | Integrate | Expand | Collect | PolyDiv | Roots | Sum | Prod => {
+                unreachable!()
+            }
+        }
+    }
+}
+
+// 符号计算的结果：数和数的列表按数值返回，其他的按表达式返回
+fn symbolic(mut node: Node) -> Result<Value, String> {
+    match &mut node {
+        Node::Num(num) => Ok(Value::Num(*num)),
+        Node::Matrix(elems) if elems.iter().all(|elem| matches!(elem, Node::Num(_))) => {
+            Value::list(std::mem::take(elems).into_iter().map(symbolic).collect::<Result<_, _>>()?)
+        }
+        _ => Ok(Value::Expr(Ast(node))),
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn call(func: Function, args: Vec<Value>) -> Result<Value, String> {
+        func.call(args, &mut Context::with_seed(0))
+    }
+
+    #[test]
+    fn test_integer_functions() {
+        let num = |n: Decimal| Value::Num(n);
+        assert_eq!(
+            call(
+                Function::Gcd,
+                vec![num(dec!(12)), num(dec!(18)), num(dec!(27))]
+            ),
+            Ok(num(dec!(3)))
+        );
+        assert_eq!(
+            call(Function::Lcm, vec![num(dec!(4)), num(dec!(6.0))]),
+            Ok(num(dec!(12)))
+        );
+        assert_eq!(
+            call(Function::IsPrime, vec![num(dec!(97))]),
+            Ok(num(dec!(1)))
+        );
+        assert_eq!(
+            call(Function::Factor, vec![num(dec!(12))]).map(|res| res.to_string()),
+            Ok("[2, 2, 3]".to_string())
+        );
+        assert_eq!(
+            call(Function::Gcd, vec![num(dec!(1.5)), num(dec!(3))]),
+            Err("Function 'gcd' expects integer arguments, found '1.5'".to_string())
+        );
+        assert_eq!(
+            call(
+                Function::ModInv,
+                vec![Value::Matrix(Matrix::identity(2)), num(dec!(3))]
+            ),
+            Err("Function 'mod_inv' expects a number, found 2x2 matrix".to_string())
+        );
+    }
+
+    #[test]
+    fn test_combinatoric_functions() {
+        let num = |n: Decimal| Value::Num(n);
+        assert_eq!(
+            call(Function::Choose, vec![num(dec!(5)), num(dec!(2))]),
+            Ok(num(dec!(10)))
+        );
+        assert_eq!(
+            call(Function::Perm, vec![num(dec!(5)), num(dec!(2))]),
+            Ok(num(dec!(20)))
+        );
+        assert_eq!(
+            call(
+                Function::Multinomial,
+                vec![num(dec!(2)), num(dec!(1)), num(dec!(1))]
+            ),
+            Ok(num(dec!(12)))
+        );
+        assert_eq!(
+            call(Function::Choose, vec![num(dec!(-1)), num(dec!(2))]),
+            Err("Function 'choose' expects non-negative arguments, found '-1'".to_string())
+        );
+        // C(100, 50) 超出了 Decimal 的范围
+        assert_eq!(
+            call(Function::Choose, vec![num(dec!(100)), num(dec!(50))]),
+            Err("Result is out of range: '100891344545564193334812497256'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sqrt_of_zero() {
+        let num = |n: Decimal| Value::Num(n);
+        // -0 和 0 一样，开平方得到 0
+        for zero in [Decimal::ZERO, -Decimal::ZERO, dec!(-0.00)] {
+            assert_eq!(call(Function::Sqrt, vec![num(zero)]), Ok(num(Decimal::ZERO)));
+        }
+        assert_eq!(
+            call(Function::Sqrt, vec![num(dec!(-0.01))]),
+            Err("Function 'sqrt' is undefined or overflows at '-0.01'".to_string())
+        );
+        let ast = crate::calc::parser::Parser::parse("sqrt(-0) + sqrt(0 * -1)").unwrap();
+        assert_eq!(ast.eval(&mut Context::with_seed(0)), Ok(num(Decimal::ZERO)));
+    }
+
+    #[test]
+    fn test_random_functions() {
+        use crate::calc::parser::Parser;
+
+        let run = |expr: &str, seed: u64| {
+            Parser::parse(expr)
+                .unwrap()
+                .eval(&mut Context::with_seed(seed))
+        };
+        // 相同的种子得到相同的结果
+        assert_eq!(run("sample(10, rand())", 1), run("sample(10, rand())", 1));
+        assert_ne!(run("sample(10, rand())", 1), run("sample(10, rand())", 2));
+
+        let Ok(Value::Matrix(dice)) = run("sample(100, randint(1, 6))", 3) else {
+            panic!("sample should return a row vector");
+        };
+        assert!(dice.values().iter().all(|x| *x >= dec!(1) && *x <= dec!(6)));
+
+        let Ok(Value::Matrix(stats)) = run("repeat(2000, normal(10, 2))", 4) else {
+            panic!("repeat should return [mean, std]");
+        };
+        assert!((stats.get(0, 0) - dec!(10)).abs() < dec!(0.2));
+        assert!((stats.get(0, 1) - dec!(2)).abs() < dec!(0.2));
+
+        assert_eq!(run("mean([1, 2, 3, 6])", 0), Ok(Value::Num(dec!(3))));
+        assert_eq!(
+            run("repeat(0, 1)", 0),
+            Err("Function 'repeat' expects at least one repetition".to_string())
+        );
+    }
+}
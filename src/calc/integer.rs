@@ -0,0 +1,336 @@
+use rust_decimal::Decimal;
+
+use crate::calc::{function::Function, value::Value};
+
+// 把参数转换为整数，非整数或者矩阵都是类型错误
+pub fn to_integer(func: Function, value: Value) -> Result<i128, String> {
+    let num = value.into_num(func)?;
+    let num = num.normalize();
+    if num.scale() != 0 {
+        return Err(format!(
+            "Function '{func}' expects integer arguments, found '{num}'"
+        ));
+    }
+    Ok(num.mantissa())
+}
+
+// 把参数转换为非负整数
+pub fn to_natural(func: Function, value: Value) -> Result<u128, String> {
+    let num = to_integer(func, value)?;
+    u128::try_from(num)
+        .map_err(|_| format!("Function '{func}' expects non-negative arguments, found '{num}'"))
+}
+
+pub fn to_decimal(num: i128) -> Result<Decimal, String> {
+    Decimal::try_from_i128_with_scale(num, 0).map_err(|_| format!("Result is out of range: '{num}'"))
+}
+
+pub fn natural_to_decimal(num: u128) -> Result<Decimal, String> {
+    i128::try_from(num)
+        .map_err(|_| format!("Result is out of range: '{num}'"))
+        .and_then(to_decimal)
+}
+
+pub fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+pub fn lcm(a: i128, b: i128) -> Result<i128, String> {
+    if a == 0 || b == 0 {
+        return Ok(0);
+    }
+    (a.abs() / gcd(a, b))
+        .checked_mul(b.abs())
+        .ok_or_else(|| format!("Integer overflow when calculating lcm({a}, {b})"))
+}
+
+// (a * b) % m，Decimal 能表示的整数不超过 96 位，所以模数小于 2^64 时可以直接用 u128 相乘，
+// 否则用倍加的方式避免溢出
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    if m <= u64::MAX as u128 {
+        return a * b % m;
+    }
+    let (mut a, mut b, mut res) = (a % m, b, 0u128);
+    while b > 0 {
+        if b & 1 == 1 {
+            res = (res + a) % m;
+        }
+        a = (a << 1) % m;
+        b >>= 1;
+    }
+    res
+}
+
+fn pow_mod(base: u128, mut exp: u128, m: u128) -> u128 {
+    let (mut base, mut res) = (base % m, 1 % m);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res = mul_mod(res, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    res
+}
+
+// 把整数规约到 [0, m) 区间
+fn rem(a: i128, m: i128) -> u128 {
+    a.rem_euclid(m) as u128
+}
+
+// 模逆元，使用扩展欧几里得算法
+pub fn mod_inv(a: i128, m: i128) -> Result<i128, String> {
+    if m <= 0 {
+        return Err(format!("Modulus must be positive, found '{m}'"));
+    }
+    let (mut old_r, mut r) = (a.rem_euclid(m), m);
+    let (mut old_s, mut s) = (1i128, 0i128);
+    while r != 0 {
+        let q = old_r / r;
+        (old_r, r) = (r, old_r - q * r);
+        (old_s, s) = (s, old_s - q * s);
+    }
+    if old_r != 1 {
+        return Err(format!("'{a}' has no inverse modulo '{m}'"));
+    }
+    Ok(old_s.rem_euclid(m))
+}
+
+// 模幂，负指数先求逆元
+pub fn mod_pow(base: i128, exp: i128, m: i128) -> Result<i128, String> {
+    if m <= 0 {
+        return Err(format!("Modulus must be positive, found '{m}'"));
+    }
+    let base = if exp < 0 { mod_inv(base, m)? } else { base };
+    Ok(pow_mod(rem(base, m), exp.unsigned_abs(), m as u128) as i128)
+}
+
+// 前 20 个素数，作为 Miller-Rabin 测试的底数。对于小于 3.3 * 10^24 的数，前 13 个底数已经是确定性的
+const SMALL_PRIMES: [u128; 20] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+pub fn is_prime(n: i128) -> bool {
+    if n < 2 {
+        return false;
+    }
+    let n = n as u128;
+    for p in SMALL_PRIMES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+    // n - 1 = d * 2^s
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    'witness: for a in SMALL_PRIMES {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+fn gcd_u(a: u128, b: u128) -> u128 {
+    gcd(a as i128, b as i128) as u128
+}
+
+// Pollard rho 算法，找到合数 n 的一个非平凡因子
+fn pollard_rho(n: u128) -> u128 {
+    let mut c = 1;
+    loop {
+        let f = |x: u128| (mul_mod(x, x, n) + c) % n;
+        let (mut x, mut y, mut d) = (2, 2, 1);
+        while d == 1 {
+            x = f(x);
+            y = f(f(y));
+            d = gcd_u(x.abs_diff(y), n);
+        }
+        if d != n {
+            return d;
+        }
+        // 这一轮失败了，换一个多项式重新开始
+        c += 1;
+    }
+}
+
+fn factor_into(n: u128, res: &mut Vec<u128>) {
+    if n == 1 {
+        return;
+    }
+    if is_prime(n as i128) {
+        res.push(n);
+        return;
+    }
+    let d = pollard_rho(n);
+    factor_into(d, res);
+    factor_into(n / d, res);
+}
+
+// 素因数分解，从小到大返回所有素因子（重复的因子会重复出现），负数会带上因子 -1，1 的分解结果是 [1]
+pub fn factor(n: i128) -> Result<Vec<i128>, String> {
+    if n == 0 {
+        return Err("Cannot factor 0".to_string());
+    }
+    let mut res = Vec::new();
+    if n < 0 {
+        res.push(-1);
+    }
+    let mut n = n.unsigned_abs();
+    // 先用小素数试除，剩下的部分再用 Pollard rho 分解
+    for p in SMALL_PRIMES {
+        while n.is_multiple_of(p) {
+            res.push(p as i128);
+            n /= p;
+        }
+    }
+    let mut large = Vec::new();
+    factor_into(n, &mut large);
+    large.sort();
+    res.extend(large.into_iter().map(|p| p as i128));
+    if res.is_empty() {
+        res.push(1);
+    }
+    Ok(res)
+}
+
+fn overflow(func: &str) -> String {
+    format!("Integer overflow when calculating {func}")
+}
+
+// 组合数 C(n, k)，逐项相乘再相除，每一步先约去公因子，中间结果不会超过最终结果太多
+pub fn choose(n: u128, k: u128) -> Result<u128, String> {
+    if k > n {
+        return Ok(0);
+    }
+    let k = k.min(n - k);
+    let mut res: u128 = 1;
+    for i in 0..k {
+        // res * (n - i) / (i + 1) 一定是整数，先约去 res 和 i + 1 的公因子，剩下的部分能整除 n - i
+        let g = gcd_u(res, i + 1);
+        let factor = (n - i) / ((i + 1) / g);
+        res = (res / g)
+            .checked_mul(factor)
+            .ok_or_else(|| overflow("choose"))?;
+    }
+    Ok(res)
+}
+
+// 排列数 P(n, k) = n * (n - 1) * ... * (n - k + 1)
+pub fn perm(n: u128, k: u128) -> Result<u128, String> {
+    if k > n {
+        return Ok(0);
+    }
+    (n - k + 1..=n).try_fold(1u128, |res, x| {
+        res.checked_mul(x).ok_or_else(|| overflow("perm"))
+    })
+}
+
+// 多项式系数 (k1 + k2 + ... + km)! / (k1! * k2! * ... * km!)，按 C(k1 + k2, k2) * C(k1 + k2 + k3, k3) ... 计算
+pub fn multinomial(ks: &[u128]) -> Result<u128, String> {
+    let mut total: u128 = 0;
+    let mut res: u128 = 1;
+    for k in ks {
+        total = total
+            .checked_add(*k)
+            .ok_or_else(|| overflow("multinomial"))?;
+        res = res
+            .checked_mul(choose(total, *k)?)
+            .ok_or_else(|| overflow("multinomial"))?;
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gcd_lcm() {
+        assert_eq!(gcd(12, 18), 6);
+        assert_eq!(gcd(-12, 18), 6);
+        assert_eq!(gcd(0, 5), 5);
+        assert_eq!(lcm(4, 6), Ok(12));
+        assert_eq!(lcm(-4, 6), Ok(12));
+        assert_eq!(lcm(0, 6), Ok(0));
+    }
+
+    #[test]
+    fn test_modular() {
+        assert_eq!(mod_pow(4, 13, 497), Ok(445));
+        assert_eq!(mod_pow(-2, 3, 5), Ok(2));
+        assert_eq!(mod_pow(3, -1, 7), Ok(5));
+        assert_eq!(mod_inv(3, 7), Ok(5));
+        assert_eq!(mod_inv(-3, 7), Ok(2));
+        assert_eq!(
+            mod_inv(2, 4),
+            Err("'2' has no inverse modulo '4'".to_string())
+        );
+        assert_eq!(
+            mod_pow(2, 3, 0),
+            Err("Modulus must be positive, found '0'".to_string())
+        );
+        // 模数超过 2^64 的情况
+        let m = 79228162514264337593543950319; // 小于 2^96 的最大素数
+        assert_eq!(mod_pow(3, m - 1, m), Ok(1));
+    }
+
+    #[test]
+    fn test_is_prime() {
+        let primes: Vec<i128> = (0..50).filter(|n| is_prime(*n)).collect();
+        assert_eq!(
+            primes,
+            vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47]
+        );
+        assert!(is_prime(1_000_000_007));
+        assert!(!is_prime(561)); // Carmichael 数
+        assert!(!is_prime(-7));
+        assert!(is_prime(79228162514264337593543950319));
+        assert!(!is_prime(1_000_000_007 * 998_244_353));
+    }
+
+    #[test]
+    fn test_factor() {
+        assert_eq!(factor(360), Ok(vec![2, 2, 2, 3, 3, 5]));
+        assert_eq!(factor(-15), Ok(vec![-1, 3, 5]));
+        assert_eq!(factor(1), Ok(vec![1]));
+        assert_eq!(factor(-1), Ok(vec![-1]));
+        assert_eq!(factor(97), Ok(vec![97]));
+        assert_eq!(
+            factor(1_000_000_007 * 998_244_353),
+            Ok(vec![998_244_353, 1_000_000_007])
+        );
+        assert_eq!(factor(0), Err("Cannot factor 0".to_string()));
+    }
+
+    #[test]
+    fn test_combinatorics() {
+        assert_eq!(choose(5, 2), Ok(10));
+        assert_eq!(choose(5, 0), Ok(1));
+        assert_eq!(choose(5, 6), Ok(0));
+        assert_eq!(choose(100, 50), Ok(100891344545564193334812497256));
+        // 中间结果 67! 远远超出范围，但结果不会
+        assert_eq!(choose(67, 33), Ok(14226520737620288370));
+        assert_eq!(perm(5, 2), Ok(20));
+        assert_eq!(perm(5, 0), Ok(1));
+        assert_eq!(perm(5, 6), Ok(0));
+        assert_eq!(
+            perm(200, 100),
+            Err("Integer overflow when calculating perm".to_string())
+        );
+        assert_eq!(multinomial(&[2, 1, 1]), Ok(12));
+        assert_eq!(multinomial(&[3]), Ok(1));
+    }
+}
@@ -0,0 +1,167 @@
+use rust_decimal::{Decimal, dec};
+
+use crate::calc::{
+    context::Context,
+    function::Function,
+    token::Operator,
+    value::{Value, checked},
+};
+
+// 没有指定容差时使用的默认容差
+pub const DEFAULT_TOLERANCE: Decimal = dec!(0.0000000001);
+// 最大的二分深度，到达这个深度的区间不再细分，误差估计会如实反映结果的精度
+const MAX_DEPTH: usize = 40;
+
+// 对被积函数求值，var 已经绑定到自变量的值。Box<Node> 的树和 arena 中的树都可以直接求值
+pub type Body<'a> = &'a mut dyn FnMut(&mut Context) -> Result<Value, String>;
+
+// 被积函数的求值器，每一次求值算作一次迭代，和其它的循环共用上下文中迭代次数的限制
+struct Integrand<'a> {
+    body: Body<'a>,
+    var: &'a str,
+    ctx: &'a mut Context,
+}
+
+impl Integrand<'_> {
+    fn eval(&mut self, x: Decimal) -> Result<Decimal, String> {
+        self.ctx.use_iterations(1)?;
+        self.ctx
+            .scoped(self.var, Value::Num(x), |ctx| (self.body)(ctx))?
+            .into_num(Function::Integrate)
+    }
+}
+
+fn add(a: Decimal, b: Decimal) -> Result<Decimal, String> {
+    checked(a.checked_add(b), Operator::Add)
+}
+
+fn sub(a: Decimal, b: Decimal) -> Result<Decimal, String> {
+    checked(a.checked_sub(b), Operator::Sub)
+}
+
+fn mul(a: Decimal, b: Decimal) -> Result<Decimal, String> {
+    checked(a.checked_mul(b), Operator::Mul)
+}
+
+// a 和 b 的中点，写成 a + (b - a) / 2，端点很大的时候和其它的运算一样溢出时报错
+fn midpoint(a: Decimal, b: Decimal) -> Result<Decimal, String> {
+    add(a, sub(b, a)? / Decimal::TWO)
+}
+
+// Simpson 公式：(b - a) / 6 * (f(a) + 4f(m) + f(b))
+fn simpson(width: Decimal, fa: Decimal, fm: Decimal, fb: Decimal) -> Result<Decimal, String> {
+    let sum = add(add(fa, mul(Decimal::from(4), fm)?)?, fb)?;
+    mul(width / Decimal::from(6), sum)
+}
+
+// 自适应 Simpson 积分：把区间分成两半分别计算，两半之和与整体的差小于 15 倍容差时认为收敛，
+// 否则两半各自以一半的容差继续细分。返回积分值和误差估计
+#[allow(clippy::too_many_arguments)]
+fn adapt(
+    f: &mut Integrand,
+    a: Decimal,
+    b: Decimal,
+    fa: Decimal,
+    fm: Decimal,
+    fb: Decimal,
+    whole: Decimal,
+    tol: Decimal,
+    depth: usize,
+) -> Result<(Decimal, Decimal), String> {
+    let m = midpoint(a, b)?;
+    let (lm, rm) = (midpoint(a, m)?, midpoint(m, b)?);
+    let (flm, frm) = (f.eval(lm)?, f.eval(rm)?);
+    let half = sub(b, a)? / Decimal::TWO;
+    let left = simpson(half, fa, flm, fm)?;
+    let right = simpson(half, fm, frm, fb)?;
+    let both = add(left, right)?;
+    let delta = sub(both, whole)?;
+    if depth >= MAX_DEPTH || delta.abs() <= mul(Decimal::from(15), tol)? {
+        // Richardson 外推修正，误差估计为 |delta| / 15
+        let fifteen = Decimal::from(15);
+        return Ok((add(both, delta / fifteen)?, delta.abs() / fifteen));
+    }
+    let tol = tol / Decimal::TWO;
+    let (lv, le) = adapt(f, a, m, fa, flm, fm, left, tol, depth + 1)?;
+    let (rv, re) = adapt(f, m, b, fm, frm, fb, right, tol, depth + 1)?;
+    Ok((add(lv, rv)?, add(le, re)?))
+}
+
+// 计算 body 对 var 从 a 到 b 的定积分，返回积分值和误差估计，a > b 时结果取反
+pub fn integrate(
+    body: Body,
+    var: &str,
+    a: Decimal,
+    b: Decimal,
+    tol: Decimal,
+    ctx: &mut Context,
+) -> Result<(Decimal, Decimal), String> {
+    if tol <= Decimal::ZERO {
+        return Err(format!("Tolerance must be positive, found '{tol}'"));
+    }
+    if a == b {
+        return Ok((Decimal::ZERO, Decimal::ZERO));
+    }
+    if a > b {
+        let (value, error) = integrate(body, var, b, a, tol, ctx)?;
+        return Ok((-value, error));
+    }
+    let mut f = Integrand { body, var, ctx };
+    let m = midpoint(a, b)?;
+    let (fa, fm, fb) = (f.eval(a)?, f.eval(m)?, f.eval(b)?);
+    let whole = simpson(sub(b, a)?, fa, fm, fb)?;
+    let (value, error) = adapt(&mut f, a, b, fa, fm, fb, whole, tol, 0)?;
+    Ok((value.round_dp(20).normalize(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{ast::Ast, parser::Parser};
+
+    fn integral(expr: &str, a: Decimal, b: Decimal) -> Result<(Decimal, Decimal), String> {
+        let Ast(body) = Parser::parse(expr)?;
+        integrate(&mut |ctx| body.eval(ctx), "x", a, b, DEFAULT_TOLERANCE, &mut Context::with_seed(0))
+    }
+
+    #[test]
+    fn test_integrals() {
+        // 三次以内的多项式 Simpson 公式是精确的
+        assert_eq!(integral("x^2", dec!(0), dec!(3)), Ok((dec!(9), dec!(0))));
+        let (value, error) = integral("sin(x)", dec!(0), Decimal::PI).unwrap();
+        assert!((value - dec!(2)).abs() < dec!(0.0000000001));
+        assert!(error < dec!(0.0000000001));
+        let (value, _) = integral("exp(-x^2)", dec!(-3), dec!(3)).unwrap();
+        assert!((value - dec!(1.7724146965190424678)).abs() < dec!(0.0000000001));
+        // 交换上下限结果取反
+        let (value, _) = integral("1/x", dec!(2), dec!(1)).unwrap();
+        assert!((value + dec!(0.69314718055994530942)).abs() < dec!(0.0000000001));
+        assert_eq!(integral("x", dec!(1), dec!(1)), Ok((dec!(0), dec!(0))));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            integral("ln(x)", dec!(0), dec!(1)),
+            Err("Function 'ln' is undefined or overflows at '0'".to_string())
+        );
+        let Ast(body) = Parser::parse("x").unwrap();
+        assert_eq!(
+            integrate(&mut |ctx| body.eval(ctx), "x", dec!(0), dec!(1), dec!(0), &mut Context::with_seed(0)),
+            Err("Tolerance must be positive, found '0'".to_string())
+        );
+        // 端点接近 Decimal 的范围时溢出报错而不是 panic
+        let (a, b) = (dec!(50000000000000000000000000000), dec!(60000000000000000000000000000));
+        assert_eq!(integral("x", a, b), Err("Overflow when calculating '*'".to_string()));
+        assert_eq!(integral("1", -a, b), Err("Overflow when calculating '-'".to_string()));
+        // 不可积的尖峰会耗尽迭代次数
+        let mut ctx = Context::with_seed(0);
+        ctx.max_iterations = 1000;
+        let Ast(body) = Parser::parse("1/sqrt(x)").unwrap();
+        assert!(
+            integrate(&mut |ctx| body.eval(ctx), "x", dec!(0.0000001), dec!(1), dec!(0.000000000000001), &mut ctx)
+                .unwrap_err()
+                .starts_with("Iteration count 1001 exceeds")
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use rust_decimal::Decimal;
+
+use crate::calc::{
+    ast::Node,
+    context::{Context, deep},
+    derivative::{contains_var, free_vars, substitute},
+    function::Function,
+    integer::{self, to_decimal},
+    polynomial::{self, Polynomial},
+    simplify::simplify,
+    token::Operator,
+    value::{checked, checked_div, exact_div},
+};
+
+fn mul(lhs: Decimal, rhs: Decimal) -> Result<Decimal, String> {
+    checked(lhs.checked_mul(rhs), Operator::Mul)
+}
+
+fn add(lhs: Decimal, rhs: Decimal) -> Result<Decimal, String> {
+    checked(lhs.checked_add(rhs), Operator::Add)
+}
+
+fn sub(lhs: Decimal, rhs: Decimal) -> Result<Decimal, String> {
+    checked(lhs.checked_sub(rhs), Operator::Sub)
+}
+
+// 方程组 solve([eq1, eq2, ...], [x, y, ...]) 的第一个参数：由方程组成的列表
+pub fn is_system(node: &Node) -> bool {
+    matches!(node, Node::Matrix(elems) if elems.iter().any(|elem| matches!(elem, Node::Eq(..))))
+}
+
+// 系数的精确值 num / den，以及它是否精确。x/3 的系数按 1/3 计算，不会先求值成 0.333…；
+// 四则运算以外的部分（例如 sqrt(2)）只能直接求值，这时结果不再精确
+fn ratio(node: &Node, ctx: &mut Context) -> Result<(Decimal, Decimal, bool), String> {
+    deep(|| reduced_ratio(node, ctx))
+}
+
+fn reduced_ratio(node: &Node, ctx: &mut Context) -> Result<(Decimal, Decimal, bool), String> {
+    use Node::*;
+    let (num, den, exact) = match node {
+        Num(num) => (*num, Decimal::ONE, true),
+        Neg(val) => {
+            let (p, q, exact) = ratio(val, ctx)?;
+            (-p, q, exact)
+        }
+        Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) => {
+            let (p, q, lhs_exact) = ratio(lhs, ctx)?;
+            let (r, s, rhs_exact) = ratio(rhs, ctx)?;
+            let (num, den) = match node {
+                Add(..) => (add(mul(p, s)?, mul(r, q)?)?, mul(q, s)?),
+                Sub(..) => (sub(mul(p, s)?, mul(r, q)?)?, mul(q, s)?),
+                Mul(..) => (mul(p, r)?, mul(q, s)?),
+                _ if r.is_zero() => return Err("Division by zero".to_string()),
+                _ => (mul(p, s)?, mul(q, r)?),
+            };
+            (num, den, lhs_exact && rhs_exact)
+        }
+        node => (node.eval(ctx)?.into_num(Function::Solve)?, Decimal::ONE, false),
+    };
+    // 能除尽的时候马上约掉分母，避免分子分母越乘越大
+    match exact_div(num, den) {
+        Some(quot) => Ok((quot, Decimal::ONE, exact)),
+        None => Ok((num, den, exact)),
+    }
+}
+
+// 把方程 lhs = rhs 整理成 a_1 x_1 + ... + a_n x_n = b，返回增广矩阵的一行 [a_1, ..., a_n, b]，
+// 以及这些系数是否精确。系数是分数的时候整行乘以分母，例如 x/3 = 1 => [1, 3]
+fn coefficients(
+    eq: &Node,
+    vars: &[String],
+    ctx: &mut Context,
+) -> Result<(Vec<Decimal>, bool), String> {
+    let Node::Eq(lhs, rhs) = eq else {
+        unreachable!()
+    };
+    let f = Node::Sub(lhs.clone(), rhs.clone());
+    let f = simplify(polynomial::bind(&f, ctx));
+    // 上下文中有值的变量已经代入，剩下的变量必须都是未知数
+    if let Some(var) = free_vars(&f).into_iter().find(|var| !vars.contains(var)) {
+        return Err(format!(
+            "Equation '{eq}' contains '{var}', which is not among the unknowns [{}]",
+            vars.join(", ")
+        ));
+    }
+    let nonlinear = |var: &str| format!("Equation '{eq}' is not linear in '{var}'");
+    let mut row = Vec::with_capacity(vars.len() + 1);
+    let mut exact = true;
+    for var in vars {
+        let poly = Polynomial::from_node(&f, var).map_err(|_| nonlinear(var))?;
+        let coef = poly.coef(1);
+        // x*y 这样的项对 x 是一次的，但系数含有另一个未知数
+        if poly.degree() > 1 || vars.iter().any(|var| contains_var(&coef, var)) {
+            return Err(nonlinear(var));
+        }
+        let (num, den, coef_exact) = ratio(&coef, ctx)?;
+        row.push((num, den));
+        exact &= coef_exact;
+    }
+    let zero = Node::Num(Decimal::ZERO);
+    let constant = vars.iter().fold(f, |f, var| substitute(&f, var, &zero));
+    let (num, den, constant_exact) = ratio(&constant, ctx)?;
+    row.push((-num, den));
+    exact &= constant_exact;
+
+    let mut scale = Decimal::ONE;
+    for (_, den) in &row {
+        if exact_div(scale, *den).is_none() {
+            scale = mul(scale, *den)?;
+        }
+    }
+    let row = row
+        .into_iter()
+        .map(|(num, den)| mul(num, checked_div(scale, den)?))
+        .collect::<Result<_, _>>()?;
+    Ok((row, exact))
+}
+
+// 无分数的高斯-约当消元，和矩阵的消元一样每一步的除法都是整除，有限小数不会损失精度。
+// 每一列选绝对值最大的元素作为主元，整列都是零的列对应自由变量。
+// 返回消元后的增广矩阵和每一个主元所在的列
+fn eliminate(
+    mut m: Vec<Vec<Decimal>>,
+    cols: usize,
+) -> Result<(Vec<Vec<Decimal>>, Vec<usize>), String> {
+    let mut prev = Decimal::ONE;
+    let mut pivots = Vec::new();
+    for c in 0..cols {
+        let r = pivots.len();
+        let Some(pivot) = (r..m.len())
+            .filter(|i| !m[*i][c].is_zero())
+            .max_by_key(|i| m[*i][c].abs())
+        else {
+            continue;
+        };
+        m.swap(pivot, r);
+        for i in (0..m.len()).filter(|i| *i != r) {
+            for j in (0..=cols).filter(|j| *j != c) {
+                let val = sub(mul(m[r][c], m[i][j])?, mul(m[i][c], m[r][j])?)?;
+                m[i][j] = checked_div(val, prev)?;
+            }
+            m[i][c] = Decimal::ZERO;
+        }
+        prev = m[r][c];
+        pivots.push(c);
+    }
+    Ok((m, pivots))
+}
+
+// num / den，除不尽的时候保留为约分之后的分数，例如 1/3
+fn fraction(num: Decimal, den: Decimal) -> Result<Node, String> {
+    if let Some(quot) = exact_div(num, den) {
+        return Ok(Node::Num(quot));
+    }
+    let quot = checked_div(num, den)?;
+    let (num, den) = (num.normalize(), den.normalize());
+    let scale = num.scale().max(den.scale());
+    let to_int = |x: Decimal| {
+        x.mantissa()
+            .checked_mul(10i128.checked_pow(scale - x.scale())?)
+    };
+    let (Some(mut p), Some(mut q)) = (to_int(num), to_int(den)) else {
+        return Ok(Node::Num(quot.normalize()));
+    };
+    let g = integer::gcd(p, q);
+    (p, q) = (p / g, q / g);
+    if q < 0 {
+        (p, q) = (-p, -q);
+    }
+    Ok(Node::Div(
+        Box::new(Node::Num(to_decimal(p)?)),
+        Box::new(Node::Num(to_decimal(q)?)),
+    ))
+}
+
+// 解线性方程组，解唯一时按未知数的顺序返回每个未知数的值，
+// 有无穷多解时返回用自由变量表示的通解，例如 [x = -z + 1, y = 2*z]
+pub fn solve(eqs: &[Node], vars: &[String], ctx: &mut Context) -> Result<Node, String> {
+    let mut rows = Vec::with_capacity(eqs.len());
+    let mut exact = true;
+    for eq in eqs {
+        let (row, row_exact) = coefficients(eq, vars, ctx)?;
+        rows.push(row);
+        exact &= row_exact;
+    }
+    // 系数不精确（例如含有 sqrt(2)）时分数没有意义，直接给出小数
+    let quot = |num: Decimal, den: Decimal| match exact {
+        true => fraction(num, den),
+        false => Ok(Node::Num(checked_div(num, den)?.normalize())),
+    };
+    let n = vars.len();
+    let (m, pivots) = eliminate(rows, n)?;
+    let rank = pivots.len();
+    // 消元之后系数全为零的行，右边不为零说明方程之间互相矛盾
+    if m[rank..].iter().any(|row| !row[n].is_zero()) {
+        return Err(format!(
+            "Linear system has no solution: the coefficient matrix has rank {rank}, but the augmented matrix has rank {}",
+            rank + 1
+        ));
+    }
+    let free: Vec<usize> = (0..n).filter(|c| !pivots.contains(c)).collect();
+    // 所有的未知数都是自由变量（例如 0 = 0），每个未知数都可以取任意值
+    if rank == 0 {
+        let var = |var: &String| Box::new(Node::Var(var.clone()));
+        let solution = vars.iter().map(|v| Node::Eq(var(v), var(v)));
+        return Ok(Node::Matrix(solution.collect()));
+    }
+    let mut values = Vec::with_capacity(rank);
+    for (r, c) in pivots.iter().enumerate() {
+        let pivot = m[r][*c];
+        let mut value = quot(m[r][n], pivot)?;
+        for j in free.iter().filter(|j| !m[r][**j].is_zero()) {
+            let term = Node::Mul(
+                Box::new(quot(-m[r][*j], pivot)?),
+                Box::new(Node::Var(vars[*j].clone())),
+            );
+            value = Node::Add(Box::new(value), Box::new(term));
+        }
+        values.push((*c, simplify(value)));
+    }
+    if free.is_empty() {
+        return Ok(Node::Matrix(
+            values.into_iter().map(|(_, value)| value).collect(),
+        ));
+    }
+    let solution = values
+        .into_iter()
+        .map(|(c, value)| Node::Eq(Box::new(Node::Var(vars[c].clone())), Box::new(value)));
+    Ok(Node::Matrix(solution.collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{ast::Ast, parser::Parser, value::Value};
+
+    fn solve_system(system: &str, vars: &[&str]) -> Result<String, String> {
+        let Ast(Node::Matrix(eqs)) = &Parser::parse(system).unwrap() else {
+            panic!("expected a list of equations");
+        };
+        let vars: Vec<String> = vars.iter().map(|var| var.to_string()).collect();
+        solve(eqs, &vars, &mut Context::with_seed(0)).map(|res| res.to_string())
+    }
+
+    #[test]
+    fn test_unique_solution() {
+        assert_eq!(
+            solve_system("[2x + y = 5, x - y = 1]", &["x", "y"]),
+            Ok("[2, 1]".to_string())
+        );
+        // 除不尽的结果保留为分数
+        assert_eq!(
+            solve_system("[3x = 1, x + 3y = 0]", &["x", "y"]),
+            Ok("[1/3, -1/9]".to_string())
+        );
+        assert_eq!(
+            solve_system("[0.1x + 0.2y = 0.3, x = y + 0.5]", &["x", "y"]),
+            Ok("[4/3, 5/6]".to_string())
+        );
+        // 分数系数按精确值计算
+        assert_eq!(solve_system("[x/3 = 1]", &["x"]), Ok("[3]".to_string()));
+        assert_eq!(solve_system("[x = 1/3]", &["x"]), Ok("[1/3]".to_string()));
+        assert_eq!(
+            solve_system("[x/3 + y/6 = 1, x - y/4 = 0]", &["x", "y"]),
+            Ok("[1, 4]".to_string())
+        );
+        // 系数不精确的时候给出小数
+        assert_eq!(
+            solve_system("[3x = sqrt(2)]", &["x"]),
+            Ok("[0.4714045207910316829338962414]".to_string())
+        );
+        // 方程比未知数多，但是彼此相容
+        assert_eq!(
+            solve_system("[x + y = 2, x - y = 0, 2x = 2]", &["x", "y"]),
+            Ok("[1, 1]".to_string())
+        );
+        // 第一列的第一个元素是 0，需要换行
+        assert_eq!(
+            solve_system("[y = 2, x + y = 3]", &["x", "y"]),
+            Ok("[1, 2]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parametric_solution() {
+        assert_eq!(
+            solve_system("[x + y + z = 1, x - y = 0]", &["x", "y", "z"]),
+            Ok("[x = -0.5*z + 0.5, y = -0.5*z + 0.5]".to_string())
+        );
+        assert_eq!(
+            solve_system("[x + 2y = 4, 2x + 4y = 8]", &["x", "y"]),
+            Ok("[x = -2*y + 4]".to_string())
+        );
+        assert_eq!(solve_system("[0 = 0]", &["x"]), Ok("[x = x]".to_string()));
+        assert_eq!(
+            solve_system("[x - x = 0]", &["x", "y"]),
+            Ok("[x = x, y = y]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_context_variables() {
+        // 上下文中有值的变量代入方程，不是未知数
+        let Ast(Node::Matrix(eqs)) = &Parser::parse("[x + a = 5, y = 2a]").unwrap() else {
+            panic!("expected a list of equations");
+        };
+        let mut ctx = Context::with_seed(0);
+        let vars = ["x".to_string(), "y".to_string()];
+        let res = ctx.scoped("a", Value::Num(Decimal::TWO), |ctx| solve(eqs, &vars, ctx));
+        assert_eq!(res.map(|res| res.to_string()), Ok("[3, 4]".to_string()));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            solve_system("[x + y = 1, x + y = 2]", &["x", "y"]),
+            Err("Linear system has no solution: the coefficient matrix has rank 1, but the augmented matrix has rank 2".to_string())
+        );
+        assert_eq!(
+            solve_system("[x*y = 1, x = 2]", &["x", "y"]),
+            Err("Equation 'x*y = 1' is not linear in 'x'".to_string())
+        );
+        assert_eq!(
+            solve_system("[x^2 = 1, y = 2]", &["x", "y"]),
+            Err("Equation 'x^2 = 1' is not linear in 'x'".to_string())
+        );
+        assert_eq!(
+            solve_system("[sin(x) = 1, y = 2]", &["x", "y"]),
+            Err("Equation 'sin(x) = 1' is not linear in 'x'".to_string())
+        );
+        assert_eq!(
+            solve_system("[x + a = 1, y = 2]", &["x", "y"]),
+            Err("Equation 'x + a = 1' contains 'a', which is not among the unknowns [x, y]".to_string())
+        );
+        assert_eq!(
+            solve_system("[x = 1, y = 2, z = 3]", &["x", "y"]),
+            Err("Equation 'z = 3' contains 'z', which is not among the unknowns [x, y]".to_string())
+        );
+    }
+}
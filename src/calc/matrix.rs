@@ -0,0 +1,446 @@
+use std::fmt::Display;
+
+use rust_decimal::Decimal;
+
+use crate::calc::{
+    token::Operator,
+    value::{checked, checked_div},
+};
+
+// 消元之后的增广矩阵，以及行交换的次数是否为奇数
+type Eliminated = (Vec<Vec<Decimal>>, bool);
+
+// 按行存储的矩阵
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<Decimal>,
+}
+
+fn mul(lhs: Decimal, rhs: Decimal) -> Result<Decimal, String> {
+    checked(lhs.checked_mul(rhs), Operator::Mul)
+}
+
+fn sub(lhs: Decimal, rhs: Decimal) -> Result<Decimal, String> {
+    checked(lhs.checked_sub(rhs), Operator::Sub)
+}
+
+impl Matrix {
+    pub fn from_rows(rows: Vec<Vec<Decimal>>) -> Result<Matrix, String> {
+        let cols = rows.first().map_or(0, Vec::len);
+        if cols == 0 {
+            return Err("Empty matrix".to_string());
+        }
+        if let Some(row) = rows.iter().find(|row| row.len() != cols) {
+            return Err(format!(
+                "Matrix rows must have the same length, expected {cols}, found {}",
+                row.len()
+            ));
+        }
+        Ok(Matrix {
+            rows: rows.len(),
+            cols,
+            data: rows.concat(),
+        })
+    }
+
+    pub fn identity(n: usize) -> Matrix {
+        let mut data = vec![Decimal::ZERO; n * n];
+        for i in 0..n {
+            data[i * n + i] = Decimal::ONE;
+        }
+        Matrix {
+            rows: n,
+            cols: n,
+            data,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    // 形如 2x3 的形状描述，用于错误信息
+    pub fn shape(&self) -> String {
+        format!("{}x{}", self.rows, self.cols)
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Decimal {
+        self.data[row * self.cols + col]
+    }
+
+    // 按行排列的所有元素
+    pub fn values(&self) -> &[Decimal] {
+        &self.data
+    }
+
+    pub fn row(&self, row: usize) -> &[Decimal] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    fn to_rows(&self) -> Vec<Vec<Decimal>> {
+        (0..self.rows).map(|i| self.row(i).to_vec()).collect()
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut data = Vec::with_capacity(self.data.len());
+        for j in 0..self.cols {
+            for i in 0..self.rows {
+                data.push(self.get(i, j));
+            }
+        }
+        Matrix {
+            rows: self.cols,
+            cols: self.rows,
+            data,
+        }
+    }
+
+    pub fn map(&self, f: impl Fn(Decimal) -> Result<Decimal, String>) -> Result<Matrix, String> {
+        Ok(Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().map(|x| f(*x)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn scale(&self, factor: Decimal) -> Result<Matrix, String> {
+        self.map(|x| mul(x, factor))
+    }
+
+    // 逐元素的加减法，两个矩阵形状必须相同
+    pub fn elementwise(&self, rhs: &Matrix, op: Operator) -> Result<Matrix, String> {
+        if self.rows != rhs.rows || self.cols != rhs.cols {
+            return Err(format!(
+                "Shape mismatch: cannot apply '{op}' to {} matrix and {} matrix",
+                self.shape(),
+                rhs.shape()
+            ));
+        }
+        let data = self
+            .data
+            .iter()
+            .zip(&rhs.data)
+            .map(|(a, b)| match op {
+                Operator::Add => checked(a.checked_add(*b), op),
+                Operator::Sub => checked(a.checked_sub(*b), op),
+                _ => unreachable!(),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data,
+        })
+    }
+
+    // 矩阵乘法，左矩阵的列数必须等于右矩阵的行数
+    pub fn mul(&self, rhs: &Matrix) -> Result<Matrix, String> {
+        if self.cols != rhs.rows {
+            return Err(format!(
+                "Shape mismatch: cannot multiply {} matrix by {} matrix",
+                self.shape(),
+                rhs.shape()
+            ));
+        }
+        let mut data = Vec::with_capacity(self.rows * rhs.cols);
+        for i in 0..self.rows {
+            for j in 0..rhs.cols {
+                let mut sum = Decimal::ZERO;
+                for k in 0..self.cols {
+                    sum = checked(
+                        sum.checked_add(mul(self.get(i, k), rhs.get(k, j))?),
+                        Operator::Add,
+                    )?;
+                }
+                data.push(sum);
+            }
+        }
+        Ok(Matrix {
+            rows: self.rows,
+            cols: rhs.cols,
+            data,
+        })
+    }
+
+    // 方阵的整数次幂，负数次幂先求逆
+    pub fn pow(&self, exp: Decimal) -> Result<Matrix, String> {
+        self.check_square("^")?;
+        if !exp.fract().is_zero() {
+            return Err(format!(
+                "Matrix power expects an integer exponent, found '{exp}'"
+            ));
+        }
+        let mut base = if exp.is_sign_negative() {
+            self.inverse()?
+        } else {
+            self.clone()
+        };
+        let mut exp = u64::try_from(exp.abs().trunc().mantissa())
+            .map_err(|_| format!("Matrix power exponent is too large: '{exp}'"))?;
+        let mut res = Matrix::identity(self.rows);
+        // 快速幂
+        while exp > 0 {
+            if exp & 1 == 1 {
+                res = res.mul(&base)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base)?;
+            }
+        }
+        Ok(res)
+    }
+
+    fn check_square(&self, what: &str) -> Result<(), String> {
+        if self.rows != self.cols {
+            Err(format!(
+                "'{what}' expects a square matrix, found {} matrix",
+                self.shape()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // 无分数的高斯-约当消元（Bareiss 算法）：对增广矩阵 [A | B] 进行消元，
+    // 每一步的除法都是整除，所以整数（以及有限小数）矩阵在消元过程中不会损失精度。
+    // 消元结束后左边是对角阵，对角线上都是 ±det(A)，右边是 ±det(A) * A^-1 * B。
+    // 奇异矩阵返回 None
+    fn eliminate(&self, rhs: &Matrix) -> Result<Option<Eliminated>, String> {
+        let n = self.rows;
+        let mut m: Vec<Vec<Decimal>> = self
+            .to_rows()
+            .into_iter()
+            .zip(rhs.to_rows())
+            .map(|(mut row, ext)| {
+                row.extend(ext);
+                row
+            })
+            .collect();
+        let width = n + rhs.cols;
+        let mut prev = Decimal::ONE;
+        let mut swapped = false;
+        for k in 0..n {
+            // 选主元：找到这一列第一个非零的元素
+            let Some(pivot) = (k..n).find(|i| !m[*i][k].is_zero()) else {
+                return Ok(None);
+            };
+            if pivot != k {
+                m.swap(pivot, k);
+                swapped = !swapped;
+            }
+            for i in (0..n).filter(|i| *i != k) {
+                for j in (0..width).filter(|j| *j != k) {
+                    let val = sub(mul(m[k][k], m[i][j])?, mul(m[i][k], m[k][j])?)?;
+                    m[i][j] = checked_div(val, prev)?;
+                }
+                m[i][k] = Decimal::ZERO;
+            }
+            prev = m[k][k];
+        }
+        Ok(Some((m, swapped)))
+    }
+
+    pub fn det(&self) -> Result<Decimal, String> {
+        self.check_square("det")?;
+        let empty = Matrix {
+            rows: self.rows,
+            cols: 0,
+            data: Vec::new(),
+        };
+        match self.eliminate(&empty)? {
+            Some((m, swapped)) => {
+                let det = m[self.rows - 1][self.rows - 1];
+                Ok(if swapped { -det } else { det }.normalize())
+            }
+            None => Ok(Decimal::ZERO),
+        }
+    }
+
+    pub fn inverse(&self) -> Result<Matrix, String> {
+        self.check_square("inv")?;
+        self.solve_unchecked(&Matrix::identity(self.rows))
+    }
+
+    // 解线性方程组 A * X = B，B 可以是列向量、行向量或者多列的矩阵，行向量的解也以行向量返回
+    pub fn solve(&self, rhs: &Matrix) -> Result<Matrix, String> {
+        self.check_square("solve")?;
+        if rhs.rows == self.rows {
+            self.solve_unchecked(rhs)
+        } else if rhs.rows == 1 && rhs.cols == self.rows {
+            Ok(self.solve_unchecked(&rhs.transpose())?.transpose())
+        } else {
+            Err(format!(
+                "Shape mismatch: cannot solve {} system with {} right-hand side",
+                self.shape(),
+                rhs.shape()
+            ))
+        }
+    }
+
+    fn solve_unchecked(&self, rhs: &Matrix) -> Result<Matrix, String> {
+        let Some((m, _)) = self.eliminate(rhs)? else {
+            return Err("Matrix is singular".to_string());
+        };
+        let n = self.rows;
+        let mut data = Vec::with_capacity(n * rhs.cols);
+        // 消元之后每一行只剩对角线上的元素，除以它得到解
+        for (i, row) in m.iter().enumerate() {
+            for x in &row[n..] {
+                data.push(checked_div(*x, row[i])?.normalize());
+            }
+        }
+        Ok(Matrix {
+            rows: n,
+            cols: rhs.cols,
+            data,
+        })
+    }
+}
+
+// 行向量显示为 [1, 2, 3]，其他矩阵按行显示为 [[1, 2], [3, 4]]，两种格式都可以重新作为矩阵字面量输入
+impl Display for Matrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_row = |f: &mut std::fmt::Formatter<'_>, row: &[Decimal]| {
+            write!(f, "[")?;
+            for (j, x) in row.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{x}")?;
+            }
+            write!(f, "]")
+        };
+        if self.rows == 1 {
+            return fmt_row(f, self.row(0));
+        }
+        write!(f, "[")?;
+        for i in 0..self.rows {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            fmt_row(f, self.row(i))?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    // 辅助函数：由整数构建矩阵
+    fn mat(rows: &[&[i64]]) -> Matrix {
+        Matrix::from_rows(
+            rows.iter()
+                .map(|row| row.iter().map(|x| Decimal::from(*x)).collect())
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_shape_check() {
+        assert_eq!(
+            Matrix::from_rows(vec![vec![dec!(1), dec!(2)], vec![dec!(3)]]),
+            Err("Matrix rows must have the same length, expected 2, found 1".to_string())
+        );
+        assert_eq!(
+            mat(&[&[1, 2, 3]]).mul(&mat(&[&[1, 2]])),
+            Err("Shape mismatch: cannot multiply 1x3 matrix by 1x2 matrix".to_string())
+        );
+        assert_eq!(
+            mat(&[&[1, 2]]).elementwise(&mat(&[&[1], &[2]]), Operator::Add),
+            Err("Shape mismatch: cannot apply '+' to 1x2 matrix and 2x1 matrix".to_string())
+        );
+        assert_eq!(
+            mat(&[&[1, 2]]).det(),
+            Err("'det' expects a square matrix, found 1x2 matrix".to_string())
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = mat(&[&[1, 2], &[3, 4]]);
+        assert_eq!(a.transpose(), mat(&[&[1, 3], &[2, 4]]));
+        assert_eq!(a.scale(dec!(2)), Ok(mat(&[&[2, 4], &[6, 8]])));
+        assert_eq!(
+            a.elementwise(&mat(&[&[1, 1], &[1, 1]]), Operator::Sub),
+            Ok(mat(&[&[0, 1], &[2, 3]]))
+        );
+        assert_eq!(
+            a.mul(&mat(&[&[1, 2, 3], &[4, 5, 6]])),
+            Ok(mat(&[&[9, 12, 15], &[19, 26, 33]]))
+        );
+        assert_eq!(a.pow(dec!(3)), Ok(mat(&[&[37, 54], &[81, 118]])));
+        assert_eq!(a.pow(dec!(0)), Ok(Matrix::identity(2)));
+        assert_eq!(
+            a.pow(dec!(0.5)),
+            Err("Matrix power expects an integer exponent, found '0.5'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_det() {
+        assert_eq!(mat(&[&[1, 2], &[3, 4]]).det(), Ok(dec!(-2)));
+        assert_eq!(
+            mat(&[&[2, 0, 1], &[1, 3, 2], &[1, 1, 2]]).det(),
+            Ok(dec!(6))
+        );
+        // 需要交换行的情况
+        assert_eq!(
+            mat(&[&[0, 0, 1], &[0, 1, 0], &[1, 0, 0]]).det(),
+            Ok(dec!(-1))
+        );
+        assert_eq!(mat(&[&[1, 2], &[2, 4]]).det(), Ok(dec!(0)));
+    }
+
+    #[test]
+    fn test_inverse_and_solve() {
+        assert_eq!(
+            mat(&[&[1, 2], &[3, 4]]).inverse(),
+            Ok(
+                Matrix::from_rows(vec![vec![dec!(-2), dec!(1)], vec![dec!(1.5), dec!(-0.5)]])
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            mat(&[&[1, 2, 3], &[0, 1, 4], &[5, 6, 0]]).inverse(),
+            Ok(mat(&[&[-24, 18, 5], &[20, -15, -4], &[-5, 4, 1]]))
+        );
+        // 需要交换行的情况
+        assert_eq!(
+            mat(&[&[0, 1], &[1, 0]]).inverse(),
+            Ok(mat(&[&[0, 1], &[1, 0]]))
+        );
+        assert_eq!(
+            mat(&[&[1, 2], &[2, 4]]).inverse(),
+            Err("Matrix is singular".to_string())
+        );
+
+        let a = mat(&[&[2, 0, 1], &[1, 3, 2], &[1, 1, 2]]);
+        assert_eq!(
+            a.solve(&mat(&[&[5], &[13], &[9]])),
+            Ok(mat(&[&[1], &[2], &[3]]))
+        );
+
+        // 2x + y = 5, x - y = 1
+        let a = mat(&[&[2, 1], &[1, -1]]);
+        assert_eq!(a.solve(&mat(&[&[5], &[1]])), Ok(mat(&[&[2], &[1]])));
+        assert_eq!(a.solve(&mat(&[&[5, 1]])), Ok(mat(&[&[2, 1]])));
+        assert_eq!(
+            a.solve(&mat(&[&[5, 1, 0]])),
+            Err("Shape mismatch: cannot solve 2x2 system with 1x3 right-hand side".to_string())
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(mat(&[&[1, 2], &[3, 4]]).to_string(), "[[1, 2], [3, 4]]");
+        assert_eq!(mat(&[&[1, 2, 3]]).to_string(), "[1, 2, 3]");
+        assert_eq!(mat(&[&[1], &[2]]).to_string(), "[[1], [2]]");
+    }
+}
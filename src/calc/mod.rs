@@ -1,13 +1,201 @@
-use rust_decimal::Decimal;
+use crate::calc::{ast::Ast, parser::Parser, value::Value};
 
-use crate::calc::parser::Parser;
+pub use crate::calc::{
+    context::Context,
+    stack::Stack,
+    tokenizer::Numerals,
+};
 
 mod token;
 mod tokenizer;
 mod ast;
 mod parser;
+mod function;
+mod value;
+mod matrix;
+mod integer;
+mod context;
+mod stats;
+mod simplify;
+mod derivative;
+mod printer;
+mod equation;
+mod integral;
+mod elementary;
+mod polynomial;
+mod linear;
+mod render;
+mod tree;
+mod steps;
+mod stack;
+mod sexpr;
+mod binary;
+mod arena;
+mod bytecode;
+mod bench;
+#[cfg(feature = "serde")]
+mod schema;
 
-pub fn expr(expr: &str) -> Result<Decimal, String> {
-    let parse_res = Parser::parse(expr)?;
-    Ok(parse_res.eval())
-}
\ No newline at end of file
+// 输入表达式的记法
+#[derive(PartialEq, Clone, Copy)]
+pub enum Notation {
+    Infix,
+    // 逆波兰式，例如 3 4 + 2 *
+    Rpn,
+    // S 表达式，例如 (* (+ 3 4) 2)
+    Sexpr,
+    // 十六进制表示的二进制语法树，格式见 binary.rs
+    Bytes,
+    // 序列化的语法树，格式见 schema.rs
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+// 语法树的深度超过 ctx.max_depth 时报错
+fn parse(expr: &str, notation: Notation, ctx: &Context) -> Result<Ast, String> {
+    let max_depth = ctx.max_depth;
+    match notation {
+        Notation::Infix => Parser::parse_with(expr, ctx.numerals, max_depth),
+        Notation::Rpn => Parser::parse_rpn(expr, max_depth),
+        Notation::Sexpr => sexpr::parse(expr, max_depth),
+        Notation::Bytes => Ast::from_bytes(&binary::from_hex(expr)?, max_depth),
+        #[cfg(feature = "serde")]
+        Notation::Json => Ast::from_json(expr, max_depth),
+    }
+}
+
+pub fn expr(expr: &str, notation: Notation, ctx: &mut Context) -> Result<Value, String> {
+    let parse_res = parse(expr, notation, ctx)?;
+    ctx.reset_iterations();
+    parse_res.eval(ctx)
+}
+
+// 解析之后重新打印的表达式，full 为 true 时给每一个复合的运算数都加上括号，用来确认输入是怎样分组的
+pub fn normalize(
+    expr: &str,
+    notation: Notation,
+    full: bool,
+    ctx: &Context,
+) -> Result<String, String> {
+    let ast = parse(expr, notation, ctx)?;
+    Ok(if full {
+        printer::parenthesized(&ast.0)
+    } else {
+        ast.to_string()
+    })
+}
+
+// 逐步求值的过程，每一行是化简了一个子表达式之后的表达式，最后一行是结果
+pub fn steps(expr: &str, ctx: &mut Context) -> Result<String, String> {
+    let ast = parse(expr, Notation::Infix, ctx)?;
+    ctx.reset_iterations();
+    let (steps, value) = ast.trace(ctx);
+    let steps = steps.join("\n→ ");
+    // 出错时先给出已经完成的各步，再给出错误
+    match value {
+        Ok(_) => Ok(steps),
+        Err(err) => Err(format!("{steps}\n→ {err}")),
+    }
+}
+
+// 中缀表达式对应的逆波兰式
+pub fn rpn(expr: &str, ctx: &Context) -> Result<String, String> {
+    Parser::to_rpn(expr, ctx.numerals)
+}
+
+// 中缀表达式对应的 S 表达式
+pub fn sexpr(expr: &str, ctx: &Context) -> Result<String, String> {
+    Ok(parse(expr, Notation::Infix, ctx)?.to_sexpr())
+}
+
+// 把表达式解析和求值 count 次，比较不同的语法树和字节码的耗时
+pub fn bench(expr: &str, count: u128, ctx: &Context) -> Result<String, String> {
+    bench::bench(expr, count, ctx)
+}
+
+// 表达式的语法树编码成的二进制数据，用十六进制显示
+pub fn bytes(expr: &str, ctx: &Context) -> Result<String, String> {
+    Ok(binary::to_hex(&parse(expr, Notation::Infix, ctx)?.to_bytes()))
+}
+
+// 表达式的语法树序列化成的 JSON
+#[cfg(feature = "serde")]
+pub fn json(expr: &str, ctx: &Context) -> Result<String, String> {
+    Ok(parse(expr, Notation::Infix, ctx)?.to_json())
+}
+
+// 化简表达式但不求值，表达式中可以含有未赋值的变量
+pub fn simplify(expr: &str, ctx: &Context) -> Result<String, String> {
+    Ok(parse(expr, Notation::Infix, ctx)?.simplify().to_string())
+}
+
+// 表达式的 LaTeX 代码，括号按照优先级添加
+pub fn latex(expr: &str, ctx: &Context) -> Result<String, String> {
+    Ok(parse(expr, Notation::Infix, ctx)?.to_latex())
+}
+
+// 表达式的展示形式 MathML
+pub fn mathml(expr: &str, ctx: &Context) -> Result<String, String> {
+    Ok(parse(expr, Notation::Infix, ctx)?.to_mathml())
+}
+
+// 表达式解析出的语法树，dot 为 true 时输出 Graphviz 的 DOT 格式，否则输出缩进的树状图
+pub fn tree(expr: &str, dot: bool, ctx: &Context) -> Result<String, String> {
+    let ast = parse(expr, Notation::Infix, ctx)?;
+    Ok(if dot { ast.to_dot() } else { ast.to_tree() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 很高的语法树在测试线程默认大小的栈上也能完成每一种处理，链的长度不受 :depth 限制
+    #[test]
+    fn test_tall_trees() {
+        let n = 5_000;
+        let mut ctx = Context::with_seed(0);
+        ctx.max_depth = n + 1;
+        let shapes = [
+            // 很长的链
+            vec!["1"; n + 1].join(" + "),
+            // 很深的嵌套
+            format!("{}1", "1^".repeat(n)),
+            format!("{}0{}", "sin(".repeat(n), ")".repeat(n)),
+            // 链和嵌套交替出现
+            format!("{}1{}", "(1 + ".repeat(n / 2), ")*1".repeat(n / 2)),
+        ];
+        for expr in &shapes {
+            assert!(super::expr(expr, Notation::Infix, &mut ctx).is_ok());
+            assert!(simplify(expr, &ctx).is_ok());
+            assert!(latex(expr, &ctx).is_ok());
+            assert!(mathml(expr, &ctx).is_ok());
+            assert!(tree(expr, false, &ctx).is_ok());
+            assert!(tree(expr, true, &ctx).is_ok());
+            assert!(normalize(expr, Notation::Infix, true, &ctx).is_ok());
+            assert!(bench(expr, 1, &ctx).is_ok());
+            let sexpr = sexpr(expr, &ctx).unwrap();
+            assert!(super::expr(&sexpr, Notation::Sexpr, &mut ctx).is_ok());
+            let bytes = bytes(expr, &ctx).unwrap();
+            assert!(super::expr(&bytes, Notation::Bytes, &mut ctx).is_ok());
+            let rpn = rpn(expr, &ctx).unwrap();
+            assert!(super::expr(&rpn, Notation::Rpn, &mut ctx).is_ok());
+        }
+        // 逐步求值每一步都要打印整个表达式，用第一步就出错的表达式检查
+        let steps = steps(&format!("{}x{}", "sin(".repeat(n), ")".repeat(n)), &mut ctx);
+        assert!(steps.unwrap_err().ends_with("Unknown variable: 'x'"));
+        // diff 本身增加一层嵌套
+        let chain = vec!["x"; n].join(" + ");
+        let diff = format!("diff({chain}, x, 1)");
+        let value = super::expr(&diff, Notation::Infix, &mut ctx).unwrap();
+        assert_eq!(value.to_string(), n.to_string());
+        assert!(simplify(&format!("diff({chain}, x)"), &ctx).is_ok());
+        // 默认的嵌套深度下，很长的链照样可以求值和打印
+        let mut ctx = Context::with_seed(0);
+        for count in [20_000, 200_000] {
+            let chain = vec!["1"; count].join(" + ");
+            let value = super::expr(&chain, Notation::Infix, &mut ctx).unwrap();
+            assert_eq!(value.to_string(), count.to_string());
+            assert_eq!(normalize(&chain, Notation::Infix, false, &ctx), Ok(chain));
+        }
+    }
+}
@@ -1,33 +1,83 @@
+use rust_decimal::Decimal;
+
 use crate::calc::{
+    arena::ArenaAst,
     ast::{Ast, Node},
+    function::Function,
     token::{Operator, Token},
-    tokenizer::Tokenizer,
+    tokenizer::{Lexeme, Numerals, Span, Tokenizer},
 };
 
 pub struct Parser;
 
-fn basic_check(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
+// 判断上一个 token 之后是否是一个表达式的开头，此时的正负号是一元的正负号而不是加减号
+fn is_expr_start(last: Option<&Token>) -> bool {
+    match last {
+        None | Some(Token::Op(_)) | Some(Token::Comma) => true,
+        Some(Token::Paren(lr)) => *lr == '(' || *lr == '[',
+        _ => false,
+    }
+}
+
+// 标识符和左括号前面的正负号无法合并到数字里，如果这是一个一元的符号，正号直接丢掉，负号换成取负运算符
+fn merge_sign(res: &mut Vec<(Token, Span)>) {
+    if let Some((Token::Op(op), span)) = res.last()
+        && op.priority() == 1
+        && is_expr_start(res.len().checked_sub(2).map(|i| &res[i].0))
+    {
+        let (op, span) = (*op, *span);
+        res.pop();
+        if op == Operator::Sub {
+            res.push((Token::Op(Operator::Neg), span));
+        }
+    }
+}
+
+// 检查分词的结果并合并正负号，每个 token 都带着它在输入中的位置，错误信息中有列号。
+// 后面紧跟左括号的标识符直接换成函数，函数名不会复制成 String
+fn basic_check(expr: &str, lexemes: Vec<(Lexeme, Span)>) -> Result<Vec<(Token, Span)>, String> {
     let mut res = Vec::new();
-    if !tokens.ends_with(&[Token::EOF]) {
+    if !matches!(lexemes.last(), Some((Lexeme::EOF, _))) {
         Err("Errors occurs before translating".to_string())
     } else {
         let mut last_token = Token::EOF;
-        let mut lparen = Vec::<bool>::new();
-        for token in tokens {
+        // 尚未匹配的左括号和它的位置，以及这个括号是否是函数调用的括号
+        let mut lparen = Vec::<(char, Span, bool)>::new();
+        let mut lexemes = lexemes.into_iter().peekable();
+        while let Some((lexeme, span)) = lexemes.next() {
+            let token = match lexeme {
+                Lexeme::Ident(name) if matches!(lexemes.peek(), Some((Lexeme::Paren('('), _))) => {
+                    // 未知的函数名保持为标识符，在左括号处报错
+                    match Function::from_name(name) {
+                        Some(func) => Token::Func(func, 0),
+                        None => Token::Ident(name.to_string()),
+                    }
+                }
+                lexeme => Token::from(lexeme),
+            };
             match token {
                 Token::Num(n) => {
                     match last_token {
                         Token::Num(last_num) => {
                             // 上一个 token 是 num，那么这一个符号应该是运算符或者结束，但是这一个却是数字，非法
-                            return Err(format!("Expected operator or eof behind num: '{last_num}', found num: '{n}'"))
+                            return Err(span.error(expr, &format!("Expected operator or eof behind num: '{last_num}', found num: '{n}'")))
                         }
+                        Token::Ident(ref name) => {
+                            return Err(span.error(expr, &format!("Expected operator or eof behind '{name}', found num: '{n}'")))
+                        }
+                        Token::Comma => {
+                            // 逗号之后是新的参数的开始，直接加入
+                            res.push((Token::Num(n), span));
+                            last_token = Token::Num(n);
+                        }
+                        Token::Func(..) | Token::List(_) => unreachable!(),
                         Token::Paren(lr) => {
                             // 如果上一个括号类token是括号
-                            if lr == ')' {
+                            if lr == ')' || lr == ']' {
                                 // 如果上一个token是右括号，这并不合法
-                                return Err(format!("Expected operator or eof behind ')', found num: '{n}'"));
+                                return Err(span.error(expr, &format!("Expected operator or eof behind '{lr}', found num: '{n}'")));
                             } else {
-                                res.push(Token::Num(n));
+                                res.push((Token::Num(n), span));
                                 last_token = Token::Num(n);
                             }
                         }
@@ -35,93 +85,105 @@ fn basic_check(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
                             // 如果上一个token类型是运算符，那么应该将运算符的正负合到数字里面（仅正负能够整合到数字里）
                             match op {
                                 Operator::Sub => {
-                                    res.pop(); // 这里将负号弹出来了
-                                    match res.last() {
-                                        Some(Token::Num(_)) => {
+                                    let (_, sign) = res.pop().unwrap(); // 这里将负号弹出来了
+                                    match res.last().map(|(token, _)| token) {
+                                        Some(Token::Num(_) | Token::Ident(_)) => {
                                             // 如果是 数 负号 数 的序列，将这个负号解释为减号，把这个减号重新加入结果的token序列
-                                            res.push(Token::Op(Operator::Sub));
-                                            res.push(Token::Num(n));
+                                            res.push((Token::Op(Operator::Sub), sign));
+                                            res.push((Token::Num(n), span));
                                             last_token = Token::Num(n);
                                         }
-                                        Some(Token::Op(_)) => {
-                                            // 如果是 运算符 负号 数 的序列，将这个负号解释为负号，将这个数字取反加入token序列
-                                            res.push(Token::Num(-n));
+                                        Some(Token::Op(_) | Token::Comma) => {
+                                            // 如果是 运算符 负号 数 的序列（或者 逗号 负号 数 的序列），将这个负号解释为负号，将这个数字取反加入token序列
+                                            res.push((Token::Num(-n), sign.to(span)));
                                             last_token = Token::Num(-n);
                                         }
                                         Some(Token::Paren(lr)) => {
-                                            if *lr == '(' {
+                                            if *lr == '(' || *lr == '[' {
                                                 // 如果是左括号 减号 数字的序列，那么这个数字是表达式开始，解释为负号，将数字取反加入token序列
-                                                res.push(Token::Num(-n));
+                                                res.push((Token::Num(-n), sign.to(span)));
                                                 last_token = Token::Num(-n);
                                             } else {
                                                 // 如果是右括号 减号 数字的序列，那么这个数字是减号的第二个操作数，解释为减号
-                                                res.push(Token::Op(Operator::Sub));
-                                                res.push(Token::Num(n));
+                                                res.push((Token::Op(Operator::Sub), sign));
+                                                res.push((Token::Num(n), span));
                                                 last_token = Token::Num(n);
                                             }
                                         }
-                                        Some(Token::EOF) => {
-                                            // EOF 不会加入序列，不可能有
+                                        Some(Token::EOF | Token::Func(..) | Token::List(_)) => {
+                                            // EOF 不会加入序列，函数名后面一定是左括号，列表只出现在逆波兰式中，不可能有
                                             unreachable!()
                                         }
                                         None => {
                                             // 负号前面没有token，那么这个数字是算式开始，将这个负号解释为负号，将数字取反加入token序列
-                                            res.push(Token::Num(-n));
+                                            res.push((Token::Num(-n), sign.to(span)));
                                             last_token = Token::Num(-n);
                                         }
                                     }
                                 }
                                 Operator::Add => {
-                                    res.pop(); // 这里将正号弹出来了
-                                    match res.last() {
-                                        Some(Token::Num(_)) => {
+                                    let (_, sign) = res.pop().unwrap(); // 这里将正号弹出来了
+                                    match res.last().map(|(token, _)| token) {
+                                        Some(Token::Num(_) | Token::Ident(_)) => {
                                             // 如果是 数 正号 数 的序列，将这个负号解释为加号，把这个加号重新加入结果的token序列
-                                            res.push(Token::Op(Operator::Add));
-                                            res.push(Token::Num(n));
+                                            res.push((Token::Op(Operator::Add), sign));
+                                            res.push((Token::Num(n), span));
                                             last_token = Token::Num(n);
                                         }
-                                        Some(Token::Op(_)) => {
-                                            // 如果是 运算符 正号 数 的序列，将这个负号解释为正号，将这个数字加入token序列
-                                            res.push(Token::Num(n));
+                                        Some(Token::Op(_) | Token::Comma) => {
+                                            // 如果是 运算符 正号 数 的序列（或者 逗号 正号 数 的序列），将这个负号解释为正号，将这个数字加入token序列
+                                            res.push((Token::Num(n), span));
                                             last_token = Token::Num(n);
                                         }
                                         Some(Token::Paren(lr)) => {
-                                            if *lr == '(' {
+                                            if *lr == '(' || *lr == '[' {
                                                 // 如果是左括号 加号号 数字的序列，那么这个数字是表达式开始，解释为正号，将数字加入token序列
-                                                res.push(Token::Num(n));
+                                                res.push((Token::Num(n), span));
                                                 last_token = Token::Num(n);
                                             } else {
                                                 // 如果是右括号 加号 数字的序列，那么这个数字是加号的第二个操作数，解释为加号
-                                                res.push(Token::Op(Operator::Add));
-                                                res.push(Token::Num(n));
+                                                res.push((Token::Op(Operator::Add), sign));
+                                                res.push((Token::Num(n), span));
                                                 last_token = Token::Num(n);
                                             }
                                         }
-                                        Some(Token::EOF) => {
-                                            // EOF 不会加入序列，不可能有
+                                        Some(Token::EOF | Token::Func(..) | Token::List(_)) => {
+                                            // EOF 不会加入序列，函数名后面一定是左括号，列表只出现在逆波兰式中，不可能有
                                             unreachable!()
                                         }
                                         None => {
                                             // 正号前面没有token，那么这个数字是算式开始，将这个正号解释为正号，将数字加入token序列
-                                            res.push(Token::Num(n));
+                                            res.push((Token::Num(n), span));
                                             last_token = Token::Num(n);
                                         }
                                     }
                                 }
                                 _ => {
                                     // 如果是其他运算符，直接加入序列
-                                    res.push(Token::Num(n));
+                                    res.push((Token::Num(n), span));
                                     last_token = Token::Num(n);
                                 }
                             }
                         }
                         Token::EOF => {
                             // 这说明这个数字是算式开始，直接加入
-                            res.push(Token::Num(n));
+                            res.push((Token::Num(n), span));
                             last_token = Token::Num(n);
                         }
                     }
                 }
+                Token::Op(Operator::Sqrt) => {
+                    // √ 和标识符一样是一个操作数的开始，例如 2√x 解释为 2*√x，-√x 解释为 -(√x)
+                    match &last_token {
+                        Token::Num(_) => res.push((Token::Op(Operator::Mul), span)),
+                        Token::Ident(_) | Token::Paren(')' | ']') => {
+                            return Err(span.error(expr, &format!("Expected operator or eof behind '{last_token}', found '√'")));
+                        }
+                        _ => merge_sign(&mut res),
+                    }
+                    res.push((Token::Op(Operator::Sqrt), span));
+                    last_token = Token::Op(Operator::Sqrt)
+                }
                 Token::Op(this_op) => {
                     if let Token::Op(last_op) = last_token {
                         // 如果上一个 token 是运算符，看一下能否进行运算符的合并，同样只有正负号能够合并
@@ -130,97 +192,197 @@ fn basic_check(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
                             // 合并正负运算符
                             if last_op == this_op {
                                 // 如果同号，那么把上一个token拿出来（有可能是负负得正），再加入一个正号token
-                                res.pop();
-                                res.push(Token::Op(Add));
+                                let (_, last) = res.pop().unwrap();
+                                res.push((Token::Op(Add), last.to(span)));
                                 last_token = Token::Op(Add);
                             } else {
                                 // 如果异号，那么把上一个token拿出来（有可能是正负得负），再加入一个负号token
-                                res.pop();
-                                res.push(Token::Op(Sub));
+                                let (_, last) = res.pop().unwrap();
+                                res.push((Token::Op(Sub), last.to(span)));
                                 last_token = Token::Op(Sub);
                             }
-                        } else if last_op.priority() > 1 && this_op.priority() == 1 {
-                            // 允许乘除一个负数或者负的表达式，也允许乘幂后面跟一个正负号
-                            res.push(Token::Op(this_op));
+                        } else if last_op.priority() != 1 && this_op.priority() == 1 {
+                            // 允许乘除一个负数或者负的表达式，也允许乘幂和等号后面跟一个正负号
+                            res.push((Token::Op(this_op), span));
                             last_token = Token::Op(this_op);
                         } else {
                             // 这种情况就是剩下的this_op的优先级大于1的情况，比如^*, -/, */，^/ 都是不合法的
-                            return Err(format!(
+                            return Err(span.error(expr, &format!(
                                 "Expected num or parenthesis behind '{last_op}', found operator: '{this_op}'"
-                            ));
+                            )));
                         }
                     } else {
                         // 上一个token不是运算符
                         match last_token {
-                            Token::Paren(last_token) => {
-                                if last_token == '(' && this_op.priority() >= 1 {
-                                    // 左括号后面跟着非正负的运算符，不合法，提前返回
-                                    return Err(format!(
-                                        "Expected num or expr behind '(', found operator: '{this_op}'"
-                                    ));
-                                }
+                            Token::Paren(last_token @ ('(' | '[')) if this_op.priority() != 1 => {
+                                // 左括号后面跟着非正负的运算符，不合法，提前返回
+                                return Err(span.error(expr, &format!(
+                                    "Expected num or expr behind '{last_token}', found operator: '{this_op}'"
+                                )));
                             }
-                            Token::EOF => {
+                            Token::Comma if this_op.priority() != 1 => {
+                                // 逗号之后是新的参数的开始，同样只允许正负号
+                                return Err(span.error(expr, &format!(
+                                    "Expected num or expr behind ',', found operator: '{this_op}'"
+                                )));
+                            }
+                            Token::EOF if this_op.priority() != 1 => {
                                 // 如果上一个 token 是一开始的 EOF，如果不是正负号，非法
-                                if this_op.priority() > 1 {
-                                    return Err(format!("Expected a num or '(' to start an expr, found '{this_op}'"))
-                                }
+                                return Err(span.error(expr, &format!("Expected a num or '(' to start an expr, found '{this_op}'")))
                             }
-                            // 如果上一个 token 是数字、右括号，合法离开这个分支继续执行
+                            // 负数后面跟着乘方时负号的优先级低于乘方，-2^2 是 -(2^2)，和 -x^2 一致。
+                            // 分词器产生的数都是非负的，负数一定是合并了前面的负号得到的
+                            Token::Num(n) if this_op == Operator::Pow && n.is_sign_negative() => {
+                                let (_, num) = res.pop().unwrap();
+                                res.push((Token::Op(Operator::Neg), num));
+                                res.push((Token::Num(-n), num));
+                            }
+                            // 如果上一个 token 是数字、标识符、右括号，合法离开这个分支继续执行
                             _ => {}
                         }
-                        res.push(Token::Op(this_op));
+                        res.push((Token::Op(this_op), span));
                         last_token = Token::Op(this_op)
                     }
                 }
                 Token::Paren(lr) => {
                     match lr {
-                        '(' => lparen.push(true),
-                        ')' => match lparen.pop() {
-                            Some(_) => {}
-                            None => return Err("Unmatched brackets".to_string()),
+                        '(' => match &last_token {
+                            // 函数名 左括号 的序列是函数调用，函数名已经在上面换成了函数
+                            Token::Func(..) => lparen.push(('(', span, true)),
+                            Token::Ident(name) => {
+                                let span = res.last().unwrap().1;
+                                return Err(span.error(expr, &format!("Unknown function: '{name}'")));
+                            }
+                            _ => {
+                                merge_sign(&mut res);
+                                lparen.push(('(', span, false));
+                            }
                         },
+                        '[' => {
+                            if let Token::Num(_) | Token::Ident(_) | Token::Paren(')' | ']') = last_token {
+                                return Err(span.error(expr, &format!("Expected operator or eof behind '{last_token}', found '['")));
+                            }
+                            merge_sign(&mut res);
+                            lparen.push(('[', span, false));
+                        }
+                        ')' | ']' => {
+                            let expected = if lr == ')' { '(' } else { '[' };
+                            match lparen.pop() {
+                                Some((l, _, _)) if l == expected => {}
+                                _ => return Err(span.error(expr, "Unmatched brackets")),
+                            }
+                            if let Token::Op(_) | Token::Comma = last_token {
+                                return Err(span.error(expr, &format!("Expected num or expr behind '{last_token}', found '{lr}'")));
+                            }
+                        }
                         _ => unreachable!(), // 分词器会剔除其他符号
                     }
-                    res.push(Token::Paren(lr));
+                    res.push((Token::Paren(lr), span));
                     last_token = Token::Paren(lr)
                 }
+                Token::Ident(_) | Token::Func(..) => {
+                    match &last_token {
+                        Token::Num(_) => {
+                            // 数字后面紧跟标识符是省略了乘号的系数，例如 2x 解释为 2*x
+                            res.push((Token::Op(Operator::Mul), span));
+                        }
+                        Token::Ident(_) | Token::Paren(')' | ']') => {
+                            return Err(span.error(expr, &format!("Expected operator or eof behind '{last_token}', found '{token}'")));
+                        }
+                        _ => merge_sign(&mut res),
+                    }
+                    res.push((token.clone(), span));
+                    last_token = token
+                }
+                Token::Comma => {
+                    // 逗号只能用来分隔函数参数或者方括号里的元素
+                    match lparen.last() {
+                        Some(('[', _, _)) | Some(('(', _, true)) => {}
+                        _ => return Err(span.error(expr, "Unexpected ',' outside of function call or matrix")),
+                    }
+                    match last_token {
+                        Token::Num(_) | Token::Ident(_) | Token::Paren(')' | ']') => {}
+                        _ => return Err(span.error(expr, &format!("Expected num or expr before ',', found '{last_token}'"))),
+                    }
+                    res.push((Token::Comma, span));
+                    last_token = Token::Comma
+                }
+                Token::List(_) => unreachable!(), // 只会出现在逆波兰式中
                 Token::EOF => break,
             }
         }
-        if !lparen.is_empty() {
-            Err("Unmatched brackets".to_string())
-        } else {
-            Ok(res)
+        // 报告最后一个没有匹配的左括号
+        match lparen.last() {
+            Some((_, span, _)) => Err(span.error(expr, "Unmatched brackets")),
+            None => Ok(res),
         }
     }
 }
 
-fn get_rpn(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
+// 调度场算法，token 的位置只用于错误信息，输出的逆波兰式不再带有位置
+fn get_rpn(expr: &str, tokens: Vec<(Token, Span)>) -> Result<Vec<Token>, String> {
     use Token::*;
 
     let mut rpn = Vec::new(); // 输出队列
-    let mut stack = Vec::new(); // 运算符栈
+    let mut stack: Vec<(Token, Span)> = Vec::new(); // 运算符栈
+    let mut args = Vec::new(); // 每一层括号里已经出现的参数个数
+    let mut last_lparen = false; // 上一个 token 是否是左括号，用于识别空的参数列表
 
-    for token in tokens {
+    for (token, span) in tokens {
+        let is_lparen = matches!(token, Paren('(' | '['));
         match token {
-            Num(_) => rpn.push(token.clone()),       // 数字直接输出
-            Paren('(') => stack.push(token.clone()), // 左括号入栈
-            Paren(')') => {
-                // 弹出所有运算符直到左括号
-                while let Some(top) = stack.pop() {
+            Num(_) | Ident(_) => rpn.push(token), // 数字和标识符直接输出
+            Func(..) => stack.push((token, span)), // 函数入栈，等待右括号
+            Paren('(' | '[') => {
+                // 左括号入栈
+                stack.push((token, span));
+                args.push(1);
+            }
+            Comma => {
+                // 弹出所有运算符直到左括号，开始下一个参数
+                while let Some((top, _)) = stack.last() {
                     match top {
-                        Paren('(') => break, // 找到左括号，停止
-                        _ => rpn.push(top),  // 其他运算符输出
+                        Paren('(' | '[') => break,
+                        _ => rpn.push(stack.pop().unwrap().0),
+                    }
+                }
+                match args.last_mut() {
+                    Some(count) => *count += 1,
+                    None => return Err(span.error(expr, "Unexpected ','")),
+                }
+            }
+            Paren(rparen) => {
+                let lparen = if rparen == ')' { '(' } else { '[' };
+                // 弹出所有运算符直到左括号
+                loop {
+                    match stack.pop() {
+                        Some((Paren(lr), _)) if lr == lparen => break, // 找到左括号，停止
+                        Some((Paren(_), _)) | None => {
+                            return Err(span.error(expr, "Unmatched parentheses"));
+                        }
+                        Some((top, _)) => rpn.push(top), // 其他运算符输出
                     }
                 }
+                let count = args.pop().unwrap();
+                // 紧跟着左括号的右括号说明括号里没有参数
+                let count = if last_lparen { 0 } else { count };
+                if rparen == ']' {
+                    rpn.push(List(count));
+                } else if let Some((Func(func, _), _)) = stack.last() {
+                    rpn.push(Func(*func, count));
+                    stack.pop();
+                } else if count == 0 {
+                    return Err(span.error(expr, "Empty parentheses"));
+                }
             }
-            Paren(_) => unreachable!(),
 
             Op(op) => {
                 let current_op = op;
-                // 处理栈顶优先级更高的运算符
-                while let Some(top) = stack.last() {
+                // 一元的前缀运算符不会弹出任何运算符，因为它的操作数还没有出现
+                while let Some((top, _)) = stack.last() {
+                    if current_op.is_unary() {
+                        break;
+                    }
                     match top {
                         Op(top_op) => {
                             let top_pri = top_op.priority();
@@ -230,7 +392,7 @@ fn get_rpn(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
                             if (top_op.is_left_associative() && top_pri >= cur_pri)
                                 || (!top_op.is_left_associative() && top_pri > cur_pri)
                             {
-                                rpn.push(stack.pop().unwrap());
+                                rpn.push(stack.pop().unwrap().0);
                             } else {
                                 break;
                             }
@@ -241,17 +403,19 @@ fn get_rpn(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
                 }
 
                 // 当前运算符入栈
-                stack.push(token.clone());
+                stack.push((token, span));
             }
 
+            List(_) => unreachable!(), // 只会出现在输出的逆波兰式里
             EOF => break, // 结束标志
         }
+        last_lparen = is_lparen;
     }
 
     // 弹出栈中所有剩余运算符
-    while let Some(op) = stack.pop() {
-        if let Paren('(') = op {
-            return Err("Unmatched parentheses".to_string(),);
+    while let Some((op, span)) = stack.pop() {
+        if let Paren(_) | Func(..) = op {
+            return Err(span.error(expr, "Unmatched parentheses"));
         }
         rpn.push(op);
     }
@@ -259,14 +423,158 @@ fn get_rpn(tokens: Vec<Token>) -> Result<Vec<Token>, String> {
     Ok(rpn)
 }
 
-fn parse(tokens: Vec<Token>) -> Result<Ast, String> {
+// 由逆波兰式构建语法树的方式，Box<Node> 的树和 arena 中的树共用 build 中的检查和错误信息
+pub trait Builder {
+    type Node;
+
+    fn num(&mut self, num: Decimal) -> Self::Node;
+    fn var(&mut self, name: String) -> Self::Node;
+    fn neg(&mut self, val: Self::Node) -> Self::Node;
+    // op 是除了取负以外的运算符
+    fn binary(&mut self, op: Operator, lhs: Self::Node, rhs: Self::Node) -> Self::Node;
+    fn is_eq(&self, node: &Self::Node) -> bool;
+    fn call(&mut self, func: Function, args: Vec<Self::Node>) -> Result<Self::Node, String>;
+    fn list(&mut self, elems: Vec<Self::Node>) -> Self::Node;
+}
+
+struct BoxTree;
+
+impl Builder for BoxTree {
+    type Node = Node;
+
+    fn num(&mut self, num: Decimal) -> Node {
+        Node::Num(num)
+    }
+
+    fn var(&mut self, name: String) -> Node {
+        Node::Var(name)
+    }
+
+    fn neg(&mut self, val: Node) -> Node {
+        Node::Neg(Box::new(val))
+    }
+
+    fn binary(&mut self, op: Operator, lhs: Node, rhs: Node) -> Node {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match op {
+            Operator::Add => Node::Add(lhs, rhs),
+            Operator::Sub => Node::Sub(lhs, rhs),
+            Operator::Mul => Node::Mul(lhs, rhs),
+            Operator::Div => Node::Div(lhs, rhs),
+            Operator::Pow => Node::Pow(lhs, rhs),
+            Operator::Eq => Node::Eq(lhs, rhs),
+            Operator::Neg | Operator::Sqrt => unreachable!(),
+        }
+    }
+
+    fn is_eq(&self, node: &Node) -> bool {
+        matches!(node, Node::Eq(..))
+    }
+
+    fn call(&mut self, func: Function, args: Vec<Node>) -> Result<Node, String> {
+        Node::call(func, args)
+    }
+
+    fn list(&mut self, elems: Vec<Node>) -> Node {
+        Node::Matrix(elems)
+    }
+}
+
+// 语法树的深度，叶子节点的深度是 1。nest 是嵌套的层数，由 :depth 限制；左结合的运算符连成的链
+// （1 + 2 + 3）不算嵌套，链的左边和整个链在同一层，所以很长的 1 + 1 + ... + 1 的嵌套层数是 2，
+// 链的长度不受限制
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Depth {
+    pub nest: usize,
+}
+
+impl Depth {
+    pub const LEAF: Depth = Depth { nest: 1 };
+
+    // 一般的节点比最深的子节点深一层
+    pub fn parent(children: impl IntoIterator<Item = Depth>) -> Depth {
+        children.into_iter().fold(Depth::LEAF, |depth, child| Depth {
+            nest: depth.nest.max(child.nest + 1),
+        })
+    }
+
+    // 左结合的运算符，左边的运算数不增加嵌套的层数
+    pub fn chain(lhs: Depth, rhs: Depth) -> Depth {
+        Depth {
+            nest: lhs.nest.max(rhs.nest + 1),
+        }
+    }
+
+    // 自上而下计算深度时，子节点的深度
+    pub fn child(self, is_chain_lhs: bool) -> Depth {
+        Depth {
+            nest: if is_chain_lhs { self.nest } else { self.nest + 1 },
+        }
+    }
+
+    pub fn check(self, max_depth: usize) -> Result<Depth, String> {
+        if self.nest > max_depth {
+            Err(format!("Expression nesting exceeds {max_depth} levels"))
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+// 左边的运算数不算嵌套的运算符
+pub fn is_chain(op: Operator) -> bool {
+    matches!(op, Operator::Add | Operator::Sub | Operator::Mul | Operator::Div)
+}
+
+// 构建的同时记录每个节点的嵌套深度，超过限制时报错
+pub fn build<B: Builder>(
+    tokens: Vec<Token>,
+    builder: &mut B,
+    max_depth: usize,
+) -> Result<B::Node, String> {
     use Token::*;
 
-    let mut stack: Vec<Node> = Vec::new();
+    let mut stack: Vec<B::Node> = Vec::new();
+    // stack 中每个节点的深度
+    let mut depths: Vec<Depth> = Vec::new();
+    // 弹出 count 个节点之后压入的新节点的深度
+    let parent = |depths: &mut Vec<Depth>, count: usize| {
+        let children = depths.split_off(depths.len() - count);
+        depths.push(Depth::parent(children).check(max_depth)?);
+        Ok::<(), String>(())
+    };
 
     for token in tokens {
         match token {
-            Num(n) => stack.push(Node::Num(n)),
+            Num(n) => {
+                parent(&mut depths, 0)?;
+                stack.push(builder.num(n));
+            }
+
+            Ident(name) => {
+                parent(&mut depths, 0)?;
+                stack.push(builder.var(name));
+            }
+
+            Op(Operator::Neg) => {
+                let Some(val) = stack.pop() else {
+                    return Err(
+                        "Insufficient operands for operator".to_string(),
+                    );
+                };
+                parent(&mut depths, 1)?;
+                stack.push(builder.neg(val));
+            }
+
+            Op(Operator::Sqrt) => {
+                let Some(val) = stack.pop() else {
+                    return Err(
+                        "Insufficient operands for operator".to_string(),
+                    );
+                };
+                parent(&mut depths, 1)?;
+                stack.push(builder.call(Function::Sqrt, vec![val])?);
+            }
 
             Op(op) => {
                 if stack.len() < 2 {
@@ -275,18 +583,41 @@ fn parse(tokens: Vec<Token>) -> Result<Ast, String> {
                     );
                 }
 
-                let right = Box::new(stack.pop().unwrap());
-                let left = Box::new(stack.pop().unwrap());
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
 
-                let node = match op {
-                    Operator::Add => Node::Add(left, right),
-                    Operator::Sub => Node::Sub(left, right),
-                    Operator::Mul => Node::Mul(left, right),
-                    Operator::Div => Node::Div(left, right),
-                    Operator::Pow => Node::Pow(left, right),
-                };
+                // 方程只能有一个等号
+                if op == Operator::Eq && (builder.is_eq(&left) || builder.is_eq(&right)) {
+                    return Err("Expected a single '=' in an equation".to_string());
+                }
+
+                if is_chain(op) {
+                    let rhs = depths.pop().unwrap();
+                    let lhs = depths.pop().unwrap();
+                    depths.push(Depth::chain(lhs, rhs).check(max_depth)?);
+                } else {
+                    parent(&mut depths, 2)?;
+                }
+                stack.push(builder.binary(op, left, right));
+            }
+
+            Func(func, count) => {
+                func.check_arity(count)?;
+                if stack.len() < count {
+                    return Err(format!("Insufficient arguments for function '{func}'"));
+                }
+                let args = stack.split_off(stack.len() - count);
+                parent(&mut depths, count)?;
+                stack.push(builder.call(func, args)?);
+            }
 
-                stack.push(node);
+            List(count) => {
+                if stack.len() < count {
+                    return Err("Insufficient elements for matrix".to_string());
+                }
+                let elems = stack.split_off(stack.len() - count);
+                parent(&mut depths, count)?;
+                stack.push(builder.list(elems));
             }
 
             _ => {} // 忽略其他token
@@ -298,28 +629,154 @@ fn parse(tokens: Vec<Token>) -> Result<Ast, String> {
             "Malformed expression".to_string(),
         )
     } else {
-        Ok(Ast(stack.pop().unwrap()))
+        Ok(stack.pop().unwrap())
     }
 }
 
+fn parse(tokens: Vec<Token>, max_depth: usize) -> Result<Ast, String> {
+    build(tokens, &mut BoxTree, max_depth).map(Ast)
+}
+
+// 逆波兰式中的一个词：数、运算符、标识符、函数名或者列表 [n]。
+// 一元的负号写成 neg，√ 写成 sqrt，参数个数不固定的函数写成 name:n，例如 gcd:3
+pub fn rpn_token(word: &str) -> Result<Token, String> {
+    use Token::*;
+    let op = match word {
+        "+" => Some(Operator::Add),
+        "-" => Some(Operator::Sub),
+        "*" => Some(Operator::Mul),
+        "/" => Some(Operator::Div),
+        "^" => Some(Operator::Pow),
+        "=" => Some(Operator::Eq),
+        "neg" => Some(Operator::Neg),
+        _ => None,
+    };
+    if let Some(op) = op {
+        return Ok(Op(op));
+    }
+    if let Some(count) = word.strip_prefix('[').and_then(|word| word.strip_suffix(']')) {
+        return count
+            .parse()
+            .map(List)
+            .map_err(|_| format!("Expected an element count in '{word}'"));
+    }
+    if word.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+        return word.parse().map(Num).map_err(|_| format!("Invalid number: '{word}'"));
+    }
+    let (name, count) = match word.split_once(':') {
+        Some((name, count)) => (name, Some(count)),
+        None => (word, None),
+    };
+    if let Some(func) = Function::from_name(name) {
+        let arity = func.arity();
+        let count = match count {
+            Some(count) => count
+                .parse()
+                .map_err(|_| format!("Expected an argument count in '{word}'"))?,
+            None if arity.start() == arity.end() => *arity.start(),
+            None => {
+                return Err(format!(
+                    "Function '{func}' expects {} argument(s), write '{func}:n' with the argument count",
+                    func.arity_text()
+                ));
+            }
+        };
+        return Ok(Func(func, count));
+    }
+    let is_ident = word.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && word.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if is_ident {
+        return Ok(Ident(word.to_string()));
+    }
+    Err(format!("Unexpected '{word}' in RPN expression"))
+}
+
+// rpn_token 的逆过程
+fn rpn_word(token: &Token) -> String {
+    match token {
+        Token::Op(Operator::Neg) => "neg".to_string(),
+        Token::Op(Operator::Sqrt) => Function::Sqrt.to_string(),
+        Token::Func(func, count) if func.arity().start() != func.arity().end() => {
+            format!("{func}:{count}")
+        }
+        token => token.to_string(),
+    }
+}
+
+// 分词的结果引用输入中的切片，变量名在 basic_check 中才复制成 String
+fn tokenize(expr: &str, numerals: Numerals) -> Result<Vec<(Lexeme<'_>, Span)>, String> {
+    Tokenizer::new(expr, numerals).collect()
+}
+
+// 中缀表达式的逆波兰式，各步的错误都带有列号
+fn infix_rpn(expr: &str, numerals: Numerals) -> Result<Vec<Token>, String> {
+    get_rpn(expr, basic_check(expr, tokenize(expr, numerals)?)?)
+}
+
 impl Parser {
+    // 只接受 ASCII 数字，使用默认的最大嵌套深度。
+    // REPL 中的设置可以由 :unicode 和 :depth 修改，总是通过 parse_with 解析
+    #[cfg(test)]
     pub fn parse(expr: &str) -> Result<Ast, String> {
-        let tokens: Vec<Token> = Tokenizer::from(expr).collect();
-        parse(get_rpn(basic_check(tokens)?)?)
+        Parser::parse_with(expr, Numerals::Ascii, crate::calc::context::DEFAULT_MAX_DEPTH)
+    }
+
+    // 语法树的深度超过 max_depth 时报错。括号本身不增加深度，例如 ((1)) 的深度是 1
+    pub fn parse_with(expr: &str, numerals: Numerals, max_depth: usize) -> Result<Ast, String> {
+        parse(infix_rpn(expr, numerals)?, max_depth)
+    }
+
+    // 和 parse 相同的检查，节点保存在 arena 中
+    pub fn parse_arena(
+        expr: &str,
+        numerals: Numerals,
+        max_depth: usize,
+    ) -> Result<ArenaAst, String> {
+        ArenaAst::build(infix_rpn(expr, numerals)?, max_depth)
+    }
+
+    // 以空白分隔的逆波兰式，例如 "3 4 + 2 *"，和中缀表达式一样由 parse 构建语法树
+    pub fn parse_rpn(expr: &str, max_depth: usize) -> Result<Ast, String> {
+        let tokens = expr
+            .split_whitespace()
+            .map(rpn_token)
+            .collect::<Result<Vec<Token>, String>>()?;
+        parse(tokens, max_depth)
+    }
+
+    // 中缀表达式对应的逆波兰式，可以由 parse_rpn 读回
+    pub fn to_rpn(expr: &str, numerals: Numerals) -> Result<String, String> {
+        let rpn = infix_rpn(expr, numerals)?;
+        Ok(rpn.iter().map(rpn_word).collect::<Vec<String>>().join(" "))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use rust_decimal::dec;
+    use crate::calc::{
+        context::{Context, DEFAULT_MAX_DEPTH},
+        value::Value,
+    };
+    use rust_decimal::{Decimal, dec};
+
+    // basic_check 的结果去掉位置
+    fn check(expr: &str) -> Result<Vec<Token>, String> {
+        let tokens = basic_check(expr, tokenize(expr, Numerals::Ascii).unwrap())?;
+        Ok(tokens.into_iter().map(|(token, _)| token).collect())
+    }
+
+    // 手写的 token 序列没有位置
+    fn unspanned(tokens: Vec<Token>) -> Vec<(Token, Span)> {
+        let span = Span { start: 0, end: 0 };
+        tokens.into_iter().map(|token| (token, span)).collect()
+    }
 
     #[test]
     fn basic_check_sign_merge() {
         // 测试两个正号合并
-        let tokens = Tokenizer::from("13.0 + +1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 + +1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Add),
@@ -328,9 +785,8 @@ mod test {
         );
 
         // 测试正负号合并
-        let tokens = Tokenizer::from("13.0 +- 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 +- 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Sub),
@@ -339,9 +795,8 @@ mod test {
         );
 
         // 测试负正号合并
-        let tokens = Tokenizer::from("13.0 -+ 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 -+ 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Sub),
@@ -350,9 +805,8 @@ mod test {
         );
 
         // 测试两个负号合并
-        let tokens = Tokenizer::from("13.0 -- 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 -- 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Add),
@@ -361,9 +815,8 @@ mod test {
         );
 
         // 测试三个负号合并
-        let tokens = Tokenizer::from("13.0 --- 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 --- 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Sub),
@@ -372,9 +825,8 @@ mod test {
         );
 
         // 测试三个正号合并
-        let tokens = Tokenizer::from("13.0 +++ 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 +++ 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Add),
@@ -386,20 +838,17 @@ mod test {
     #[test]
     fn basic_check_error_check() {
         // 检测括号匹配
-        let tokens = Tokenizer::from("(13.0 + 1 (").collect();
-        assert_eq!(basic_check(tokens), Err("Unmatched brackets".to_string()));
+        assert_eq!(check("(13.0 + 1 ("), Err("Unmatched brackets at column 11".to_string()));
 
         // 测试运算符之后跟运算符的错误
-        let tokens = Tokenizer::from("13.0 +* 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
-            Err("Expected num or parenthesis behind '+', found operator: '*'".to_string())
+            check("13.0 +* 1 "),
+            Err("Expected num or parenthesis behind '+', found operator: '*' at column 7".to_string())
         );
 
         // 测试运算符之后跟运算符的错误
-        let tokens = Tokenizer::from("13.0 *+ 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 *+ 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Mul),
@@ -408,9 +857,8 @@ mod test {
         );
 
         // 测试运算符之后跟运算符的错误
-        let tokens = Tokenizer::from("13.0 *- 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 *- 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Mul),
@@ -419,9 +867,8 @@ mod test {
         );
 
         // 测试运算符之后跟运算符的错误
-        let tokens = Tokenizer::from("13.0 *---- 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("13.0 *---- 1 "),
             Ok(vec![
                 Token::Num(dec!(13.0)),
                 Token::Op(Operator::Mul),
@@ -430,9 +877,8 @@ mod test {
         );
 
         // 测试运算符之后跟运算符的错误
-        let tokens = Tokenizer::from("14.0 *-+-- 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
+            check("14.0 *-+-- 1 "),
             Ok(vec![
                 Token::Num(dec!(14.0)),
                 Token::Op(Operator::Mul),
@@ -441,38 +887,44 @@ mod test {
         );
 
         // 测试运算符之后跟运算符的错误
-        let tokens = Tokenizer::from("13.0 *--/ 1 ").collect();
         assert_eq!(
-            basic_check(tokens),
-            Err("Expected num or parenthesis behind '+', found operator: '/'".to_string())
+            check("13.0 *--/ 1 "),
+            Err("Expected num or parenthesis behind '+', found operator: '/' at column 9".to_string())
         );
 
         // 测试左括号后跟运算符的错误
-        let tokens = Tokenizer::from("13.0 * 1 (--/ ").collect();
         assert_eq!(
-            basic_check(tokens),
-            Err("Expected num or expr behind '(', found operator: '-'".to_string())
+            check("13.0 * 1 (*/ "),
+            Err("Expected num or expr behind '(', found operator: '*' at column 11".to_string())
+        );
+
+        // 左括号后允许跟正负号（README 中的规则，solve(A, (-1) * b) 这样的参数需要），
+        // 所以原来的 (--/ 现在合并成正号之后在 '/' 处报错
+        assert_eq!(
+            check("13.0 * 1 (--/ "),
+            Err("Expected num or parenthesis behind '+', found operator: '/' at column 13".to_string())
+        );
+        assert_eq!(
+            check("(-1)"),
+            Ok(vec![Token::Paren('('), Token::Num(dec!(-1)), Token::Paren(')')])
         );
 
         // 测试右括号后跟数字的错误
-        let tokens = Tokenizer::from("13.0 * 1* ( 1-4) 3.0 ").collect();
         assert_eq!(
-            basic_check(tokens),
-            Err("Expected operator or eof behind ')', found num: '3.0'".to_string())
+            check("13.0 * 1* ( 1-4) 3.0 "),
+            Err("Expected operator or eof behind ')', found num: '3.0' at column 18".to_string())
         );
 
         // 测试两个连续数字的错误
-        let tokens = Tokenizer::from("13.0  1 --/ ").collect();
         assert_eq!(
-            basic_check(tokens),
-            Err("Expected operator or eof behind num: '13.0', found num: '1'".to_string())
+            check("13.0  1 --/ "),
+            Err("Expected operator or eof behind num: '13.0', found num: '1' at column 7".to_string())
         );
 
         // 测试以非正负号符号开头的错误
-        let tokens = Tokenizer::from("*13.0  1 --/ ").collect();
         assert_eq!(
-            basic_check(tokens),
-            Err("Expected a num or '(' to start an expr, found '*'".to_string())
+            check("*13.0  1 --/ "),
+            Err("Expected a num or '(' to start an expr, found '*' at column 1".to_string())
         );
     }
 
@@ -482,7 +934,7 @@ mod test {
         use super::Token::*;
         // 测试基本表达式
         let tokens = vec![Num(dec!(1)), Op(Add), Num(dec!(2)), EOF];
-        let rpn = get_rpn(tokens).unwrap();
+        let rpn = get_rpn("", unspanned(tokens)).unwrap();
         assert_eq!(rpn, vec![Num(dec!(1)), Num(dec!(2)), Op(Add)]);
 
         // 测试运算符优先级 1 + 2 * 3 => 1 2 3 * +
@@ -494,7 +946,7 @@ mod test {
             Num(dec!(3)),
             EOF,
         ];
-        let rpn = get_rpn(tokens).unwrap();
+        let rpn = get_rpn("", unspanned(tokens)).unwrap();
         assert_eq!(
             rpn,
             vec![Num(dec!(1)), Num(dec!(2)), Num(dec!(3)), Op(Mul), Op(Add)]
@@ -511,7 +963,7 @@ mod test {
             Num(dec!(3)),
             EOF,
         ];
-        let rpn = get_rpn(tokens).unwrap();
+        let rpn = get_rpn("", unspanned(tokens)).unwrap();
         assert_eq!(
             rpn,
             vec![Num(dec!(1)), Num(dec!(2)), Op(Add), Num(dec!(3)), Op(Mul)]
@@ -526,7 +978,7 @@ mod test {
             Num(dec!(4)),
             EOF,
         ];
-        let rpn = get_rpn(tokens).unwrap();
+        let rpn = get_rpn("", unspanned(tokens)).unwrap();
         assert_eq!(
             rpn,
             vec![Num(dec!(2)), Num(dec!(3)), Num(dec!(4)), Op(Pow), Op(Pow)]
@@ -547,7 +999,7 @@ mod test {
             Num(dec!(3)),
             EOF,
         ];
-        let rpn = get_rpn(tokens).unwrap();
+        let rpn = get_rpn("", unspanned(tokens)).unwrap();
         assert_eq!(
             rpn,
             vec![
@@ -571,7 +1023,7 @@ mod test {
         use super::Token::*;
         // 测试简单加法
         let tokens = vec![Num(dec!(1)), Num(dec!(2)), Op(Add), EOF];
-        let ast = parse(tokens).unwrap();
+        let ast = parse(tokens, DEFAULT_MAX_DEPTH).unwrap();
         assert_eq!(
             ast,
             Ast(Node::Add(
@@ -589,7 +1041,7 @@ mod test {
             Op(Add),
             EOF,
         ];
-        let ast = parse(tokens).unwrap();
+        let ast = parse(tokens, DEFAULT_MAX_DEPTH).unwrap();
         assert_eq!(
             ast,
             Ast(Node::Add(
@@ -610,7 +1062,7 @@ mod test {
             Op(Mul),
             EOF,
         ];
-        let ast = parse(tokens).unwrap();
+        let ast = parse(tokens, DEFAULT_MAX_DEPTH).unwrap();
         assert_eq!(
             ast,
             Ast(Node::Mul(
@@ -624,7 +1076,7 @@ mod test {
 
         // 测试幂运算
         let tokens = vec![Num(dec!(2)), Num(dec!(3)), Op(Pow), EOF];
-        let ast = parse(tokens).unwrap();
+        let ast = parse(tokens, DEFAULT_MAX_DEPTH).unwrap();
         assert_eq!(
             ast,
             Ast(Node::Pow(
@@ -646,7 +1098,7 @@ mod test {
             Op(Sub),
             EOF,
         ];
-        let ast = parse(tokens).unwrap();
+        let ast = parse(tokens, DEFAULT_MAX_DEPTH).unwrap();
         assert_eq!(
             ast,
             Ast(Node::Sub(
@@ -733,4 +1185,259 @@ mod test {
             ))
         );
     }
+
+    #[test]
+    fn test_parse_matrix_and_call() {
+        let num = |n: Decimal| Box::new(Node::Num(n));
+
+        // 矩阵字面量
+        let ast = Parser::parse("[[1, 2], [3, -4]]").unwrap();
+        assert_eq!(
+            ast,
+            Ast(Node::Matrix(vec![
+                Node::Matrix(vec![Node::Num(dec!(1)), Node::Num(dec!(2))]),
+                Node::Matrix(vec![Node::Num(dec!(3)), Node::Num(dec!(-4))]),
+            ]))
+        );
+
+        // 函数调用以及函数前的负号
+        let ast = Parser::parse("-det([1]) * 2").unwrap();
+        assert_eq!(
+            ast,
+            Ast(Node::Mul(
+                Box::new(Node::Neg(Box::new(Node::Call(
+                    Function::Det,
+                    vec![Node::Matrix(vec![Node::Num(dec!(1))])]
+                )))),
+                num(dec!(2))
+            ))
+        );
+
+        // 取负的优先级低于乘方：-(1)^2 => -((1)^2)
+        let ast = Parser::parse("-(1)^2").unwrap();
+        assert_eq!(
+            ast,
+            Ast(Node::Neg(Box::new(Node::Pow(num(dec!(1)), num(dec!(2))))))
+        );
+
+        assert_eq!(Parser::parse("foo(1)"), Err("Unknown function: 'foo' at column 1".to_string()));
+        assert_eq!(
+            Parser::parse("det(1, 2)"),
+            Err("Function 'det' expects 1 argument(s), found 2".to_string())
+        );
+        assert_eq!(
+            Parser::parse("1, 2"),
+            Err("Unexpected ',' outside of function call or matrix at column 2".to_string())
+        );
+        assert_eq!(Parser::parse("(1]"), Err("Unmatched brackets at column 3".to_string()));
+    }
+
+    #[test]
+    fn test_parse_sum() {
+        let ast = Parser::parse("sum(k, 1, n, -k)").unwrap();
+        assert_eq!(
+            ast,
+            Ast(Node::Sum(
+                "k".to_string(),
+                Box::new(Node::Num(dec!(1))),
+                Box::new(Node::Var("n".to_string())),
+                Box::new(Node::Neg(Box::new(Node::Var("k".to_string()))))
+            ))
+        );
+        assert_eq!(
+            Parser::parse("prod(2, 1, 3, 4)"),
+            Err("Function 'prod' expects a variable as its first argument".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_equation() {
+        // 等号的优先级最低，等号后面可以跟负号
+        let ast = Parser::parse("x + 1 = -2").unwrap();
+        assert_eq!(
+            ast,
+            Ast(Node::Eq(
+                Box::new(Node::Add(
+                    Box::new(Node::Var("x".to_string())),
+                    Box::new(Node::Num(dec!(1)))
+                )),
+                Box::new(Node::Num(dec!(-2)))
+            ))
+        );
+        assert_eq!(
+            Parser::parse("x = 1 = 2"),
+            Err("Expected a single '=' in an equation".to_string())
+        );
+        assert_eq!(
+            Parser::parse("(= 1)"),
+            Err("Expected num or expr behind '(', found operator: '=' at column 2".to_string())
+        );
+        assert_eq!(
+            Parser::parse("solve(x = 1, 2)"),
+            Err("Function 'solve' expects a variable as its second argument".to_string())
+        );
+        assert_eq!(
+            Parser::parse("solve([[1]], [1], 2)"),
+            Err("Function 'solve' expects 2 argument(s) for a linear system, found 3".to_string())
+        );
+        assert_eq!(
+            Parser::parse("solve([x = 1, 2], [x])"),
+            Err("Function 'solve' expects a list of equations as its first argument".to_string())
+        );
+        assert_eq!(
+            Parser::parse("solve([x = 1], x)"),
+            Err("Function 'solve' expects a list of variables as its second argument".to_string())
+        );
+        assert_eq!(
+            Parser::parse("solve([x + y = 1], [x, y, x])"),
+            Err("Variable 'x' appears more than once in 'solve'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        // 数字后面紧跟标识符时省略了乘号，优先级和普通的乘法相同
+        let explicit = |expr: &str| Parser::parse(expr).unwrap();
+        assert_eq!(Parser::parse("2x + y"), Ok(explicit("2*x + y")));
+        assert_eq!(Parser::parse("-3x^2"), Ok(explicit("-3*x^2")));
+        assert_eq!(Parser::parse("2sin(x)"), Ok(explicit("2*sin(x)")));
+        assert_eq!(
+            Parser::parse("x y"),
+            Err("Expected operator or eof behind 'x', found 'y' at column 3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_negative_power() {
+        // 负号的优先级低于乘方，负数和负的变量一样：-2^2 是 -(2^2)
+        let explicit = |expr: &str| Parser::parse(expr).unwrap();
+        let eval = |expr: &str| Parser::parse(expr).unwrap().eval(&mut Context::with_seed(0));
+        assert_eq!(Parser::parse("-2^2"), Ok(explicit("-(2^2)")));
+        assert_eq!(Parser::parse("2*-3^2"), Ok(explicit("2*-(3^2)")));
+        assert_eq!(Parser::parse("2^-3^2"), Ok(explicit("2^-(3^2)")));
+        assert_eq!(eval("-2^2"), Ok(Value::Num(dec!(-4))));
+        assert_eq!(eval("(-2)^2"), Ok(Value::Num(dec!(4))));
+        assert_eq!(eval("sum(k, 2, 2, -k^2)"), eval("-2^2"));
+        // 指数里的负数仍然合并到数字里
+        assert_eq!(
+            Parser::to_rpn("-2^2 + 2^-2", Numerals::Ascii),
+            Ok("2 2 ^ neg 2 -2 ^ +".to_string())
+        );
+    }
+
+    #[test]
+    fn test_prefix_sqrt() {
+        // √ 和取负一样是前缀运算符，只作用于紧跟的操作数，所以 √x^2 是 √(x^2)
+        let unicode = |expr: &str| {
+            Parser::parse_with(expr, Numerals::Unicode, DEFAULT_MAX_DEPTH)
+        };
+        let explicit = |expr: &str| Parser::parse(expr).unwrap();
+        assert_eq!(unicode("√4"), Ok(explicit("sqrt(4)")));
+        assert_eq!(unicode("√x^2"), Ok(explicit("sqrt(x^2)")));
+        assert_eq!(unicode("2√x"), Ok(explicit("2*sqrt(x)")));
+        assert_eq!(unicode("√√16"), Ok(explicit("sqrt(sqrt(16))")));
+        assert_eq!(unicode("√-4"), Ok(explicit("sqrt(-4)")));
+        assert_eq!(unicode("-√x + √(1 + x)"), Ok(explicit("-sqrt(x) + sqrt(1 + x)")));
+        assert_eq!(unicode("2^√4*3"), Ok(explicit("2^sqrt(4)*3")));
+        assert_eq!(
+            Parser::to_rpn("√x^2 + 1", Numerals::Unicode),
+            Ok("x 2 ^ sqrt 1 +".to_string())
+        );
+        assert_eq!(
+            unicode("x√2"),
+            Err("Expected operator or eof behind 'x', found '√' at column 2".to_string())
+        );
+        assert_eq!(unicode("√"), Err("Insufficient operands for operator".to_string()));
+    }
+
+    #[test]
+    fn test_rpn() {
+        let rpn = |expr: &str| Parser::to_rpn(expr, Numerals::Ascii).unwrap();
+        assert_eq!(rpn("3*(4 + 5) - 2^3"), "3 4 5 + * 2 3 ^ -");
+        assert_eq!(rpn("-x^2 + -3"), "x 2 ^ neg 3 -");
+        assert_eq!(rpn("2*-3"), "2 -3 *");
+        assert_eq!(rpn("gcd(12, 18, 8)*sin(x)"), "12 18 8 gcd:3 x sin *");
+        assert_eq!(rpn("sum(k, 1, n, [k, 2])"), "k 1 n k 2 [2] sum");
+        // 逆波兰式读回之后和原来的中缀表达式得到相同的语法树
+        for expr in [
+            "3*(4 + 5) - 2^3",
+            "-x^2 + -3",
+            "2*-3",
+            "2^3^2",
+            "gcd(12, 18, 8)*sin(x)",
+            "sum(k, 1, n, [k, 2])",
+            "solve(x^2 = 2, x, 0, 2)",
+            "[[1, 2], [3, 4]]",
+            "rand()",
+        ] {
+            assert_eq!(Parser::parse_rpn(&rpn(expr), DEFAULT_MAX_DEPTH), Parser::parse(expr), "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_error_columns() {
+        // 列号按字符计数，指向出错的 token，basic_check 和 get_rpn 的错误都带有列号
+        let err = |expr| Parser::parse(expr).unwrap_err();
+        assert_eq!(err("1 + ()"), "Empty parentheses at column 6");
+        assert_eq!(err("2 * (3 + 4"), "Unmatched brackets at column 5");
+        assert_eq!(err("[1, 2)"), "Unmatched brackets at column 6");
+        assert_eq!(err("1 - -(2"), "Unmatched brackets at column 6");
+        assert_eq!(err("2 + foo(1)"), "Unknown function: 'foo' at column 5");
+        assert_eq!(err("sin(1) cos(2)"), "Expected operator or eof behind ')', found 'cos' at column 8");
+        assert_eq!(
+            Parser::parse_with("１２ + * 3", Numerals::Unicode, DEFAULT_MAX_DEPTH),
+            Err("Expected num or parenthesis behind '+', found operator: '*' at column 6".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_rpn_errors() {
+        let err = |expr: &str| Parser::parse_rpn(expr, DEFAULT_MAX_DEPTH).unwrap_err();
+        assert_eq!(Parser::parse_rpn("3 4 + 2 *", DEFAULT_MAX_DEPTH), Parser::parse("(3 + 4)*2"));
+        assert_eq!(err("3 +"), "Insufficient operands for operator");
+        assert_eq!(err("3 4"), "Malformed expression");
+        assert_eq!(err(""), "Malformed expression");
+        assert_eq!(
+            err("1 2 3 gcd"),
+            "Function 'gcd' expects at least 2 argument(s), write 'gcd:n' with the argument count"
+        );
+        assert_eq!(err("1 gcd:1"), "Function 'gcd' expects at least 2 argument(s), found 1");
+        assert_eq!(err("1 2 gcd:x"), "Expected an argument count in 'gcd:x'");
+        assert_eq!(err("1 2 [x]"), "Expected an element count in '[x]'");
+        assert_eq!(err("1..2"), "Invalid number: '1..2'");
+        assert_eq!(err("1 2 %"), "Unexpected '%' in RPN expression");
+        assert_eq!(err("1 [3]"), "Insufficient elements for matrix");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let err = |expr: &str| Parser::parse(expr).unwrap_err();
+        let deep = "Expression nesting exceeds 500 levels";
+        // 很深的嵌套返回错误而不是耗尽调用栈
+        assert_eq!(err(&format!("{}2", "2^".repeat(100_000))), deep);
+        assert_eq!(err(&format!("{}1{}", "sqrt(".repeat(100_000), ")".repeat(100_000))), deep);
+        assert_eq!(err(&format!("{}1{}", "[".repeat(100_000), "]".repeat(100_000))), deep);
+        assert_eq!(err(&format!("{}1{}", "(1 - ".repeat(100_000), ")".repeat(100_000))), deep);
+        let rpn = format!("1{}", " neg".repeat(100_000));
+        assert_eq!(Parser::parse_rpn(&rpn, DEFAULT_MAX_DEPTH).unwrap_err(), deep);
+        // 括号本身不增加深度
+        let parens = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(Parser::parse(&parens), Ok(Ast(Node::Num(Decimal::ONE))));
+        // 左结合的运算符连成的链不算嵌套，链的长度也不受限制
+        assert!(Parser::parse(&vec!["1"; 100_000].join(" + ")).is_ok());
+        assert!(Parser::parse(&vec!["x"; 100_000].join("*")).is_ok());
+        assert!(Parser::parse(&vec!["1"; 100_000].join(" / ")).is_ok());
+        assert!(Parser::parse_with(&vec!["1"; 1000].join(" - "), Numerals::Ascii, 2).is_ok());
+        // 叶子节点的深度是 1，链的右边仍然算嵌套
+        assert!(Parser::parse_with("2*sin(x)", Numerals::Ascii, 3).is_ok());
+        assert_eq!(
+            Parser::parse_with("2*sin(x)", Numerals::Ascii, 2),
+            Err("Expression nesting exceeds 2 levels".to_string())
+        );
+        assert!(Parser::parse_with("sin(x) + 2", Numerals::Ascii, 2).is_ok());
+        assert_eq!(
+            Parser::parse_with("1 + (2 + 3)", Numerals::Ascii, 2),
+            Err("Expression nesting exceeds 2 levels".to_string())
+        );
+    }
 }
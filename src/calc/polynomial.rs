@@ -0,0 +1,752 @@
+use std::cmp::Ordering;
+
+use rust_decimal::{Decimal, MathematicalOps};
+
+use crate::calc::{
+    ast::Node,
+    context::{Context, deep},
+    derivative::{contains_var, free_vars, substitute},
+    equation::{self, SolveError},
+    function::{Function, constant},
+    integer::{self, to_decimal},
+    simplify::{has_like_terms, simplify},
+    token::Operator,
+    value::{Value, checked, checked_div},
+};
+
+// 多项式的最高次数，避免 (x + 1)^100000 这样的表达式展开出巨大的结果
+const MAX_DEGREE: usize = 1000;
+// 常数项或者首项系数的因子太多时不再枚举有理根
+const MAX_DIVISORS: usize = 10_000;
+
+// 一元多项式，coefs[i] 是 var^i 的系数，最后一个系数不为 0，零多项式没有系数。
+// 系数是不含 var 的表达式，这样 collect 可以处理 a*x + b*x 这种含有其他变量的系数，
+// 除法、求根和因式分解要求系数都是数
+#[derive(Debug, PartialEq, Clone)]
+pub struct Polynomial {
+    var: String,
+    coefs: Vec<Node>,
+}
+
+fn bx(node: Node) -> Box<Node> {
+    Box::new(node)
+}
+
+fn is_zero(node: &Node) -> bool {
+    matches!(node, Node::Num(n) if n.is_zero())
+}
+
+// var^k
+fn power(var: &str, k: usize) -> Node {
+    match k {
+        0 => Node::Num(Decimal::ONE),
+        1 => Node::Var(var.to_string()),
+        _ => Node::Pow(
+            bx(Node::Var(var.to_string())),
+            bx(Node::Num(Decimal::from(k))),
+        ),
+    }
+}
+
+impl Polynomial {
+    fn new(var: &str, mut coefs: Vec<Node>) -> Polynomial {
+        while coefs.last().is_some_and(is_zero) {
+            coefs.pop();
+        }
+        Polynomial {
+            var: var.to_string(),
+            coefs,
+        }
+    }
+
+    pub fn from_decimals(var: &str, coefs: &[Decimal]) -> Polynomial {
+        Polynomial::new(var, coefs.iter().map(|c| Node::Num(*c)).collect())
+    }
+
+    // 把表达式转换为 var 的多项式，表达式只能由加、减、乘、除以不含 var 的式子和非负整数次幂构成
+    pub fn from_node(node: &Node, var: &str) -> Result<Polynomial, String> {
+        use Node::*;
+        if !contains_var(node, var) {
+            return Ok(Polynomial::new(var, vec![node.clone()]));
+        }
+        let poly = |node: &Node| Polynomial::from_node(node, var);
+        deep(|| match node {
+            Var(_) => Ok(Polynomial::new(
+                var,
+                vec![Num(Decimal::ZERO), Num(Decimal::ONE)],
+            )),
+            Add(lhs, rhs) => Ok(poly(lhs)?.add(&poly(rhs)?)),
+            Sub(lhs, rhs) => Ok(poly(lhs)?.add(&poly(rhs)?.neg())),
+            Neg(val) => Ok(poly(val)?.neg()),
+            Mul(lhs, rhs) => poly(lhs)?.mul(&poly(rhs)?),
+            Div(lhs, rhs) if !contains_var(rhs, var) => {
+                Ok(poly(lhs)?.map(|c| Div(bx(c.clone()), rhs.clone())))
+            }
+            Pow(base, exp) => match simplify((**exp).clone()) {
+                Num(n) if !n.is_sign_negative() && n.fract().is_zero() => {
+                    let n = usize::try_from(n).unwrap_or(usize::MAX);
+                    poly(base)?.pow(n)
+                }
+                _ => Err(format!("Expected a polynomial in '{var}', found '{node}'")),
+            },
+            _ => Err(format!("Expected a polynomial in '{var}', found '{node}'")),
+        })
+    }
+
+    // var^k 的系数
+    pub fn coef(&self, k: usize) -> Node {
+        self.coefs.get(k).cloned().unwrap_or(Node::Num(Decimal::ZERO))
+    }
+
+    // 零多项式的次数按 0 计算
+    pub fn degree(&self) -> usize {
+        self.coefs.len().saturating_sub(1)
+    }
+
+    fn map(&self, f: impl Fn(&Node) -> Node) -> Polynomial {
+        Polynomial::new(
+            &self.var,
+            self.coefs.iter().map(|c| simplify(f(c))).collect(),
+        )
+    }
+
+    fn neg(&self) -> Polynomial {
+        self.map(|c| Node::Neg(bx(c.clone())))
+    }
+
+    fn add(&self, other: &Polynomial) -> Polynomial {
+        let len = self.coefs.len().max(other.coefs.len());
+        let coefs = (0..len)
+            .map(|i| match (self.coefs.get(i), other.coefs.get(i)) {
+                (Some(a), Some(b)) => simplify(Node::Add(bx(a.clone()), bx(b.clone()))),
+                (Some(c), None) | (None, Some(c)) => c.clone(),
+                (None, None) => unreachable!(),
+            })
+            .collect();
+        Polynomial::new(&self.var, coefs)
+    }
+
+    fn mul(&self, other: &Polynomial) -> Result<Polynomial, String> {
+        if self.coefs.is_empty() || other.coefs.is_empty() {
+            return Ok(Polynomial::new(&self.var, vec![]));
+        }
+        if self.degree() + other.degree() > MAX_DEGREE {
+            return Err(format!("Polynomial degree exceeds {MAX_DEGREE}"));
+        }
+        let mut coefs = vec![Node::Num(Decimal::ZERO); self.coefs.len() + other.coefs.len() - 1];
+        for (i, a) in self.coefs.iter().enumerate() {
+            for (j, b) in other.coefs.iter().enumerate() {
+                let term = Node::Mul(bx(a.clone()), bx(b.clone()));
+                coefs[i + j] = simplify(Node::Add(bx(coefs[i + j].clone()), bx(term)));
+            }
+        }
+        Ok(Polynomial::new(&self.var, coefs))
+    }
+
+    // 快速幂
+    fn pow(&self, mut n: usize) -> Result<Polynomial, String> {
+        if self.degree().saturating_mul(n) > MAX_DEGREE {
+            return Err(format!("Polynomial degree exceeds {MAX_DEGREE}"));
+        }
+        let mut base = self.clone();
+        let mut res = Polynomial::new(&self.var, vec![Node::Num(Decimal::ONE)]);
+        while n > 0 {
+            if n & 1 == 1 {
+                res = res.mul(&base)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                base = base.mul(&base)?;
+            }
+        }
+        Ok(res)
+    }
+
+    // 按降幂排列的表达式，例如 x^3 + 3*x^2 + 3*x + 1，含有其他变量的系数保持为一个整体：(a + b)*x
+    pub fn to_node(&self) -> Node {
+        let terms = self
+            .coefs
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, c)| !is_zero(c));
+        let terms = terms.map(|(k, c)| Node::Mul(bx(c.clone()), bx(power(&self.var, k))));
+        let sum = terms.reduce(|acc, term| Node::Add(bx(acc), bx(term)));
+        simplify(sum.unwrap_or(Node::Num(Decimal::ZERO)))
+    }
+
+    fn numeric(&self) -> Result<Vec<Decimal>, String> {
+        self.coefs
+            .iter()
+            .map(|c| match c {
+                Node::Num(n) => Ok(*n),
+                _ => Err(format!(
+                    "Expected a polynomial with numeric coefficients, found '{}'",
+                    self.to_node()
+                )),
+            })
+            .collect()
+    }
+}
+
+// 把上下文中已经有值的变量（例如 sum 的循环变量）替换成它的值
+pub fn bind(node: &Node, ctx: &Context) -> Node {
+    free_vars(node)
+        .iter()
+        .fold(node.clone(), |node, var| match ctx.var(var) {
+            Some(Value::Num(n)) => substitute(&node, var, &Node::Num(*n)),
+            _ => node,
+        })
+}
+
+// 表达式中的自由变量，不包括 pi、e 这样的常数
+pub fn variables(node: &Node) -> Vec<String> {
+    let mut vars = free_vars(node);
+    vars.retain(|var| constant(var).is_none());
+    vars
+}
+
+// 多项式的变量，所有表达式合起来只能有一个变量
+fn single_var(nodes: &[&Node]) -> Result<String, String> {
+    let mut vars: Vec<String> = nodes.iter().flat_map(|node| variables(node)).collect();
+    vars.sort();
+    vars.dedup();
+    match &vars[..] {
+        [var] => Ok(var.clone()),
+        [] => Err(format!(
+            "Expected a polynomial in one variable, found constant '{}'",
+            nodes[0]
+        )),
+        _ => Err(format!(
+            "Expected a polynomial in one variable, found variables '{}'",
+            vars.join("', '")
+        )),
+    }
+}
+
+fn one_var_poly(node: &Node, var: &str) -> Result<Vec<Decimal>, String> {
+    Polynomial::from_node(&simplify(node.clone()), var)?.numeric()
+}
+
+// 把多项式以及各项系数中的其他变量全部展开，例如 (x + a)^2 => 2*a*x + a^2 + x^2
+pub fn expand(node: &Node) -> Result<Node, String> {
+    let node = simplify(node.clone());
+    let Some(var) = variables(&node).into_iter().next() else {
+        return Ok(node);
+    };
+    let poly = Polynomial::from_node(&node, &var)?;
+    let mut terms = Vec::new();
+    for (k, coef) in poly.coefs.iter().enumerate() {
+        // 系数无法展开（例如 sin(a)）时保持原样
+        let coef = expand(coef).unwrap_or_else(|_| coef.clone());
+        for term in summands(coef) {
+            terms.push(Node::Mul(bx(term), bx(power(&var, k))));
+        }
+    }
+    let sum = terms
+        .into_iter()
+        .reduce(|acc, term| Node::Add(bx(acc), bx(term)));
+    let res = simplify(sum.unwrap_or(Node::Num(Decimal::ZERO)));
+    // 系数超出 Decimal 的范围时同类项无法合并，例如 (x + 1)^100 中的 C(100, 50)
+    if has_like_terms(&res) {
+        return Err(format!("Coefficient out of range when expanding '{node}'"));
+    }
+    Ok(res)
+}
+
+// 把和式拆成带符号的各项
+fn summands(mut node: Node) -> Vec<Node> {
+    let negate = |terms: Vec<Node>| terms.into_iter().map(|term| Node::Neg(bx(term))).collect();
+    deep(|| match &mut node {
+        Node::Add(lhs, rhs) => [summands(lhs.take()), summands(rhs.take())].concat(),
+        Node::Sub(lhs, rhs) => [summands(lhs.take()), negate(summands(rhs.take()))].concat(),
+        Node::Neg(val) => negate(summands(val.take())),
+        _ => vec![node],
+    })
+}
+
+// 按 var 的幂合并同类项，其他变量留在系数里，例如 a*x + b*x + c => (a + b)*x + c
+pub fn collect(node: &Node, var: &str) -> Result<Node, String> {
+    Ok(Polynomial::from_node(&simplify(node.clone()), var)?.to_node())
+}
+
+// 带余除法 p = q * d + r，r 的次数小于 d 的次数，返回 (q, r)
+pub fn divide(p: &Node, d: &Node) -> Result<(Node, Node), String> {
+    let var = single_var(&[p, d])?;
+    let (mut rem, d) = (one_var_poly(p, &var)?, one_var_poly(d, &var)?);
+    let Some(lead) = d.last() else {
+        return Err("Division by zero".to_string());
+    };
+    let mut quot = vec![Decimal::ZERO; (rem.len() + 1).saturating_sub(d.len())];
+    while rem.len() >= d.len() {
+        let k = rem.len() - d.len();
+        let c = checked_div(*rem.last().unwrap(), *lead)?;
+        quot[k] = c;
+        for (i, b) in d.iter().enumerate() {
+            let term = checked(c.checked_mul(*b), Operator::Mul)?;
+            rem[k + i] = checked(rem[k + i].checked_sub(term), Operator::Sub)?;
+        }
+        // 首项已经消去，舍入误差留下的微小余数也一起去掉
+        rem.pop();
+        while rem.last().is_some_and(|c| c.round_dp(24).is_zero()) {
+            rem.pop();
+        }
+    }
+    let round = |coefs: Vec<Decimal>| -> Vec<Decimal> {
+        coefs
+            .into_iter()
+            .map(|c| c.round_dp(24).normalize())
+            .collect()
+    };
+    Ok((
+        Polynomial::from_decimals(&var, &round(quot)).to_node(),
+        Polynomial::from_decimals(&var, &round(rem)).to_node(),
+    ))
+}
+
+// 把数值系数乘上 10 的幂并除以所有系数的最大公约数，化为首项系数为正的本原整系数多项式，
+// 返回整系数和比例 c，原多项式 = c * 整系数多项式。整数溢出时返回 None
+fn primitive(coefs: &[Decimal]) -> Option<(Vec<i128>, Decimal)> {
+    let coefs: Vec<Decimal> = coefs.iter().map(|c| c.normalize()).collect();
+    let scale = coefs.iter().map(Decimal::scale).max()?;
+    let ints = coefs
+        .iter()
+        .map(|c| {
+            c.mantissa()
+                .checked_mul(10i128.checked_pow(scale - c.scale())?)
+        })
+        .collect::<Option<Vec<i128>>>()?;
+    let mut g = ints.iter().fold(0, |g, c| integer::gcd(g, *c));
+    if *ints.last()? < 0 {
+        g = -g;
+    }
+    let c = Decimal::try_from_i128_with_scale(g, scale).ok()?;
+    Some((ints.into_iter().map(|a| a / g).collect(), c.normalize()))
+}
+
+// n 的所有正因子，因子太多时返回 None
+fn divisors(n: i128) -> Option<Vec<i128>> {
+    let mut divisors = vec![1];
+    let mut primes = integer::factor(n.abs()).ok()?;
+    primes.retain(|p| *p > 1);
+    primes.dedup_by_key(|p| *p);
+    for p in primes {
+        let mut power = p;
+        let count = divisors.len();
+        while n % power == 0 {
+            for i in 0..count {
+                divisors.push(divisors[i] * power);
+            }
+            if divisors.len() > MAX_DIVISORS {
+                return None;
+            }
+            let Some(next) = power.checked_mul(p) else {
+                break;
+            };
+            power = next;
+        }
+    }
+    Some(divisors)
+}
+
+// q^n * f(p/q) = Σ a_i p^i q^(n-i)，整数溢出时返回 None
+fn eval_scaled(ints: &[i128], p: i128, q: i128) -> Option<i128> {
+    let n = ints.len() - 1;
+    let (mut acc, mut q_pow) = (ints[n], 1i128);
+    for i in (0..n).rev() {
+        q_pow = q_pow.checked_mul(q)?;
+        acc = acc
+            .checked_mul(p)?
+            .checked_add(ints[i].checked_mul(q_pow)?)?;
+    }
+    Some(acc)
+}
+
+// 除以 (q*x - p)，p/q 必须是根，这时商也是整系数的：b[k-1] = (a[k] + p * b[k]) / q
+fn deflate(ints: &[i128], p: i128, q: i128) -> Option<Vec<i128>> {
+    let n = ints.len() - 1;
+    let mut quot = vec![0; n];
+    quot[n - 1] = ints[n] / q;
+    for k in (1..n).rev() {
+        quot[k - 1] = ints[k].checked_add(p.checked_mul(quot[k])?)? / q;
+    }
+    Some(quot)
+}
+
+// 按有理根定理寻找本原整系数多项式的有理根：p/q 的分子整除常数项，分母整除首项系数。
+// 找到的根按重数重复出现，ints 被约去这些根对应的一次因式
+fn rational_roots(ints: &mut Vec<i128>) -> Vec<(i128, i128)> {
+    let mut roots = Vec::new();
+    while ints.len() > 1 && ints[0] == 0 {
+        ints.remove(0);
+        roots.push((0, 1));
+    }
+    if ints.len() <= 1 {
+        return roots;
+    }
+    let (Some(ps), Some(qs)) = (divisors(ints[0]), divisors(*ints.last().unwrap())) else {
+        return roots;
+    };
+    for q in &qs {
+        for p in ps.iter().flat_map(|p| [-p, *p]) {
+            if integer::gcd(p, *q) != 1 {
+                continue;
+            }
+            while ints.len() > 1 && eval_scaled(ints, p, *q) == Some(0) {
+                let Some(quot) = deflate(ints, p, *q) else {
+                    break;
+                };
+                *ints = quot;
+                roots.push((p, *q));
+            }
+        }
+    }
+    roots.sort_by(|a, b| compare(*a, *b));
+    roots
+}
+
+fn compare((p1, q1): (i128, i128), (p2, q2): (i128, i128)) -> Ordering {
+    match (p1.checked_mul(q2), p2.checked_mul(q1)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => Ordering::Equal,
+    }
+}
+
+fn int(n: i128) -> Result<Node, String> {
+    Ok(Node::Num(to_decimal(n)?))
+}
+
+// 分数 p/q，不能整除的时候保留为 p/q 的形式
+fn fraction(p: i128, q: i128) -> Result<Node, String> {
+    Ok(simplify(Node::Div(bx(int(p)?), bx(int(q)?))))
+}
+
+// n = k^2 * m，m 不含平方因子
+fn square_part(n: i128) -> Option<(i128, i128)> {
+    let (mut k, mut m) = (1i128, 1i128);
+    let primes = integer::factor(n).ok()?;
+    for group in primes.chunk_by(|a, b| a == b) {
+        let p = group[0];
+        for _ in 0..group.len() / 2 {
+            k = k.checked_mul(p)?;
+        }
+        if group.len() % 2 == 1 {
+            m = m.checked_mul(p)?;
+        }
+    }
+    Some((k, m))
+}
+
+// a*x^2 + b*x + c = 0 的精确实根 (-b ± k*sqrt(m)) / 2a，a > 0，从小到大排列。整数溢出时返回 None
+fn quadratic_roots(c: i128, b: i128, a: i128) -> Result<Option<Vec<Node>>, String> {
+    let ac = a.checked_mul(c).and_then(|ac| ac.checked_mul(4));
+    let d = b
+        .checked_mul(b)
+        .zip(ac)
+        .and_then(|(bb, ac)| bb.checked_sub(ac));
+    let (Some(d), Some(den)) = (d, a.checked_mul(2)) else {
+        return Ok(None);
+    };
+    if d < 0 {
+        return Ok(Some(vec![]));
+    }
+    if d == 0 {
+        return Ok(Some(vec![fraction(-b, den)?]));
+    }
+    let Some((k, m)) = square_part(d) else {
+        return Ok(None);
+    };
+    if m == 1 {
+        return Ok(Some(vec![fraction(-b - k, den)?, fraction(-b + k, den)?]));
+    }
+    let g = integer::gcd(integer::gcd(b, k), den);
+    let (b, k, den) = (b / g, k / g, den / g);
+    let root = |op: fn(Box<Node>, Box<Node>) -> Node| -> Result<Node, String> {
+        let sqrt = Node::Call(Function::Sqrt, vec![int(m)?]);
+        let num = op(bx(int(-b)?), bx(Node::Mul(bx(int(k)?), bx(sqrt))));
+        Ok(simplify(Node::Div(bx(num), bx(int(den)?))))
+    };
+    Ok(Some(vec![root(Node::Sub)?, root(Node::Add)?]))
+}
+
+// 三次及以上的部分用数值方法求根（二次的直接用求根公式），所有实根都在 |x| <= 1 + max|a_i / a_n| 之内，也都在
+// |x| <= 2 * max|a_(n-k) / a_n|^(1/k) 之内（Fujiwara 界），取两者中较小的一个。常数项很大时
+// （x^3 + 5e28）后者开方之后小得多，搜索区间不会接近 Decimal::MAX
+// 奇数次的多项式一定有实根，数值方法没有找到根时报告错误而不是说没有实根
+fn numeric_roots(coefs: &[Decimal], var: &str, ctx: &mut Context) -> Result<Vec<Node>, String> {
+    if let [c, b, a] = coefs {
+        return decimal_quadratic_roots(*c, *b, *a);
+    }
+    let n = coefs.len() - 1;
+    let lead = coefs[n];
+    let (mut cauchy, mut fujiwara) = (Decimal::ZERO, Decimal::ZERO);
+    for (k, c) in coefs[..n].iter().rev().enumerate() {
+        let ratio = checked_div(*c, lead)?.abs();
+        cauchy = cauchy.max(ratio);
+        fujiwara = fujiwara.max(root_bound(ratio, k as u32 + 1));
+    }
+    let bound = [Some(cauchy), fujiwara.checked_mul(Decimal::TWO)]
+        .into_iter()
+        .filter_map(|bound| bound?.checked_add(Decimal::ONE))
+        .min();
+    let bound = checked(bound, Operator::Add)?;
+    let f = Polynomial::from_decimals(var, coefs).to_node();
+    let eq = Node::Eq(bx(f.clone()), bx(Node::Num(Decimal::ZERO)));
+    match equation::solve(&eq, var, -bound, bound, ctx) {
+        Ok(roots) => Ok(roots.into_iter().map(Node::Num).collect()),
+        Err(SolveError::NoRoot(..)) if n.is_multiple_of(2) => Ok(vec![]),
+        Err(SolveError::NoRoot(..)) => Err(format!("Failed to locate the real roots of '{f}'")),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// 整数的判别式溢出时，在 Decimal 中解 a*x^2 + b*x + c = 0。化为 x^2 + 2m*x + q = 0，
+// |m| 很大时判别式 m^2 - q 按 m^2 * (1 - q/m/m) 计算，不会溢出。绝对值较大的根
+// -(m + sign(m)*sqrt(m^2 - q)) 没有相消的误差，另一个根由韦达定理 q / x1 得到
+fn decimal_quadratic_roots(c: Decimal, b: Decimal, a: Decimal) -> Result<Vec<Node>, String> {
+    let m = checked_div(b, a)? / Decimal::TWO;
+    let q = checked_div(c, a)?;
+    let sqrt = if m.abs() >= Decimal::ONE {
+        let rest = Decimal::ONE.checked_sub(checked_div(checked_div(q, m)?, m)?);
+        let sqrt = checked(rest, Operator::Sub)?.sqrt();
+        sqrt.map(|sqrt| checked(sqrt.checked_mul(m.abs()), Operator::Mul)).transpose()?
+    } else {
+        checked(m.checked_mul(m).and_then(|mm| mm.checked_sub(q)), Operator::Sub)?.sqrt()
+    };
+    let Some(sqrt) = sqrt else {
+        return Ok(vec![]);
+    };
+    if sqrt.is_zero() {
+        return Ok(vec![Node::Num((-m).normalize())]);
+    }
+    let sqrt = if m.is_sign_negative() { -sqrt } else { sqrt };
+    let far = checked(m.checked_add(sqrt), Operator::Add)?;
+    let (x1, x2) = (-far, checked_div(q, -far)?);
+    Ok(vec![Node::Num(x1.min(x2).normalize()), Node::Num(x1.max(x2).normalize())])
+}
+
+// 不小于 ratio 的 k 次方根的 2 的幂，只用来估计根的范围，不需要精确的开方
+fn root_bound(ratio: Decimal, k: u32) -> Decimal {
+    let mut root = Decimal::ONE;
+    loop {
+        // 乘方溢出说明 root^k 已经超过了 ratio
+        let power = (1..k).try_fold(root, |acc, _| acc.checked_mul(root));
+        if power.is_none_or(|power| power >= ratio) {
+            return root;
+        }
+        match root.checked_mul(Decimal::TWO) {
+            Some(next) => root = next,
+            None => return Decimal::MAX,
+        }
+    }
+}
+
+// 多项式的所有实根，从小到大排列，重根只出现一次。有理根和二次因式的根是精确的（例如 sqrt(2) + 1），
+// 其余的根是数值解
+pub fn roots(node: &Node, ctx: &mut Context) -> Result<Vec<Node>, String> {
+    let var = single_var(&[node])?;
+    let coefs = one_var_poly(node, &var)?;
+    if coefs.is_empty() {
+        return Err(format!("Every number is a root of '{node}'"));
+    }
+    let mut roots = Vec::new();
+    match primitive(&coefs) {
+        Some((mut ints, _)) => {
+            for (p, q) in rational_roots(&mut ints) {
+                roots.push(fraction(p, q)?);
+            }
+            match ints[..] {
+                [_] => {}
+                [c, b] => roots.push(fraction(-c, b)?),
+                [c, b, a] => match quadratic_roots(c, b, a)? {
+                    Some(res) => roots.extend(res),
+                    None => roots.extend(numeric_roots(&to_decimals(&ints)?, &var, ctx)?),
+                },
+                _ => roots.extend(numeric_roots(&to_decimals(&ints)?, &var, ctx)?),
+            }
+        }
+        None => roots.extend(numeric_roots(&coefs, &var, ctx)?),
+    }
+    let mut keyed = Vec::new();
+    for root in roots {
+        let value = root.eval(ctx)?.into_num(Function::Roots)?;
+        keyed.push((value, root));
+    }
+    keyed.sort_by_key(|(value, _)| *value);
+    keyed.dedup_by(|a, b| a.1 == b.1);
+    Ok(keyed.into_iter().map(|(_, root)| root).collect())
+}
+
+fn to_decimals(ints: &[i128]) -> Result<Vec<Decimal>, String> {
+    ints.iter().map(|n| to_decimal(*n)).collect()
+}
+
+// 在有理数范围内分解因式：常数 * 有理根对应的一次因式的幂 * 没有有理根的部分，
+// 例如 2*x^3 - 2*x => 2*(x + 1)*x*(x - 1)
+pub fn factor(node: &Node) -> Result<Node, String> {
+    let var = single_var(&[node])?;
+    let coefs = one_var_poly(node, &var)?;
+    let Some((mut ints, c)) = primitive(&coefs) else {
+        return Ok(Polynomial::from_decimals(&var, &coefs).to_node());
+    };
+    let roots = rational_roots(&mut ints);
+    let mut factors = Vec::new();
+    for group in roots.chunk_by(|a, b| a == b) {
+        let (p, q) = group[0];
+        let linear = Polynomial::from_decimals(&var, &[to_decimal(-p)?, to_decimal(q)?]).to_node();
+        factors.push(match group.len() {
+            1 => linear,
+            n => Node::Pow(bx(linear), bx(Node::Num(Decimal::from(n)))),
+        });
+    }
+    if ints.len() > 1 {
+        factors.push(Polynomial::from_decimals(&var, &to_decimals(&ints)?).to_node());
+    }
+    if factors.is_empty() || c == Decimal::NEGATIVE_ONE {
+        // 常数本身或者 -1 * ... 写成 -(...)
+        let product = factors
+            .into_iter()
+            .reduce(|acc, f| Node::Mul(bx(acc), bx(f)));
+        return Ok(match product {
+            None => Node::Num(c),
+            Some(product) => Node::Neg(bx(product)),
+        });
+    }
+    if c != Decimal::ONE {
+        factors.insert(0, Node::Num(c));
+    }
+    Ok(factors
+        .into_iter()
+        .reduce(|acc, f| Node::Mul(bx(acc), bx(f)))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{ast::Ast, parser::Parser};
+
+    fn parse(expr: &str) -> Node {
+        Parser::parse(expr).unwrap().0
+    }
+
+    fn show(res: Result<Node, String>) -> Result<String, String> {
+        res.map(|node| node.to_string())
+    }
+
+    #[test]
+    fn test_expand_and_collect() {
+        let expand = |expr: &str| show(expand(&parse(expr)));
+        assert_eq!(expand("(x + 1)^3"), Ok("x^3 + 3*x^2 + 3*x + 1".to_string()));
+        assert_eq!(expand("(x - 1)*(x + 1) - x^2"), Ok("-1".to_string()));
+        assert_eq!(expand("(2*x - 3)^2/4"), Ok("x^2 - 3*x + 2.25".to_string()));
+        assert_eq!(expand("(x + a)^2"), Ok("2*a*x + a^2 + x^2".to_string()));
+        assert_eq!(
+            expand("sin(x)*(x + 1)"),
+            Err("Expected a polynomial in 'x', found 'sin(x)'".to_string())
+        );
+        assert_eq!(
+            expand("x^(1/2)"),
+            Err("Expected a polynomial in 'x', found 'x^0.5'".to_string())
+        );
+        assert_eq!(
+            expand("(x + 1)^2000"),
+            Err("Polynomial degree exceeds 1000".to_string())
+        );
+        // C(100, 50) 超出了 Decimal 的范围
+        assert_eq!(
+            expand("(x + 1)^100"),
+            Err("Coefficient out of range when expanding '(x + 1)^100'".to_string())
+        );
+        assert_eq!(
+            expand("(x + a)^100"),
+            Err("Coefficient out of range when expanding '(a + x)^100'".to_string())
+        );
+
+        let collect = |expr: &str| show(collect(&parse(expr), "x"));
+        assert_eq!(collect("a*x + b*x + c"), Ok("(a + b)*x + c".to_string()));
+        assert_eq!(collect("x*(x + 2) - 2*x"), Ok("x^2".to_string()));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // 多项式转换成的表达式可以重新解析成同一个多项式
+        for expr in ["(x + 1)^3", "(2*x - 3)^2/4", "x^5 - x/3", "7"] {
+            let node = parse(expr);
+            let poly = Polynomial::from_node(&node, "x").unwrap();
+            let Ast(printed) = Parser::parse(&poly.to_node().to_string()).unwrap();
+            assert_eq!(Polynomial::from_node(&printed, "x"), Ok(poly));
+        }
+    }
+
+    #[test]
+    fn test_divide() {
+        let divide = |p: &str, d: &str| {
+            divide(&parse(p), &parse(d)).map(|(q, r)| (q.to_string(), r.to_string()))
+        };
+        assert_eq!(
+            divide("x^3 - 2*x^2 + 4", "x - 3"),
+            Ok(("x^2 + x + 3".to_string(), "13".to_string()))
+        );
+        assert_eq!(
+            divide("x^2 - 1", "2*x + 2"),
+            Ok(("0.5*x - 0.5".to_string(), "0".to_string()))
+        );
+        assert_eq!(divide("x", "x^2"), Ok(("0".to_string(), "x".to_string())));
+        assert_eq!(divide("x", "x - x"), Err("Division by zero".to_string()));
+        assert_eq!(
+            divide("x", "y"),
+            Err("Expected a polynomial in one variable, found variables 'x', 'y'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_roots_and_factor() {
+        let roots = |expr: &str| {
+            roots(&parse(expr), &mut Context::with_seed(0))
+                .map(|roots| roots.iter().map(Node::to_string).collect::<Vec<_>>())
+        };
+        assert_eq!(roots("2*x - 3"), Ok(vec!["1.5".to_string()]));
+        assert_eq!(
+            roots("x^2 - 2*x - 1"),
+            Ok(vec!["-sqrt(2) + 1".to_string(), "sqrt(2) + 1".to_string()])
+        );
+        assert_eq!(
+            roots("3*x^2 - 1"),
+            Ok(vec!["-(sqrt(3)/3)".to_string(), "sqrt(3)/3".to_string()])
+        );
+        assert_eq!(roots("x^2 + 1"), Ok(vec![]));
+        assert_eq!(
+            roots("(3*x - 1)^2*(x + 2)"),
+            Ok(vec!["-2".to_string(), "1/3".to_string()])
+        );
+        // 没有有理根的三次多项式使用数值解
+        let res = roots("x^3 - 2").unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].starts_with("1.25992104989487316476"));
+        // 常数项很大时根的范围不会溢出
+        let res = roots("x^3 + 50000000000000000000000000000").unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].starts_with("-3684031498.6"), "{}", res[0]);
+        // 整数判别式溢出时用 Decimal 的求根公式，两个相差很大的根都不会丢
+        assert_eq!(
+            roots("x^2 + 100000000000000000000*x + 1"),
+            Ok(vec!["-100000000000000000000".to_string(), "-0.00000000000000000001".to_string()])
+        );
+        assert_eq!(roots("x^2 + 100000000000000000000"), Ok(vec![]));
+        assert_eq!(
+            roots("x - x"),
+            Err("Every number is a root of 'x - x'".to_string())
+        );
+
+        let factor = |expr: &str| show(factor(&parse(expr)));
+        assert_eq!(factor("2*x^3 - 2*x"), Ok("2*(x + 1)*x*(x - 1)".to_string()));
+        assert_eq!(factor("x^2 - 4*x + 4"), Ok("(x - 2)^2".to_string()));
+        assert_eq!(
+            factor("1 - 3*x + 2*x^2"),
+            Ok("(2*x - 1)*(x - 1)".to_string())
+        );
+        assert_eq!(factor("-x^2 + 2"), Ok("-(x^2 - 2)".to_string()));
+        assert_eq!(factor("0.5*x^2 + 0.5*x"), Ok("0.5*(x + 1)*x".to_string()));
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt::Display;
+
+use crate::calc::{ast::Ast, ast::Node, context::deep, token::Operator};
+
+// 节点作为运算数时的优先级，数字、变量、函数调用这些不可分割的节点优先级最高
+fn precedence(node: &Node) -> u8 {
+    match node {
+        Node::Eq(..) => Operator::Eq.priority(),
+        Node::Add(..) | Node::Sub(..) => Operator::Add.priority(),
+        Node::Mul(..) | Node::Div(..) => Operator::Mul.priority(),
+        Node::Pow(..) => Operator::Pow.priority(),
+        Node::Neg(_) => Operator::Neg.priority(),
+        _ => Operator::Pow.priority() + 1,
+    }
+}
+
+fn paren(text: String, needed: bool) -> String {
+    if needed { format!("({text})") } else { text }
+}
+
+fn binary(f: &mut std::fmt::Formatter<'_>, op: Operator, lhs: &Node, rhs: &Node) -> std::fmt::Result {
+    let pri = op.priority();
+    let rhs_text = rhs.to_string();
+    // 左边的运算数：优先级更低的要加括号，右结合的运算符（乘方）左边同优先级的也要加括号，
+    // 负数作为底数也加上括号，避免把 (-2)^2 看成 -(2^2)
+    let lhs_paren = precedence(lhs) < pri
+        || (precedence(lhs) == pri && !op.is_left_associative())
+        || (op == Operator::Pow && lhs.to_string().starts_with('-'));
+    // 右边的运算数：优先级更低的要加括号，左结合的运算符右边同优先级的也要加括号；
+    // 加减号后面的负号会和加减号合并（a - -b 会被当成 a + b），所以也要加括号
+    let rhs_paren = precedence(rhs) < pri
+        || (precedence(rhs) == pri && op.is_left_associative())
+        || (pri == 1 && rhs_text.starts_with('-'));
+    // 左边直接写入 f，打印很长的链 1 + 2 + ... 时不会反复复制前面的部分
+    if lhs_paren {
+        write!(f, "({lhs})")?;
+    } else {
+        write!(f, "{lhs}")?;
+    }
+    let rhs_text = paren(rhs_text, rhs_paren);
+    if pri <= 1 {
+        write!(f, " {op} {rhs_text}")
+    } else {
+        write!(f, "{op}{rhs_text}")
+    }
+}
+
+fn list(f: &mut std::fmt::Formatter<'_>, nodes: &[&Node]) -> std::fmt::Result {
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{node}")?;
+    }
+    Ok(())
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Node::*;
+        deep(|| match self {
+            Add(lhs, rhs) => binary(f, Operator::Add, lhs, rhs),
+            Sub(lhs, rhs) => binary(f, Operator::Sub, lhs, rhs),
+            Mul(lhs, rhs) => binary(f, Operator::Mul, lhs, rhs),
+            Div(lhs, rhs) => binary(f, Operator::Div, lhs, rhs),
+            Pow(lhs, rhs) => binary(f, Operator::Pow, lhs, rhs),
+            Eq(lhs, rhs) => binary(f, Operator::Eq, lhs, rhs),
+            Neg(val) => {
+                let text = val.to_string();
+                // 负号后面紧跟数字会被合并成一个负数，紧跟负号会被合并成正号，这两种情况都需要括号
+                let needed = precedence(val) < Operator::Neg.priority()
+                    || text.starts_with(|c: char| c.is_ascii_digit() || c == '-');
+                write!(f, "-{}", paren(text, needed))
+            }
+            Num(num) => write!(f, "{num}"),
+            Var(name) => write!(f, "{name}"),
+            Matrix(elems) => {
+                write!(f, "[")?;
+                list(f, &elems.iter().collect::<Vec<_>>())?;
+                write!(f, "]")
+            }
+            Call(func, args) => {
+                write!(f, "{func}(")?;
+                list(f, &args.iter().collect::<Vec<_>>())?;
+                write!(f, ")")
+            }
+            Sum(var, from, to, body) | Prod(var, from, to, body) => {
+                let name = if matches!(self, Sum(..)) {
+                    "sum"
+                } else {
+                    "prod"
+                };
+                write!(f, "{name}({var}, ")?;
+                list(f, &[from, to, body])?;
+                write!(f, ")")
+            }
+        })
+    }
+}
+
+// 给每一个复合的运算数都加上括号，用来显示表达式是怎样分组的，例如 2^3^2 显示为 2^(3^2)
+pub fn parenthesized(node: &Node) -> String {
+    use Node::*;
+    let operand = |node: &Node| {
+        let text = parenthesized(node);
+        let needed = precedence(node) <= Operator::Pow.priority() || text.starts_with('-');
+        paren(text, needed)
+    };
+    let binary = |op: Operator, lhs: &Node, rhs: &Node| {
+        let (lhs, rhs) = (operand(lhs), operand(rhs));
+        if op.priority() <= 1 {
+            format!("{lhs} {op} {rhs}")
+        } else {
+            format!("{lhs}{op}{rhs}")
+        }
+    };
+    let list = |nodes: &[&Node]| {
+        let texts: Vec<String> = nodes.iter().map(|node| parenthesized(node)).collect();
+        texts.join(", ")
+    };
+    deep(|| match node {
+        Add(lhs, rhs) => binary(Operator::Add, lhs, rhs),
+        Sub(lhs, rhs) => binary(Operator::Sub, lhs, rhs),
+        Mul(lhs, rhs) => binary(Operator::Mul, lhs, rhs),
+        Div(lhs, rhs) => binary(Operator::Div, lhs, rhs),
+        Pow(lhs, rhs) => binary(Operator::Pow, lhs, rhs),
+        Eq(lhs, rhs) => binary(Operator::Eq, lhs, rhs),
+        // 数字也要加括号，否则 -(2) 会被合并成负数 -2
+        Neg(val) => format!("-{}", paren(parenthesized(val), !matches!(**val, Var(_) | Call(..)))),
+        Num(_) | Var(_) => node.to_string(),
+        Matrix(elems) => format!("[{}]", list(&elems.iter().collect::<Vec<_>>())),
+        Call(func, args) => format!("{func}({})", list(&args.iter().collect::<Vec<_>>())),
+        Sum(var, from, to, body) | Prod(var, from, to, body) => {
+            let name = if matches!(node, Sum(..)) { "sum" } else { "prod" };
+            format!("{name}({var}, {})", list(&[from, to, body]))
+        }
+    })
+}
+
+impl Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{context::Rng, function::Function, parser::Parser};
+
+    // 打印之后再解析，应该得到相同的 AST
+    fn round_trip(expr: &str) -> String {
+        let ast = Parser::parse(expr).unwrap();
+        let text = ast.to_string();
+        assert_eq!(
+            Parser::parse(&text),
+            Ok(ast),
+            "'{expr}' printed as '{text}'"
+        );
+        text
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        assert_eq!(round_trip("(1 + 2) * 3"), "(1 + 2)*3");
+        assert_eq!(round_trip("1 + (2 * 3)"), "1 + 2*3");
+        assert_eq!(round_trip("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(round_trip("(1 - 2) - 3"), "1 - 2 - 3");
+        assert_eq!(round_trip("2 ^ 3 ^ 2"), "2^3^2");
+        assert_eq!(round_trip("(2 ^ 3) ^ 2"), "(2^3)^2");
+        assert_eq!(round_trip("x / (y * z)"), "x/(y*z)");
+    }
+
+    #[test]
+    fn test_signs() {
+        assert_eq!(round_trip("-x^2"), "-x^2");
+        assert_eq!(round_trip("(-x)^2"), "(-x)^2");
+        assert_eq!(round_trip("(-2)^2"), "(-2)^2");
+        assert_eq!(round_trip("x - (-y)"), "x - (-y)");
+        assert_eq!(round_trip("x - (-2)"), "x - (-2)");
+        assert_eq!(round_trip("x * -y"), "x*-y");
+        assert_eq!(round_trip("-(x + 1)"), "-(x + 1)");
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(round_trip("det([[1, 2], [3, x]])"), "det([[1, 2], [3, x]])");
+        assert_eq!(round_trip("sum(k, 1, n, k^2)"), "sum(k, 1, n, k^2)");
+        assert_eq!(round_trip("-sin(x)"), "-sin(x)");
+    }
+
+    #[test]
+    fn test_parenthesized() {
+        let full = |expr: &str| parenthesized(&Parser::parse(expr).unwrap().0);
+        assert_eq!(full("2^3^2"), "2^(3^2)");
+        assert_eq!(full("1 - 2 - 3"), "(1 - 2) - 3");
+        assert_eq!(full("-x^2 + 2*-3"), "(-(x^2)) + (2*(-3))");
+        assert_eq!(full("-(2)"), "-(2)");
+        assert_eq!(full("sin(x*y) = 1"), "sin(x*y) = 1");
+    }
+
+    // 随机生成 parser 能够产生的 AST：方程只出现在最外层，sum 的第一个参数是变量
+    fn random_node(rng: &mut Rng, depth: u32) -> Node {
+        use Node::*;
+        const NUMS: [&str; 7] = ["0", "1", "2.5", "-3", "10", "0.125", "-0.5"];
+        const VARS: [&str; 4] = ["x", "y", "z", "e"];
+        let sub = |rng: &mut Rng| Box::new(random_node(rng, depth - 1));
+        match rng.below(if depth == 0 { 2 } else { 12 }) {
+            0 => Num(NUMS[rng.below(NUMS.len() as u128) as usize].parse().unwrap()),
+            1 => Var(VARS[rng.below(VARS.len() as u128) as usize].to_string()),
+            2 => Add(sub(rng), sub(rng)),
+            3 => Sub(sub(rng), sub(rng)),
+            4 => Mul(sub(rng), sub(rng)),
+            5 => Div(sub(rng), sub(rng)),
+            6 | 7 => Pow(sub(rng), sub(rng)),
+            8 => Neg(sub(rng)),
+            9 => Matrix((0..=rng.below(2)).map(|_| *sub(rng)).collect()),
+            10 => Call(Function::Gcd, vec![*sub(rng), *sub(rng)]),
+            _ => Sum("k".to_string(), sub(rng), sub(rng), sub(rng)),
+        }
+    }
+
+    #[test]
+    fn test_random_round_trip() {
+        let mut rng = Rng::new(2024);
+        for _ in 0..3000 {
+            let mut node = random_node(&mut rng, 4);
+            if rng.below(5) == 0 {
+                node = Node::Eq(Box::new(node), Box::new(random_node(&mut rng, 2)));
+            }
+            let ast = Ast(node);
+            for text in [ast.to_string(), parenthesized(&ast.0)] {
+                assert_eq!(Parser::parse(&text), Ok(ast.clone()), "printed as '{text}'");
+            }
+        }
+    }
+}
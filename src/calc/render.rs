@@ -0,0 +1,502 @@
+use rust_decimal::Decimal;
+
+use crate::calc::{
+    ast::{Ast, Node},
+    context::deep,
+    function::Function,
+    token::Operator,
+};
+
+// 把 AST 转换成排版语言（LaTeX、MathML）。括号的规则和 printer 相同，只是分数线本身就能分组，
+// 所以分子、分母和指数都不需要括号，而分数作为底数时需要括号
+trait Markup {
+    fn num(&self, num: Decimal) -> String;
+    fn var(&self, name: &str) -> String;
+    fn paren(&self, text: String) -> String;
+    fn infix(&self, op: Operator, lhs: String, rhs: String) -> String;
+    fn neg(&self, text: String) -> String;
+    fn frac(&self, num: String, den: String) -> String;
+    fn pow(&self, base: String, exp: String) -> String;
+    fn sqrt(&self, text: String) -> String;
+    fn call(&self, func: Function, args: Vec<String>) -> String;
+    fn matrix(&self, rows: Vec<Vec<String>>) -> String;
+    // sum 和 prod：∑_{var = from}^{to} body
+    fn series(&self, func: Function, var: &str, from: String, to: String, body: String) -> String;
+    // ∫_{from}^{to} body dvar
+    fn integral(&self, body: String, var: &str, from: String, to: String) -> String;
+    // d/dvar body，point 是求值的点
+    fn derivative(&self, body: String, var: &str, point: Option<String>) -> String;
+}
+
+// 排版时的优先级，分数线和不可分割的节点一样不需要外加括号
+fn precedence(node: &Node) -> u8 {
+    match node {
+        Node::Eq(..) => 0,
+        Node::Add(..) | Node::Sub(..) => 1,
+        Node::Mul(..) => 2,
+        Node::Neg(_) => 3,
+        Node::Pow(..) => 4,
+        _ => 5,
+    }
+}
+
+// 表达式排版出来是否以负号开头
+fn starts_negative(node: &Node) -> bool {
+    deep(|| match node {
+        Node::Num(num) => num.is_sign_negative(),
+        Node::Neg(_) => true,
+        Node::Add(lhs, _) | Node::Sub(lhs, _) | Node::Mul(lhs, _) => starts_negative(lhs),
+        _ => false,
+    })
+}
+
+// 求和、求积、积分和导数这些大运算符没有右边界，后面的内容都会被当成它的一部分。
+// 在某一点的导数以竖线结尾，已经有了右边界
+fn is_big(node: &Node) -> bool {
+    match node {
+        Node::Sum(..) | Node::Prod(..) | Node::Call(Function::Integrate, _) => true,
+        Node::Call(Function::Diff, args) => args.len() == 2,
+        _ => false,
+    }
+}
+
+// 表达式排版出来是否以没有加括号的大运算符结尾，这样的表达式后面不能直接跟别的运算符：
+// sum(k, 1, n, k)*x 要排版成 (∑ k)·x，而 x*sum(k, 1, n, k) 不需要括号
+fn ends_big(node: &Node) -> bool {
+    deep(|| match node {
+        node if is_big(node) => true,
+        Node::Add(_, rhs) | Node::Sub(_, rhs) | Node::Mul(_, rhs) => {
+            let pri = if matches!(node, Node::Mul(..)) { 2 } else { 1 };
+            precedence(rhs) > pri && !starts_negative(rhs) && ends_big(rhs)
+        }
+        Node::Neg(val) => precedence(val) > 2 && !starts_negative(val) && ends_big(val),
+        _ => false,
+    })
+}
+
+// 作为底数时不需要括号的节点
+fn is_atom(node: &Node) -> bool {
+    match node {
+        Node::Num(num) => !num.is_sign_negative(),
+        Node::Call(Function::Integrate | Function::Diff, _) => false,
+        Node::Var(_) | Node::Call(..) | Node::Matrix(_) => true,
+        _ => false,
+    }
+}
+
+fn render(node: &Node, m: &impl Markup) -> String {
+    use Node::*;
+    let wrap = |node: &Node, needed: bool| {
+        let text = render(node, m);
+        if needed { m.paren(text) } else { text }
+    };
+    let infix = |op: Operator, lhs: &Node, rhs: &Node| {
+        let pri = if op == Operator::Mul { 2 } else { 1 };
+        // 左边优先级更低的或者以大运算符结尾的要加括号；右边同优先级的也要加括号（保持原来的结合方式），
+        // 负号开头的也要加括号
+        let lhs = wrap(lhs, precedence(lhs) < pri || ends_big(lhs));
+        let rhs = wrap(rhs, precedence(rhs) <= pri || starts_negative(rhs));
+        m.infix(op, lhs, rhs)
+    };
+    deep(|| match node {
+        Add(lhs, rhs) => infix(Operator::Add, lhs, rhs),
+        Sub(lhs, rhs) => infix(Operator::Sub, lhs, rhs),
+        Mul(lhs, rhs) => infix(Operator::Mul, lhs, rhs),
+        Eq(lhs, rhs) => m.infix(Operator::Eq, render(lhs, m), render(rhs, m)),
+        Div(lhs, rhs) => m.frac(render(lhs, m), render(rhs, m)),
+        Pow(base, exp) => m.pow(wrap(base, !is_atom(base)), render(exp, m)),
+        Neg(val) => m.neg(wrap(val, precedence(val) <= 2 || starts_negative(val))),
+        Num(num) => m.num(*num),
+        Var(name) => m.var(name),
+        Matrix(elems) => {
+            let rows = if elems.iter().all(|elem| matches!(elem, Matrix(_))) {
+                elems
+                    .iter()
+                    .map(|row| match row {
+                        Matrix(row) => row.iter().map(|elem| render(elem, m)).collect(),
+                        _ => unreachable!(),
+                    })
+                    .collect()
+            } else {
+                vec![elems.iter().map(|elem| render(elem, m)).collect()]
+            };
+            m.matrix(rows)
+        }
+        Call(Function::Sqrt, args) => m.sqrt(render(&args[0], m)),
+        Call(Function::Diff, args) => {
+            let Var(var) = &args[1] else { unreachable!() };
+            let body = wrap(&args[0], precedence(&args[0]) <= 2);
+            m.derivative(body, var, args.get(2).map(|point| render(point, m)))
+        }
+        Call(Function::Integrate, args) => {
+            let Var(var) = &args[1] else { unreachable!() };
+            let body = wrap(&args[0], precedence(&args[0]) <= 1);
+            m.integral(body, var, render(&args[2], m), render(&args[3], m))
+        }
+        Call(func, args) => m.call(*func, args.iter().map(|arg| render(arg, m)).collect()),
+        Sum(var, from, to, body) | Prod(var, from, to, body) => {
+            let func = if matches!(node, Sum(..)) {
+                Function::Sum
+            } else {
+                Function::Prod
+            };
+            let body = wrap(body, precedence(body) <= 1);
+            m.series(func, var, render(from, m), render(to, m), body)
+        }
+    })
+}
+
+struct Latex;
+
+// 有专门命令的函数，例如 \sin
+const LATEX_FUNCTIONS: [Function; 6] = [
+    Function::Sin,
+    Function::Cos,
+    Function::Tan,
+    Function::Ln,
+    Function::Exp,
+    Function::Det,
+];
+
+impl Markup for Latex {
+    fn num(&self, num: Decimal) -> String {
+        num.to_string()
+    }
+
+    // 单个字母直接输出，x_1 输出成下标，其他多个字母的名字用正体
+    fn var(&self, name: &str) -> String {
+        match name.split_once('_') {
+            _ if name == "pi" => "\\pi".to_string(),
+            _ if name.chars().count() == 1 => name.to_string(),
+            Some((base, sub)) if !base.is_empty() && !sub.is_empty() => {
+                format!("{}_{{{}}}", self.var(base), self.var(sub))
+            }
+            _ => format!("\\mathrm{{{}}}", name.replace('_', "\\_")),
+        }
+    }
+
+    fn paren(&self, text: String) -> String {
+        format!("\\left({text}\\right)")
+    }
+
+    fn infix(&self, op: Operator, lhs: String, rhs: String) -> String {
+        let op = if op == Operator::Mul {
+            "\\cdot".to_string()
+        } else {
+            op.to_string()
+        };
+        format!("{lhs} {op} {rhs}")
+    }
+
+    fn neg(&self, text: String) -> String {
+        format!("-{text}")
+    }
+
+    fn frac(&self, num: String, den: String) -> String {
+        format!("\\frac{{{num}}}{{{den}}}")
+    }
+
+    fn pow(&self, base: String, exp: String) -> String {
+        format!("{base}^{{{exp}}}")
+    }
+
+    fn sqrt(&self, text: String) -> String {
+        format!("\\sqrt{{{text}}}")
+    }
+
+    fn call(&self, func: Function, args: Vec<String>) -> String {
+        let name = if LATEX_FUNCTIONS.contains(&func) {
+            format!("\\{func}")
+        } else {
+            format!("\\operatorname{{{}}}", func.name().replace('_', "\\_"))
+        };
+        format!("{name}{}", self.paren(args.join(", ")))
+    }
+
+    fn matrix(&self, rows: Vec<Vec<String>>) -> String {
+        let rows: Vec<String> = rows.into_iter().map(|row| row.join(" & ")).collect();
+        format!(
+            "\\begin{{bmatrix}} {} \\end{{bmatrix}}",
+            rows.join(" \\\\ ")
+        )
+    }
+
+    fn series(&self, func: Function, var: &str, from: String, to: String, body: String) -> String {
+        let var = self.var(var);
+        format!("\\{func}_{{{var} = {from}}}^{{{to}}} {body}")
+    }
+
+    fn integral(&self, body: String, var: &str, from: String, to: String) -> String {
+        format!("\\int_{{{from}}}^{{{to}}} {body} \\, d{}", self.var(var))
+    }
+
+    fn derivative(&self, body: String, var: &str, point: Option<String>) -> String {
+        let var = self.var(var);
+        let diff = format!("\\frac{{d}}{{d{var}}} {body}");
+        match point {
+            Some(point) => format!("\\left.{diff}\\right|_{{{var} = {point}}}"),
+            None => diff,
+        }
+    }
+}
+
+struct MathMl;
+
+impl MathMl {
+    fn op(&self, op: &str) -> String {
+        format!("<mo>{op}</mo>")
+    }
+
+    fn row(&self, items: &[String]) -> String {
+        format!("<mrow>{}</mrow>", items.concat())
+    }
+}
+
+impl Markup for MathMl {
+    fn num(&self, num: Decimal) -> String {
+        if num.is_sign_negative() {
+            self.row(&[self.op("-"), format!("<mn>{}</mn>", num.abs())])
+        } else {
+            format!("<mn>{num}</mn>")
+        }
+    }
+
+    fn var(&self, name: &str) -> String {
+        match name.split_once('_') {
+            _ if name == "pi" => "<mi>π</mi>".to_string(),
+            Some((base, sub)) if !base.is_empty() && !sub.is_empty() => {
+                let sub = if sub.chars().all(|c| c.is_ascii_digit()) {
+                    format!("<mn>{sub}</mn>")
+                } else {
+                    self.var(sub)
+                };
+                format!("<msub>{}{sub}</msub>", self.var(base))
+            }
+            _ => format!("<mi>{name}</mi>"),
+        }
+    }
+
+    fn paren(&self, text: String) -> String {
+        self.row(&[self.op("("), text, self.op(")")])
+    }
+
+    fn infix(&self, op: Operator, lhs: String, rhs: String) -> String {
+        let op = match op {
+            Operator::Mul => "⋅".to_string(),
+            Operator::Sub => "−".to_string(),
+            op => op.to_string(),
+        };
+        self.row(&[lhs, self.op(&op), rhs])
+    }
+
+    fn neg(&self, text: String) -> String {
+        self.row(&[self.op("−"), text])
+    }
+
+    fn frac(&self, num: String, den: String) -> String {
+        format!("<mfrac>{num}{den}</mfrac>")
+    }
+
+    fn pow(&self, base: String, exp: String) -> String {
+        format!("<msup>{base}{exp}</msup>")
+    }
+
+    fn sqrt(&self, text: String) -> String {
+        format!("<msqrt>{text}</msqrt>")
+    }
+
+    fn call(&self, func: Function, args: Vec<String>) -> String {
+        let args = args.join(&self.op(","));
+        // U+2061 是不可见的函数应用符号
+        self.row(&[
+            format!("<mi>{func}</mi>"),
+            self.op("\u{2061}"),
+            self.paren(args),
+        ])
+    }
+
+    fn matrix(&self, rows: Vec<Vec<String>>) -> String {
+        let rows: Vec<String> = rows
+            .into_iter()
+            .map(|row| {
+                let cells: Vec<String> =
+                    row.into_iter().map(|c| format!("<mtd>{c}</mtd>")).collect();
+                format!("<mtr>{}</mtr>", cells.concat())
+            })
+            .collect();
+        self.row(&[
+            self.op("["),
+            format!("<mtable>{}</mtable>", rows.concat()),
+            self.op("]"),
+        ])
+    }
+
+    fn series(&self, func: Function, var: &str, from: String, to: String, body: String) -> String {
+        let symbol = if func == Function::Sum { "∑" } else { "∏" };
+        let lower = self.row(&[self.var(var), self.op("="), from]);
+        let op = format!("<munderover>{}{lower}{to}</munderover>", self.op(symbol));
+        self.row(&[op, body])
+    }
+
+    fn integral(&self, body: String, var: &str, from: String, to: String) -> String {
+        let op = format!("<msubsup>{}{from}{to}</msubsup>", self.op("∫"));
+        self.row(&[op, body, "<mi>d</mi>".to_string(), self.var(var)])
+    }
+
+    fn derivative(&self, body: String, var: &str, point: Option<String>) -> String {
+        let dvar = self.row(&["<mi>d</mi>".to_string(), self.var(var)]);
+        let diff = self.row(&[format!("<mfrac><mi>d</mi>{dvar}</mfrac>"), body]);
+        match point {
+            Some(point) => {
+                let at = self.row(&[self.var(var), self.op("="), point]);
+                format!("<msub>{}{at}</msub>", self.row(&[diff, self.op("|")]))
+            }
+            None => diff,
+        }
+    }
+}
+
+impl Ast {
+    pub fn to_latex(&self) -> String {
+        render(&self.0, &Latex)
+    }
+
+    // 展示形式的 MathML，包含最外层的 <math> 元素
+    pub fn to_mathml(&self) -> String {
+        format!(
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>",
+            render(&self.0, &MathMl)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calc::parser::Parser;
+
+    fn latex(expr: &str) -> String {
+        Parser::parse(expr).unwrap().to_latex()
+    }
+
+    fn mathml(expr: &str) -> String {
+        let text = Parser::parse(expr).unwrap().to_mathml();
+        let inner = text.strip_prefix("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">");
+        inner.unwrap().strip_suffix("</math>").unwrap().to_string()
+    }
+
+    #[test]
+    fn test_latex_operators() {
+        assert_eq!(latex("(1 + x)/(2*y)"), "\\frac{1 + x}{2 \\cdot y}");
+        assert_eq!(
+            latex("(x + 1)*(x - 1)"),
+            "\\left(x + 1\\right) \\cdot \\left(x - 1\\right)"
+        );
+        assert_eq!(latex("x^(n + 1)"), "x^{n + 1}");
+        assert_eq!(latex("2^3^2"), "2^{3^{2}}");
+        assert_eq!(latex("(2^3)^2"), "\\left(2^{3}\\right)^{2}");
+        assert_eq!(latex("(1/2)^2"), "\\left(\\frac{1}{2}\\right)^{2}");
+        assert_eq!(
+            latex("(-2)^2 - (-1)*x"),
+            "\\left(-2\\right)^{2} - \\left(-1 \\cdot x\\right)"
+        );
+        assert_eq!(latex("1 - (2 - 3)"), "1 - \\left(2 - 3\\right)");
+        assert_eq!(latex("-(x + 1)*y"), "-\\left(x + 1\\right) \\cdot y");
+        assert_eq!(latex("x*-y"), "x \\cdot \\left(-y\\right)");
+        assert_eq!(latex("x^2 + 1 = 0"), "x^{2} + 1 = 0");
+    }
+
+    #[test]
+    fn test_latex_functions() {
+        assert_eq!(latex("sqrt(x^2 + 1)"), "\\sqrt{x^{2} + 1}");
+        assert_eq!(latex("sin(pi*x)"), "\\sin\\left(\\pi \\cdot x\\right)");
+        assert_eq!(
+            latex("mod_pow(a, b, m)"),
+            "\\operatorname{mod\\_pow}\\left(a, b, m\\right)"
+        );
+        assert_eq!(
+            latex("sum(k, 1, n, k + 1)"),
+            "\\sum_{k = 1}^{n} \\left(k + 1\\right)"
+        );
+        assert_eq!(
+            latex("integrate(x^2, x, 0, 1)"),
+            "\\int_{0}^{1} x^{2} \\, dx"
+        );
+        assert_eq!(
+            latex("diff(x*y, x)"),
+            "\\frac{d}{dx} \\left(x \\cdot y\\right)"
+        );
+        assert_eq!(
+            latex("[[1, 2], [3, x_1]]"),
+            "\\begin{bmatrix} 1 & 2 \\\\ 3 & x_{1} \\end{bmatrix}"
+        );
+        assert_eq!(latex("rate*t"), "\\mathrm{rate} \\cdot t");
+    }
+
+    #[test]
+    fn test_latex_big_operators() {
+        // 大运算符后面还有内容时要加括号，否则后面的内容会被读成它的一部分
+        assert_eq!(
+            latex("sum(k, 1, n, k)*x"),
+            "\\left(\\sum_{k = 1}^{n} k\\right) \\cdot x"
+        );
+        assert_eq!(
+            latex("sum(k, 1, n, k) + 1"),
+            "\\left(\\sum_{k = 1}^{n} k\\right) + 1"
+        );
+        assert_eq!(
+            latex("prod(k, 1, n, k)^2"),
+            "\\left(\\prod_{k = 1}^{n} k\\right)^{2}"
+        );
+        assert_eq!(
+            latex("integrate(x, x, 0, 1)*y"),
+            "\\left(\\int_{0}^{1} x \\, dx\\right) \\cdot y"
+        );
+        assert_eq!(
+            latex("integrate(x, x, 0, 1)^2"),
+            "\\left(\\int_{0}^{1} x \\, dx\\right)^{2}"
+        );
+        assert_eq!(
+            latex("diff(x^2, x) - 1"),
+            "\\left(\\frac{d}{dx} x^{2}\\right) - 1"
+        );
+        assert_eq!(
+            latex("y*diff(x^2, x, 3) + 1"),
+            "y \\cdot \\left.\\frac{d}{dx} x^{2}\\right|_{x = 3} + 1"
+        );
+        // 在最右边时不需要括号
+        assert_eq!(latex("x*sum(k, 1, n, k)"), "x \\cdot \\sum_{k = 1}^{n} k");
+        assert_eq!(latex("1 - diff(x^2, x)"), "1 - \\frac{d}{dx} x^{2}");
+        assert_eq!(
+            latex("-sum(k, 1, n, k) = 2"),
+            "-\\sum_{k = 1}^{n} k = 2"
+        );
+    }
+
+    #[test]
+    fn test_mathml() {
+        assert_eq!(
+            mathml("(1 + x)/2"),
+            "<mfrac><mrow><mn>1</mn><mo>+</mo><mi>x</mi></mrow><mn>2</mn></mfrac>"
+        );
+        assert_eq!(
+            mathml("(x - 1)^2"),
+            "<msup><mrow><mo>(</mo><mrow><mi>x</mi><mo>−</mo><mn>1</mn></mrow><mo>)</mo></mrow><mn>2</mn></msup>"
+        );
+        assert_eq!(
+            mathml("sqrt(-2)"),
+            "<msqrt><mrow><mo>-</mo><mn>2</mn></mrow></msqrt>"
+        );
+        assert_eq!(
+            mathml("sin(x)"),
+            "<mrow><mi>sin</mi><mo>\u{2061}</mo><mrow><mo>(</mo><mi>x</mi><mo>)</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            mathml("sum(k, 1, n, k)"),
+            "<mrow><munderover><mo>∑</mo><mrow><mi>k</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>k</mi></mrow>"
+        );
+        assert_eq!(
+            mathml("sum(k, 1, n, k)*x"),
+            "<mrow><mrow><mo>(</mo><mrow><munderover><mo>∑</mo><mrow><mi>k</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>k</mi></mrow><mo>)</mo></mrow><mo>⋅</mo><mi>x</mi></mrow>"
+        );
+    }
+}
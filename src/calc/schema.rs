@@ -0,0 +1,276 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error, ser::SerializeStruct};
+
+use crate::calc::{
+    ast::{Ast, Node},
+    context::{DEFAULT_MAX_DEPTH, RED_ZONE, STACK_SEGMENT, deep},
+    function::Function,
+    parser::Depth,
+};
+
+// 语法树的 JSON 格式，最外层是 {"version": 1, "ast": 节点}。
+// 节点和 token 写成 {"type": 变体名, "value": 字段}，变体名是小写加下划线的形式，有多个字段时 value 是数组，
+// 例如 1.5 + x 是 {"type": "add", "value": [{"type": "num", "value": "1.5"}, {"type": "var", "value": "x"}]}。
+// 数保存为字符串以免损失精度，函数保存为函数名。格式有不兼容的改动时增加版本号
+pub const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Document {
+    version: u32,
+    ast: Node,
+}
+
+impl From<Ast> for Document {
+    fn from(ast: Ast) -> Self {
+        Document {
+            version: VERSION,
+            ast: ast.0,
+        }
+    }
+}
+
+// Ast 的 Deserialize 使用默认的最大嵌套深度
+impl TryFrom<Document> for Ast {
+    type Error = String;
+
+    fn try_from(doc: Document) -> Result<Ast, String> {
+        doc.into_ast(DEFAULT_MAX_DEPTH)
+    }
+}
+
+impl Document {
+    fn into_ast(self, max_depth: usize) -> Result<Ast, String> {
+        if self.version != VERSION {
+            return Err(format!(
+                "Unsupported schema version {}, expected {VERSION}",
+                self.version
+            ));
+        }
+        let (node, _) = check(self.ast, max_depth)?;
+        Ok(Ast(node))
+    }
+}
+
+// 和 Deserialize 的派生实现格式相同。派生的 Serialize 会直接递归，很长的链会耗尽调用栈，
+// 这里每一层都经过 deep
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use Node::*;
+        deep(|| {
+            let mut state = serializer.serialize_struct("Node", 2)?;
+            match self {
+                Add(lhs, rhs) => tagged(&mut state, "add", &(lhs, rhs))?,
+                Sub(lhs, rhs) => tagged(&mut state, "sub", &(lhs, rhs))?,
+                Mul(lhs, rhs) => tagged(&mut state, "mul", &(lhs, rhs))?,
+                Div(lhs, rhs) => tagged(&mut state, "div", &(lhs, rhs))?,
+                Pow(lhs, rhs) => tagged(&mut state, "pow", &(lhs, rhs))?,
+                Eq(lhs, rhs) => tagged(&mut state, "eq", &(lhs, rhs))?,
+                Neg(operand) => tagged(&mut state, "neg", operand)?,
+                Num(num) => tagged(&mut state, "num", &num.to_string())?,
+                Var(name) => tagged(&mut state, "var", name)?,
+                Matrix(elems) => tagged(&mut state, "matrix", elems)?,
+                Call(func, args) => tagged(&mut state, "call", &(func, args))?,
+                Sum(var, from, to, body) => {
+                    tagged(&mut state, "sum", &(var, from, to, body))?
+                }
+                Prod(var, from, to, body) => {
+                    tagged(&mut state, "prod", &(var, from, to, body))?
+                }
+            }
+            state.end()
+        })
+    }
+}
+
+fn tagged<S: SerializeStruct, T: Serialize + ?Sized>(
+    state: &mut S,
+    tag: &'static str,
+    value: &T,
+) -> Result<(), S::Error> {
+    state.serialize_field("type", tag)?;
+    state.serialize_field("value", value)
+}
+
+impl Serialize for Function {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Function {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Function::from_name(&name)
+            .ok_or_else(|| D::Error::custom(format!("Unknown function: '{name}'")))
+    }
+}
+
+// 反序列化得到的树没有经过解析器的检查，函数调用要经过 Node::call 重新检查参数，避免求值时出错的参数导致 panic。
+// 同时按照和解析器相同的方式计算深度，超过 max_depth 时报错
+fn check(mut node: Node, max_depth: usize) -> Result<(Node, Depth), String> {
+    use Node::*;
+    let check_box = |node: &mut Box<Node>| {
+        check(node.take(), max_depth).map(|(node, depth)| (Box::new(node), depth))
+    };
+    let check_all = |nodes: &mut Vec<Node>| -> Result<(Vec<Node>, Vec<Depth>), String> {
+        std::mem::take(nodes).into_iter().map(|node| check(node, max_depth)).collect()
+    };
+    // 左结合的运算符（chain）的左边不增加嵌套的层数
+    let binary = |lhs: &mut _, rhs: &mut _, chain: bool, make: fn(Box<Node>, Box<Node>) -> Node| {
+        let ((lhs, l), (rhs, r)) = (check_box(lhs)?, check_box(rhs)?);
+        let depth = if chain { Depth::chain(l, r) } else { Depth::parent([l, r]) };
+        Ok::<_, String>((make(lhs, rhs), depth))
+    };
+    let (node, depth) = deep(|| match &mut node {
+        Add(lhs, rhs) => binary(lhs, rhs, true, Add),
+        Sub(lhs, rhs) => binary(lhs, rhs, true, Sub),
+        Mul(lhs, rhs) => binary(lhs, rhs, true, Mul),
+        Div(lhs, rhs) => binary(lhs, rhs, true, Div),
+        Pow(lhs, rhs) => binary(lhs, rhs, false, Pow),
+        Eq(lhs, rhs) => binary(lhs, rhs, false, Eq),
+        Neg(val) => {
+            let (val, depth) = check_box(val)?;
+            Ok((Neg(val), Depth::parent([depth])))
+        }
+        Num(_) | Var(_) => Ok((node, Depth::LEAF)),
+        Matrix(elems) => {
+            let (elems, depths) = check_all(elems)?;
+            Ok((Matrix(elems), Depth::parent(depths)))
+        }
+        Call(func, args) => {
+            let (args, depths) = check_all(args)?;
+            Ok((Node::call(*func, args)?, Depth::parent(depths)))
+        }
+        Sum(var, from, to, body) => {
+            let ((from, a), (to, b), (body, c)) = (check_box(from)?, check_box(to)?, check_box(body)?);
+            Ok((Sum(std::mem::take(var), from, to, body), Depth::parent([a, b, c])))
+        }
+        Prod(var, from, to, body) => {
+            let ((from, a), (to, b), (body, c)) = (check_box(from)?, check_box(to)?, check_box(body)?);
+            Ok((Prod(std::mem::take(var), from, to, body), Depth::parent([a, b, c])))
+        }
+    })?;
+    Ok((node, depth.check(max_depth)?))
+}
+
+impl Ast {
+    pub fn to_json(&self) -> String {
+        // 所有的键都是字符串，序列化不会失败
+        serde_json::to_string(self).unwrap()
+    }
+
+    // serde_json 默认只允许 128 层嵌套，一个 70 项的和就超过了。这里去掉这个限制，
+    // 由 serde_stacker 按需扩展调用栈，读完之后再按 max_depth 检查语法树的深度
+    pub fn from_json(json: &str, max_depth: usize) -> Result<Ast, String> {
+        let invalid = |err: &dyn std::fmt::Display| format!("Invalid syntax tree JSON: {err}");
+        let mut de = serde_json::Deserializer::from_str(json);
+        de.disable_recursion_limit();
+        let mut stacker = serde_stacker::Deserializer::new(&mut de);
+        (stacker.red_zone, stacker.stack_size) = (RED_ZONE, STACK_SEGMENT);
+        let doc = Document::deserialize(stacker).map_err(|err| invalid(&err))?;
+        de.end().map_err(|err| invalid(&err))?;
+        doc.into_ast(max_depth).map_err(|err| invalid(&err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{parser::Parser, token::Token};
+
+    #[test]
+    fn test_schema() {
+        let ast = Parser::parse("1.50*-x").unwrap();
+        assert_eq!(
+            ast.to_json(),
+            r#"{"version":1,"ast":{"type":"mul","value":[{"type":"num","value":"1.50"},{"type":"neg","value":{"type":"var","value":"x"}}]}}"#
+        );
+        let ast = Parser::parse("mod_pow(2, 10, 1000)").unwrap();
+        assert_eq!(
+            ast.to_json(),
+            r#"{"version":1,"ast":{"type":"call","value":["mod_pow",[{"type":"num","value":"2"},{"type":"num","value":"10"},{"type":"num","value":"1000"}]]}}"#
+        );
+        let token = Token::Func(Function::Sin, 1);
+        assert_eq!(
+            serde_json::to_string(&token).unwrap(),
+            r#"{"type":"func","value":["sin",1]}"#
+        );
+        let token = Token::Op(crate::calc::token::Operator::Pow);
+        assert_eq!(
+            serde_json::to_string(&token).unwrap(),
+            r#"{"type":"op","value":"pow"}"#
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for expr in [
+            "1 - (2 - 3)*x^2",
+            "0.1234567890123456789012345678 + 79228162514264337593543950335",
+            "sum(k, 1, n, [k, 2])/rand()",
+            "solve(x^2 = 2, x, 0, 2)",
+            "[[1, 2], [3, 4]]",
+        ] {
+            let ast = Parser::parse(expr).unwrap();
+            assert_eq!(Ast::from_json(&ast.to_json(), DEFAULT_MAX_DEPTH), Ok(ast), "{expr}");
+        }
+        // 很长的链超过了 serde_json 默认的 128 层递归限制，但嵌套的层数不多
+        for count in [70, 20000] {
+            let expr = vec!["x"; count].join(" + ");
+            let ast = Parser::parse(&expr).unwrap();
+            assert_eq!(Ast::from_json(&ast.to_json(), DEFAULT_MAX_DEPTH), Ok(ast), "{count}");
+        }
+        // 嵌套的层数按 max_depth 检查
+        let nested = format!("{}1{}", "-(".repeat(30), ")".repeat(30));
+        let json = Parser::parse(&nested).unwrap().to_json();
+        assert!(Ast::from_json(&json, 31).is_ok());
+        assert_eq!(
+            Ast::from_json(&json, 30),
+            Err("Invalid syntax tree JSON: Expression nesting exceeds 30 levels".to_string())
+        );
+        let token = Token::Num(rust_decimal::Decimal::from_str_exact("-0.5000").unwrap());
+        let json = serde_json::to_string(&token).unwrap();
+        assert_eq!(json, r#"{"type":"num","value":"-0.5000"}"#);
+        assert_eq!(serde_json::from_str::<Token>(&json).unwrap(), token);
+    }
+
+    #[test]
+    fn test_errors() {
+        // serde_json 的错误信息后面带有出错的位置
+        let err = |json: &str| Ast::from_json(json, DEFAULT_MAX_DEPTH).unwrap_err();
+        let diff = Parser::parse("diff(1, 2)").unwrap_err();
+        for (json, expected) in [
+            (
+                r#"{"version":2,"ast":{"type":"var","value":"x"}}"#,
+                "Unsupported schema version 2, expected 1",
+            ),
+            (
+                r#"{"version":1,"ast":{"type":"call","value":["sqrt",[]]}}"#,
+                "Function 'sqrt' expects 1 argument(s), found 0",
+            ),
+            (
+                r#"{"version":1,"ast":{"type":"call","value":["diff",[{"type":"num","value":"1"},{"type":"num","value":"2"}]]}}"#,
+                &diff,
+            ),
+            (
+                r#"{"version":1,"ast":{"type":"call","value":["max",[]]}}"#,
+                "Unknown function: 'max'",
+            ),
+            (
+                r#"{"version":1,"ast":{"type":"num","value":1.5}}"#,
+                "invalid type",
+            ),
+            (
+                r#"{"version":1,"ast":{"type":"num","value":"1.2.3"}}"#,
+                "invalid value",
+            ),
+            (
+                r#"{"ast":{"type":"var","value":"x"}}"#,
+                "missing field `version`",
+            ),
+        ] {
+            let err = err(json);
+            assert!(err.starts_with("Invalid syntax tree JSON: "), "{err}");
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::{iter::Peekable, vec::IntoIter};
+
+use crate::calc::{
+    ast::{Ast, Node},
+    context::deep,
+    function::Function,
+    parser::{Depth, is_chain},
+    token::Operator,
+};
+
+// S 表达式：(+ 1 (* 2 3))。运算符和函数名写在括号里的第一个位置，+ 和 * 可以有多个参数，
+// 从左到右结合；只有一个参数的 - 是取负。方括号 [a b c] 是列表，sum 和 prod 写成 (sum k 1 n body)
+
+#[derive(Debug, PartialEq)]
+enum Item<'a> {
+    Open(char),
+    Close(char),
+    Atom(&'a str),
+}
+
+// 括号单独成词，其他的词以空白和括号分隔
+fn lex(expr: &str) -> Vec<Item<'_>> {
+    let mut items = Vec::new();
+    let mut start = None;
+    for (i, c) in expr.char_indices() {
+        if c.is_whitespace() || "()[]".contains(c) {
+            if let Some(begin) = start.take() {
+                items.push(Item::Atom(&expr[begin..i]));
+            }
+            match c {
+                '(' | '[' => items.push(Item::Open(c)),
+                ')' | ']' => items.push(Item::Close(c)),
+                _ => {}
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(begin) = start {
+        items.push(Item::Atom(&expr[begin..]));
+    }
+    items
+}
+
+fn operator(name: &str) -> Option<Operator> {
+    match name {
+        "+" => Some(Operator::Add),
+        "-" => Some(Operator::Sub),
+        "*" => Some(Operator::Mul),
+        "/" => Some(Operator::Div),
+        "^" => Some(Operator::Pow),
+        "=" => Some(Operator::Eq),
+        _ => None,
+    }
+}
+
+// 数或者变量名
+fn atom(word: &str) -> Result<Node, String> {
+    if word.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+        return word
+            .parse()
+            .map(Node::Num)
+            .map_err(|_| format!("Invalid number: '{word}'"));
+    }
+    if let Some(func) = Function::from_name(word) {
+        return Err(format!(
+            "Function '{func}' must be called as '({func} ...)'"
+        ));
+    }
+    let is_ident = word.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && word.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if is_ident {
+        Ok(Node::Var(word.to_string()))
+    } else {
+        Err(format!("Unexpected '{word}' in S-expression"))
+    }
+}
+
+// depths 是每个参数的深度，返回结果和它的深度。多个参数的 + 和 * 从左到右结合成一条链，
+// 在构建之前检查深度
+fn apply(
+    head: &str,
+    mut args: Vec<Node>,
+    depths: Vec<Depth>,
+    max_depth: usize,
+) -> Result<(Node, Depth), String> {
+    let Some(op) = operator(head) else {
+        let func =
+            Function::from_name(head).ok_or_else(|| format!("Unknown function: '{head}'"))?;
+        return Ok((Node::call(func, args)?, Depth::parent(depths)));
+    };
+    let count = args.len();
+    let expected = match op {
+        Operator::Add | Operator::Mul if count >= 2 => None,
+        Operator::Add | Operator::Mul => Some("at least 2"),
+        Operator::Sub if count == 1 || count == 2 => None,
+        Operator::Sub => Some("1 or 2"),
+        _ if count == 2 => None,
+        _ => Some("2"),
+    };
+    if let Some(expected) = expected {
+        return Err(format!(
+            "Operator '{op}' expects {expected} argument(s), found {count}"
+        ));
+    }
+    if op == Operator::Sub && count == 1 {
+        return Ok((Node::Neg(Box::new(args.remove(0))), Depth::parent(depths)));
+    }
+    if op == Operator::Eq && args.iter().any(|arg| matches!(arg, Node::Eq(..))) {
+        return Err("Expected a single '=' in an equation".to_string());
+    }
+    let mut depths = depths.into_iter();
+    let first = depths.next().unwrap();
+    let depth = depths.try_fold(first, |lhs, rhs| match is_chain(op) {
+        true => Depth::chain(lhs, rhs).check(max_depth),
+        false => Depth::parent([lhs, rhs]).check(max_depth),
+    })?;
+    let mut args = args.into_iter();
+    let first = args.next().unwrap();
+    let node = args.fold(first, |lhs, rhs| {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match op {
+            Operator::Add => Node::Add(lhs, rhs),
+            Operator::Sub => Node::Sub(lhs, rhs),
+            Operator::Mul => Node::Mul(lhs, rhs),
+            Operator::Div => Node::Div(lhs, rhs),
+            Operator::Pow => Node::Pow(lhs, rhs),
+            Operator::Eq => Node::Eq(lhs, rhs),
+            Operator::Neg | Operator::Sqrt => unreachable!(),
+        }
+    });
+    Ok((node, depth))
+}
+
+type Items<'a> = Peekable<IntoIter<Item<'a>>>;
+
+// 读取一个完整的表达式，返回语法树和它的深度，items 指向下一个未读的词。
+// outer 是从根节点到这里的深度，括号的层数超过 max_depth 时马上报错，不会读完很深的输入。
+// 还不知道 - 有几个参数的时候按减法计算，第一个参数不增加嵌套的层数，负号的深度在读完之后再检查
+fn read(items: &mut Items, outer: Depth, max_depth: usize) -> Result<(Node, Depth), String> {
+    outer.check(max_depth)?;
+    let open = match items.next() {
+        None => return Err("Unexpected end of S-expression".to_string()),
+        Some(Item::Atom(word)) => return Ok((atom(word)?, Depth::LEAF)),
+        Some(Item::Close(c)) => return Err(format!("Unexpected '{c}'")),
+        Some(Item::Open(open)) => open,
+    };
+    let close = if open == '(' { ')' } else { ']' };
+    let head = match items.peek() {
+        _ if open == '[' => None,
+        Some(Item::Atom(word)) => {
+            let word = *word;
+            items.next();
+            Some(word)
+        }
+        Some(Item::Close(')')) => return Err("Empty list '()'".to_string()),
+        Some(Item::Open(_) | Item::Close(_)) => {
+            return Err("Expected an operator or function name after '('".to_string());
+        }
+        None => return Err("Unmatched parentheses".to_string()),
+    };
+    let mut args = Vec::new();
+    let mut depths = Vec::new();
+    loop {
+        match items.peek() {
+            Some(Item::Close(c)) if *c == close => {
+                items.next();
+                break;
+            }
+            Some(Item::Close(_)) | None => return Err("Unmatched parentheses".to_string()),
+            Some(_) => {
+                let chain_lhs = args.is_empty() && head.and_then(operator).is_some_and(is_chain);
+                let (arg, depth) = deep(|| read(items, outer.child(chain_lhs), max_depth))?;
+                args.push(arg);
+                depths.push(depth);
+            }
+        }
+    }
+    let (node, depth) = match head {
+        Some(head) => apply(head, args, depths, max_depth)?,
+        None => (Node::Matrix(args), Depth::parent(depths)),
+    };
+    Ok((node, depth.check(max_depth)?))
+}
+
+pub fn parse(expr: &str, max_depth: usize) -> Result<Ast, String> {
+    let mut items = lex(expr).into_iter().peekable();
+    let (node, _) = read(&mut items, Depth::LEAF, max_depth)?;
+    match items.next() {
+        None => Ok(Ast(node)),
+        Some(_) => Err("Expected a single S-expression".to_string()),
+    }
+}
+
+fn print(node: &Node) -> String {
+    use Node::*;
+    let list = |head: String, args: &[&Node]| {
+        let args: Vec<String> = args.iter().map(|arg| print(arg)).collect();
+        format!("({head} {})", args.join(" "))
+    };
+    let binary = |op: Operator, lhs: &Node, rhs: &Node| list(op.to_string(), &[lhs, rhs]);
+    deep(|| match node {
+        Add(lhs, rhs) => binary(Operator::Add, lhs, rhs),
+        Sub(lhs, rhs) => binary(Operator::Sub, lhs, rhs),
+        Mul(lhs, rhs) => binary(Operator::Mul, lhs, rhs),
+        Div(lhs, rhs) => binary(Operator::Div, lhs, rhs),
+        Pow(lhs, rhs) => binary(Operator::Pow, lhs, rhs),
+        Eq(lhs, rhs) => binary(Operator::Eq, lhs, rhs),
+        Neg(val) => list(Operator::Neg.to_string(), &[val]),
+        Num(num) => num.to_string(),
+        Var(name) => name.clone(),
+        Matrix(elems) => {
+            let elems: Vec<String> = elems.iter().map(print).collect();
+            format!("[{}]", elems.join(" "))
+        }
+        Call(func, args) if args.is_empty() => format!("({func})"),
+        Call(func, args) => list(func.to_string(), &args.iter().collect::<Vec<_>>()),
+        Sum(var, from, to, body) | Prod(var, from, to, body) => {
+            let func = if matches!(node, Sum(..)) {
+                Function::Sum
+            } else {
+                Function::Prod
+            };
+            let var = Var(var.clone());
+            list(func.to_string(), &[&var, from, to, body])
+        }
+    })
+}
+
+impl Ast {
+    // 可以由 sexpr::parse 读回的 S 表达式
+    pub fn to_sexpr(&self) -> String {
+        print(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{
+        context::DEFAULT_MAX_DEPTH,
+        parser::Parser,
+    };
+
+    fn parse(expr: &str) -> Result<Ast, String> {
+        super::parse(expr, DEFAULT_MAX_DEPTH)
+    }
+
+    #[test]
+    fn test_parse() {
+        let infix = |expr: &str| Parser::parse(expr).unwrap();
+        assert_eq!(parse("(+ 1 (* 2 3))"), Ok(infix("1 + 2*3")));
+        assert_eq!(parse("(+ 1 2 3 4)"), Ok(infix("1 + 2 + 3 + 4")));
+        assert_eq!(parse("(- (^ x 2))"), Ok(infix("-x^2")));
+        assert_eq!(parse("(- -3 x)"), Ok(infix("-3 - x")));
+        assert_eq!(parse(" (sin\n(/ pi 2)) "), Ok(infix("sin(pi/2)")));
+        assert_eq!(parse("(gcd 12 18 8)"), Ok(infix("gcd(12, 18, 8)")));
+        assert_eq!(parse("(rand)"), Ok(infix("rand()")));
+        assert_eq!(parse("[[1 2] [3 x]]"), Ok(infix("[[1, 2], [3, x]]")));
+        assert_eq!(parse("(sum k 1 n (^ k 2))"), Ok(infix("sum(k, 1, n, k^2)")));
+        assert_eq!(
+            parse("(solve (= (^ x 2) 2) x)"),
+            Ok(infix("solve(x^2 = 2, x)"))
+        );
+        assert_eq!(parse("42"), Ok(infix("42")));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |expr: &str| parse(expr).unwrap_err();
+        assert_eq!(err(""), "Unexpected end of S-expression");
+        assert_eq!(err("(+ 1 2"), "Unmatched parentheses");
+        assert_eq!(err("(+ 1 2]"), "Unmatched parentheses");
+        assert_eq!(err(")"), "Unexpected ')'");
+        assert_eq!(err("()"), "Empty list '()'");
+        assert_eq!(
+            err("((+ 1 2) 3)"),
+            "Expected an operator or function name after '('"
+        );
+        assert_eq!(err("(+ 1 2) 3"), "Expected a single S-expression");
+        assert_eq!(
+            err("(+ 1)"),
+            "Operator '+' expects at least 2 argument(s), found 1"
+        );
+        assert_eq!(
+            err("(- 1 2 3)"),
+            "Operator '-' expects 1 or 2 argument(s), found 3"
+        );
+        assert_eq!(err("(^ 2)"), "Operator '^' expects 2 argument(s), found 1");
+        assert_eq!(err("(= (= x 1) 2)"), "Expected a single '=' in an equation");
+        assert_eq!(err("(max 1 2)"), "Unknown function: 'max'");
+        assert_eq!(
+            err("(sqrt 1 2)"),
+            "Function 'sqrt' expects 1 argument(s), found 2"
+        );
+        assert_eq!(
+            err("(+ sin 1)"),
+            "Function 'sin' must be called as '(sin ...)'"
+        );
+        assert_eq!(err("(+ 1 2..3)"), "Invalid number: '2..3'");
+        assert_eq!(err("(+ 1 #)"), "Unexpected '#' in S-expression");
+        assert_eq!(
+            err("(sum 1 1 n k)"),
+            Parser::parse("sum(1, 1, n, k)").unwrap_err()
+        );
+        // 括号的层数会增加深度，多个参数的 + 连成的链不算嵌套，链的长度也不受限制
+        let nested = format!("{}1{}", "(sin ".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(err(&nested), "Expression nesting exceeds 500 levels");
+        let nested = format!("{}1{}", "(- ".repeat(600), ")".repeat(600));
+        assert_eq!(err(&nested), "Expression nesting exceeds 500 levels");
+        let list = format!("{}1{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(err(&list), "Expression nesting exceeds 500 levels");
+        let chain = format!("(+{})", " 1".repeat(501));
+        assert_eq!(parse(&chain).map(|ast| ast.to_string().len()), Ok(2001));
+        for chain in [
+            format!("(+{})", " 1".repeat(100_000)),
+            format!("{}1{}", "(- ".repeat(100_000), " 1)".repeat(100_000)),
+        ] {
+            assert!(parse(&chain).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_print() {
+        let sexpr = |expr: &str| Parser::parse(expr).unwrap().to_sexpr();
+        assert_eq!(sexpr("1 + 2*3"), "(+ 1 (* 2 3))");
+        assert_eq!(sexpr("-x^2*-3"), "(* (- (^ x 2)) -3)");
+        assert_eq!(sexpr("[[1, 2], [3, rand()]]"), "[[1 2] [3 (rand)]]");
+        assert_eq!(sexpr("prod(k, 1, n, k + 1)"), "(prod k 1 n (+ k 1))");
+        // 打印的结果读回之后得到相同的语法树
+        for expr in [
+            "1 - (2 - 3)",
+            "2^3^2",
+            "-(x + 1)*y",
+            "gcd(12, 18, 8)/sin(x)",
+            "sum(k, 1, n, [k, 2])",
+            "solve(x^2 = 2, x, 0, 2)",
+            "diff(x^2, x, 3)",
+        ] {
+            let ast = Parser::parse(expr).unwrap();
+            assert_eq!(parse(&ast.to_sexpr()), Ok(ast), "{expr}");
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::cmp::Ordering;
+
+use rust_decimal::{Decimal, MathematicalOps};
+
+use crate::calc::{
+    ast::Node,
+    context::{Context, deep},
+    function::Function,
+    value::{Value, exact_div},
+};
+
+// 自底向上化简表达式：常数折叠，去掉 x + 0、x * 1、x ^ 1 这类恒等运算，合并同类项，
+// 并把加法和乘法的运算数排成固定的顺序。化简时假定变量都是数，
+// 改变结果形状的化简（例如 x - x => 0）只对不含矩阵的子表达式进行，含有随机函数的项不会被合并
+pub fn simplify(mut node: Node) -> Node {
+    use Node::*;
+    deep(|| match &mut node {
+        Add(lhs, rhs) => sum(Add(bx(simplify(lhs.take())), bx(simplify(rhs.take())))),
+        Sub(lhs, rhs) => sum(Sub(bx(simplify(lhs.take())), bx(simplify(rhs.take())))),
+        Neg(val) => sum(Neg(bx(simplify(val.take())))),
+        Mul(lhs, rhs) => product(Mul(bx(simplify(lhs.take())), bx(simplify(rhs.take())))),
+        Div(lhs, rhs) => div(simplify(lhs.take()), simplify(rhs.take())),
+        Pow(lhs, rhs) => pow(simplify(lhs.take()), simplify(rhs.take())),
+        Eq(lhs, rhs) => Eq(bx(simplify(lhs.take())), bx(simplify(rhs.take()))),
+        Matrix(elems) => Matrix(std::mem::take(elems).into_iter().map(simplify).collect()),
+        Call(func, args) => call(*func, std::mem::take(args).into_iter().map(simplify).collect()),
+        Sum(var, from, to, body) => Sum(
+            std::mem::take(var),
+            bx(simplify(from.take())),
+            bx(simplify(to.take())),
+            bx(simplify(body.take())),
+        ),
+        Prod(var, from, to, body) => Prod(
+            std::mem::take(var),
+            bx(simplify(from.take())),
+            bx(simplify(to.take())),
+            bx(simplify(body.take())),
+        ),
+        Num(_) | Var(_) => node,
+    })
+}
+
+fn bx(node: Node) -> Box<Node> {
+    Box::new(node)
+}
+
+fn is_num(node: &Node, num: Decimal) -> bool {
+    matches!(node, Node::Num(n) if *n == num)
+}
+
+// 子表达式的值是否一定是数（而不是矩阵）
+fn is_scalar(node: &Node) -> bool {
+    use Node::*;
+    deep(|| match node {
+        Num(_) | Var(_) => true,
+        Matrix(_) | Eq(..) => false,
+        Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) | Pow(lhs, rhs) => {
+            is_scalar(lhs) && is_scalar(rhs)
+        }
+        Neg(val) => is_scalar(val),
+        Call(Function::Diff, args) => is_scalar(&args[0]),
+        Call(func, args) => !func.returns_matrix() && args.iter().all(is_scalar),
+        Sum(_, _, _, body) | Prod(_, _, _, body) => is_scalar(body),
+    })
+}
+
+// 子表达式是否含有随机函数，这样的子表达式每次求值的结果都不同
+fn is_random(node: &Node) -> bool {
+    use Node::*;
+    deep(|| match node {
+        Num(_) | Var(_) => false,
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Div(lhs, rhs)
+        | Pow(lhs, rhs)
+        | Eq(lhs, rhs) => is_random(lhs) || is_random(rhs),
+        Neg(val) => is_random(val),
+        Matrix(elems) => elems.iter().any(is_random),
+        Call(func, args) => func.is_random() || args.iter().any(is_random),
+        Sum(_, from, to, body) | Prod(_, from, to, body) => {
+            is_random(from) || is_random(to) || is_random(body)
+        }
+    })
+}
+
+// 可以和别的项合并、可以被消去的子表达式
+fn is_plain(node: &Node) -> bool {
+    is_scalar(node) && !is_random(node)
+}
+
+// 子表达式在变量取任何数值时都有定义（不考虑溢出）：只由加减乘、除以非零常数和非负整数次幂构成。
+// 只有这样的项才能乘以 0 之后消去，0 * (1 / 0) 和 0 * sin(x) 要保留，
+// 求值的时候才会报告错误
+fn is_total(node: &Node) -> bool {
+    use Node::*;
+    deep(|| match node {
+        Num(_) | Var(_) => true,
+        Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) => is_total(lhs) && is_total(rhs),
+        // 除以不为 0 的常数，例如多项式的系数 1 / 3
+        Div(lhs, rhs) => matches!(**rhs, Num(n) if !n.is_zero()) && is_total(lhs),
+        Pow(base, exp) => {
+            matches!(**exp, Num(n) if n.fract().is_zero() && !n.is_sign_negative())
+                && is_total(base)
+        }
+        Neg(val) => is_total(val),
+        _ => false,
+    })
+}
+
+// 把加减法展开成一串带系数的项，常数项的 term 是 1
+fn collect_terms(mut node: Node, sign: Decimal, terms: &mut Vec<(Decimal, Node)>) {
+    use Node::*;
+    deep(|| match &mut node {
+        Add(lhs, rhs) => {
+            collect_terms(lhs.take(), sign, terms);
+            collect_terms(rhs.take(), sign, terms);
+        }
+        Sub(lhs, rhs) => {
+            collect_terms(lhs.take(), sign, terms);
+            collect_terms(rhs.take(), -sign, terms);
+        }
+        Neg(val) => collect_terms(val.take(), -sign, terms),
+        _ => {
+            let (coef, term) = split_coef(node);
+            terms.push((sign * coef, term));
+        }
+    })
+}
+
+// 和式中是否有没合并的同类项：系数相加溢出的时候 sum 只能把它们分开保留
+pub fn has_like_terms(node: &Node) -> bool {
+    let mut terms = Vec::new();
+    collect_terms(node.clone(), Decimal::ONE, &mut terms);
+    terms
+        .iter()
+        .enumerate()
+        .any(|(i, (_, term))| is_plain(term) && terms[..i].iter().any(|(_, other)| other == term))
+}
+
+// 把乘积最左边的常数系数拆出来：2 * x * y => (2, x * y)
+fn split_coef(mut node: Node) -> (Decimal, Node) {
+    use Node::*;
+    deep(|| match &mut node {
+        Num(num) => (*num, Num(Decimal::ONE)),
+        Mul(lhs, rhs) => match split_coef(lhs.take()) {
+            (coef, lhs) if is_num(&lhs, Decimal::ONE) => (coef, rhs.take()),
+            (coef, lhs) => (coef, Mul(bx(lhs), bx(rhs.take()))),
+        },
+        _ => (Decimal::ONE, node),
+    })
+}
+
+// 排序用的次数：x^2 的次数是 2，x * y 的次数是 2，常数的次数是 0
+fn degree(node: &Node) -> Decimal {
+    use Node::*;
+    deep(|| match node {
+        Num(_) => Decimal::ZERO,
+        Pow(base, exp) => match **exp {
+            Num(exp) => degree(base).checked_mul(exp).unwrap_or(Decimal::ZERO),
+            _ => Decimal::ONE,
+        },
+        Mul(lhs, rhs) => degree(lhs)
+            .checked_add(degree(rhs))
+            .unwrap_or(Decimal::ZERO),
+        _ => Decimal::ONE,
+    })
+}
+
+// 加法的项按次数从高到低排列，次数相同的按打印出来的字符串排列，常数项放在最后
+fn term_order(a: &Node, b: &Node) -> Ordering {
+    let (a_const, b_const) = (is_num(a, Decimal::ONE), is_num(b, Decimal::ONE));
+    a_const
+        .cmp(&b_const)
+        .then_with(|| degree(b).cmp(&degree(a)))
+        .then_with(|| a.to_string().cmp(&b.to_string()))
+}
+
+// 化简加减法：合并常数项和同类项，消去系数为 0 的有定义的项，再按固定顺序重新组合
+fn sum(node: Node) -> Node {
+    let mut collected = Vec::new();
+    collect_terms(node, Decimal::ONE, &mut collected);
+    let mut terms: Vec<(Decimal, Node)> = Vec::new();
+    for (coef, term) in collected {
+        let like = if is_plain(&term) {
+            terms
+                .iter_mut()
+                .find(|(other, like)| *like == term && other.checked_add(coef).is_some())
+        } else {
+            None
+        };
+        match like {
+            Some((other, _)) => *other += coef,
+            None => terms.push((coef, term)),
+        }
+    }
+    terms.retain(|(coef, term)| !coef.is_zero() || !is_total(term));
+    terms.sort_by(|(_, a), (_, b)| term_order(a, b));
+
+    let mut res: Option<Node> = None;
+    for (coef, term) in terms {
+        let negative = coef.is_sign_negative() && !coef.is_zero();
+        let term = with_coef(coef.abs(), term);
+        res = Some(match res {
+            None if negative => with_coef(Decimal::NEGATIVE_ONE, term),
+            None => term,
+            Some(acc) if negative => Node::Sub(bx(acc), bx(term)),
+            Some(acc) => Node::Add(bx(acc), bx(term)),
+        });
+    }
+    res.unwrap_or(Node::Num(Decimal::ZERO))
+}
+
+// 系数乘以项，系数为 -1 时使用负号，系数总是乘积左边的第一个因子：coef * x * y
+fn with_coef(coef: Decimal, mut term: Node) -> Node {
+    use Node::*;
+    deep(|| match &mut term {
+        _ if coef == Decimal::ONE => term,
+        Num(num) if num.checked_mul(coef).is_some() => Num(*num * coef),
+        Mul(lhs, rhs) => Mul(bx(with_coef(coef, lhs.take())), bx(rhs.take())),
+        _ if coef == Decimal::NEGATIVE_ONE => Neg(bx(term)),
+        _ => Mul(bx(Num(coef)), bx(term)),
+    })
+}
+
+// 把乘积展开成常数系数和一串因子，负号也并入系数
+fn collect_factors(mut node: Node, coef: &mut Decimal, factors: &mut Vec<Node>) {
+    use Node::*;
+    deep(|| match &mut node {
+        Mul(lhs, rhs) => {
+            collect_factors(lhs.take(), coef, factors);
+            collect_factors(rhs.take(), coef, factors);
+        }
+        Neg(val) => {
+            *coef = -*coef;
+            collect_factors(val.take(), coef, factors);
+        }
+        Num(num) if coef.checked_mul(*num).is_some() => *coef *= *num,
+        _ => factors.push(node),
+    })
+}
+
+// 化简乘法：常数系数放在最前面，底数相同的因子合并成乘方（x * x^2 => x^3），
+// 因子都是数的时候按固定顺序排列，含有矩阵的乘积不满足交换律，保持原来的顺序
+fn product(node: Node) -> Node {
+    let mut coef = Decimal::ONE;
+    let mut collected = Vec::new();
+    collect_factors(node, &mut coef, &mut collected);
+    let plain = collected.iter().all(is_plain);
+    if coef.is_zero() && collected.iter().all(is_total) {
+        return Node::Num(Decimal::ZERO);
+    }
+    let mut powers: Vec<(Node, Node)> = Vec::new();
+    for mut factor in collected {
+        let (base, exp) = match &mut factor {
+            Node::Pow(base, exp) if plain => (base.take(), exp.take()),
+            _ => (factor, Node::Num(Decimal::ONE)),
+        };
+        match powers.iter_mut().find(|(like, _)| plain && *like == base) {
+            Some((_, total)) => *total = sum(Node::Add(bx(total.clone()), bx(exp))),
+            None => powers.push((base, exp)),
+        }
+    }
+    let mut factors: Vec<Node> = powers
+        .into_iter()
+        .map(|(base, exp)| pow(base, exp))
+        .collect();
+    if plain {
+        factors.sort_by_key(|factor| factor.to_string());
+    }
+    // 合并之后的因子也可能是常数（例如 x^-1 * x），重新并入系数
+    let mut res: Option<Node> = None;
+    for factor in factors {
+        if let Node::Num(num) = factor
+            && coef.checked_mul(num).is_some()
+        {
+            coef *= num;
+            continue;
+        }
+        res = Some(match res {
+            Some(acc) => Node::Mul(bx(acc), bx(factor)),
+            None => factor,
+        });
+    }
+    match res {
+        Some(res) => with_coef(coef, res),
+        None => Node::Num(coef),
+    }
+}
+
+fn div(mut lhs: Node, mut rhs: Node) -> Node {
+    use Node::*;
+    match (&mut lhs, &mut rhs) {
+        // 只折叠能整除的常数，避免 1 / 3 变成一长串小数
+        (Num(a), Num(b)) if exact_div(*a, *b).is_some() => Num(exact_div(*a, *b).unwrap()),
+        (_, r) if is_num(r, Decimal::ONE) => lhs,
+        // 和通常的代数化简一样不考虑分母为 0 的点：0 / t => 0，t / t => 1，t 必须有定义
+        (l, r) if is_num(l, Decimal::ZERO) && !matches!(r, Num(_)) && is_total(r) => lhs,
+        (l, r) if *l == *r && is_total(l) => Num(Decimal::ONE),
+        (Neg(val), _) => sum(Neg(bx(div(val.take(), rhs)))),
+        (_, Neg(val)) => sum(Neg(bx(div(lhs, val.take())))),
+        _ => Div(bx(lhs), bx(rhs)),
+    }
+}
+
+fn pow(lhs: Node, rhs: Node) -> Node {
+    use Node::*;
+    match (lhs, rhs) {
+        // 只折叠非负整数次幂，结果是精确的
+        (Num(a), Num(b))
+            if b.fract().is_zero()
+                && !b.is_sign_negative()
+                && i64::try_from(b).is_ok_and(|n| a.checked_powi(n).is_some()) =>
+        {
+            Num(a.powi(i64::try_from(b).unwrap()))
+        }
+        (lhs, rhs) if is_num(&rhs, Decimal::ZERO) && is_total(&lhs) => Num(Decimal::ONE),
+        (lhs, rhs) if is_num(&rhs, Decimal::ONE) => lhs,
+        (lhs, _) if is_num(&lhs, Decimal::ONE) => Num(Decimal::ONE),
+        (lhs, rhs) => Pow(bx(lhs), bx(rhs)),
+    }
+}
+
+// 参数都是常数的整数函数直接求出结果
+fn call(func: Function, args: Vec<Node>) -> Node {
+    if func.is_exact() && args.iter().all(|arg| matches!(arg, Node::Num(_))) {
+        let values = args
+            .iter()
+            .map(|arg| match arg {
+                Node::Num(num) => Value::Num(*num),
+                _ => unreachable!(),
+            })
+            .collect();
+        // 出错的调用保持原样，求值的时候再报告错误
+        if let Ok(Value::Num(num)) = func.call(values, &mut Context::with_seed(0)) {
+            return Node::Num(num);
+        }
+    }
+    Node::Call(func, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::{ast::Ast, parser::Parser};
+    use rust_decimal::dec;
+
+    fn simplified(expr: &str) -> String {
+        Parser::parse(expr).unwrap().simplify().to_string()
+    }
+
+    #[test]
+    fn test_identities() {
+        assert_eq!(simplified("x * 1 + 0"), "x");
+        assert_eq!(simplified("0 - x"), "-x");
+        assert_eq!(simplified("x ^ 1 * (y - 0)"), "x*y");
+        assert_eq!(simplified("x ^ 0"), "1");
+        assert_eq!(simplified("0 * (x^2 + y)"), "0");
+        assert_eq!(simplified("x / x"), "1");
+        assert_eq!(simplified("(x + 1) / (x + 1) * y"), "y");
+        assert_eq!(simplified("-(-x) / 1"), "x");
+        assert_eq!(simplified("x - x"), "0");
+        assert_eq!(simplified("(x + y) - (y + x)"), "0");
+    }
+
+    #[test]
+    fn test_undefined_terms() {
+        // 可能没有定义的项不能乘以 0 消去，否则求值时的错误就被吞掉了
+        assert_eq!(simplified("0 * (1 / 0)"), "0*(1/0)");
+        assert_eq!(simplified("0 * sin(x)"), "0*sin(x)");
+        assert_eq!(simplified("sin(x) - sin(x)"), "0*sin(x)");
+        assert_eq!(simplified("(1 / 0) ^ 0"), "(1/0)^0");
+        assert_eq!(simplified("sin(x) / sin(x)"), "sin(x)/sin(x)");
+        let ast = Parser::parse("0 * (1 / 0)").unwrap().simplify();
+        assert!(ast.eval(&mut Context::with_seed(0)).is_err());
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(simplified("2 * 3 + x"), "x + 6");
+        assert_eq!(simplified("x * 2 * 3"), "6*x");
+        assert_eq!(simplified("2 ^ 10"), "1024");
+        assert_eq!(simplified("6 / 4"), "1.5");
+        assert_eq!(simplified("gcd(12, 18) * x"), "6*x");
+        // 不能整除的不折叠，非整数次幂也不折叠
+        assert_eq!(simplified("1 / 3"), "1/3");
+        assert_eq!(simplified("4 / 3"), "4/3");
+        assert_eq!(simplified("2 ^ 0.5"), "2^0.5");
+        assert_eq!(simplified("x - (-2) + -y"), "x - y + 2");
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        assert_eq!(simplified("1 + x + x^2"), "x^2 + x + 1");
+        assert_eq!(simplified("y * x * 2"), "2*x*y");
+        assert_eq!(simplified("x + y + x"), "2*x + y");
+        assert_eq!(simplified("x * x^2 * y"), "x^3*y");
+        assert_eq!(simplified("3 - (x + 1)"), "-x + 2");
+        assert_eq!(simplified("-(2 * x) + 5 * x"), "3*x");
+    }
+
+    #[test]
+    fn test_matrices_and_random() {
+        // 矩阵乘法不满足交换律，也不能把 A - A 化简成数 0
+        assert_eq!(
+            simplified("[[1, 2]] * x * [[3], [4]]"),
+            "[[1, 2]]*x*[[3], [4]]"
+        );
+        assert_eq!(simplified("[1, 2] - [1, 2]"), "[1, 2] - [1, 2]");
+        // 两次调用 rand() 的结果不同，不能合并
+        assert_eq!(simplified("rand() - rand()"), "rand() - rand()");
+    }
+
+    #[test]
+    fn test_same_value() {
+        // 化简前后在不同的变量取值下结果相同
+        let exprs = [
+            "(x + 1)^2 - x*x + 3*x*2 - (4 - x)",
+            "x^2 * x / (x * 1) + 0 * x - -x",
+            "sum(k, 1, 5, k*x + x*k)",
+        ];
+        for expr in exprs {
+            let ast = Parser::parse(expr).unwrap();
+            let simple = ast.simplify();
+            for x in [dec!(-2), dec!(0.5), dec!(3)] {
+                let mut ctx = Context::with_seed(0);
+                let expected = ctx.scoped("x", Value::Num(x), |ctx| ast.eval(ctx));
+                let actual = ctx.scoped("x", Value::Num(x), |ctx| simple.eval(ctx));
+                assert_eq!(actual, expected, "'{expr}' simplified to '{simple}'");
+            }
+        }
+        let Ast(node) = Parser::parse("x * 1").unwrap();
+        assert_eq!(simplify(node), Node::Var("x".to_string()));
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt::Display;
+
+use crate::calc::{
+    ast::Node,
+    context::Context,
+    function::Function,
+    parser::rpn_token,
+    token::{Operator, Token},
+    value::Value,
+};
+
+// 最多可以撤销的行数
+const MAX_UNDO: usize = 100;
+
+// HP 计算器风格的栈：每一行输入的词和逆波兰式相同，数和变量压栈，运算符和函数弹出运算数再压入结果，
+// 另外还有 swap、dup、drop、roll、clear 这些栈操作。一行中的任何一个词出错，整行都不生效
+#[derive(Default)]
+pub struct Stack {
+    items: Vec<Value>,
+    // 每一行执行之前的栈，用于撤销
+    history: Vec<Vec<Value>>,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Stack::default()
+    }
+
+    // 弹出栈顶的 n 个元素，按压栈的顺序排列
+    fn pop(&mut self, n: usize, word: &str) -> Result<Vec<Value>, String> {
+        let len = self.items.len();
+        if len < n {
+            return Err(format!(
+                "'{word}' needs {n} item(s) on the stack, found {len}"
+            ));
+        }
+        Ok(self.items.split_off(len - n))
+    }
+
+    fn apply(&mut self, word: &str, ctx: &mut Context) -> Result<(), String> {
+        match word {
+            "swap" => {
+                let mut top = self.pop(2, word)?;
+                top.swap(0, 1);
+                self.items.extend(top);
+            }
+            "dup" => {
+                let top = self.pop(1, word)?;
+                self.items.extend(top.clone());
+                self.items.extend(top);
+            }
+            "drop" => {
+                self.pop(1, word)?;
+            }
+            // 栈顶的元素移到栈底，其他元素上移一层
+            "roll" => {
+                if !self.items.is_empty() {
+                    self.items.rotate_right(1);
+                }
+            }
+            "clear" => self.items.clear(),
+            _ => {
+                let value = match rpn_token(word)? {
+                    Token::Num(num) => Value::Num(num),
+                    Token::Ident(name) => Node::Var(name).eval(ctx)?,
+                    Token::Op(Operator::Eq) => {
+                        return Err("Equations are not supported in stack mode".to_string());
+                    }
+                    Token::Op(Operator::Neg) => self.pop(1, word)?.remove(0).neg()?,
+                    // 和中缀表达式的求值使用相同的运算，结果完全一致
+                    Token::Op(op) => {
+                        let mut args = self.pop(2, word)?;
+                        let rhs = args.pop().unwrap();
+                        Value::operate(op, args.pop().unwrap(), rhs)?
+                    }
+                    Token::List(count) => Value::list(self.pop(count, word)?)?,
+                    // 惰性求值的函数、sum 和 prod 的参数是表达式而不是值
+                    Token::Func(func, _)
+                        if func.is_lazy() || matches!(func, Function::Sum | Function::Prod) =>
+                    {
+                        return Err(format!(
+                            "Function '{func}' can't be applied to values on the stack"
+                        ));
+                    }
+                    Token::Func(func, count) => {
+                        func.check_arity(count)?;
+                        func.call(self.pop(count, word)?, ctx)?
+                    }
+                    _ => unreachable!(),
+                };
+                self.items.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    // 执行一行输入，单独一行的 undo 撤销上一行
+    pub fn execute(&mut self, line: &str, ctx: &mut Context) -> Result<(), String> {
+        if line.trim() == "undo" {
+            self.items = self.history.pop().ok_or("Nothing to undo")?;
+            return Ok(());
+        }
+        let saved = self.items.clone();
+        ctx.reset_iterations();
+        for word in line.split_whitespace() {
+            if let Err(err) = self.apply(word, ctx) {
+                self.items = saved;
+                return Err(err);
+            }
+        }
+        self.history.push(saved);
+        if self.history.len() > MAX_UNDO {
+            self.history.remove(0);
+        }
+        Ok(())
+    }
+}
+
+// 和 HP 计算器一样，栈顶是第 1 层，显示在最下面
+impl Display for Stack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.items.is_empty() {
+            return write!(f, "Empty stack");
+        }
+        let len = self.items.len();
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {item}", len - i)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::parser::Parser;
+
+    fn run(lines: &[&str]) -> Result<String, String> {
+        let mut stack = Stack::new();
+        let mut ctx = Context::with_seed(0);
+        for line in lines {
+            stack.execute(line, &mut ctx)?;
+        }
+        Ok(stack.to_string())
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(run(&["3 4 +", "2 *"]), Ok("1: 14".to_string()));
+        // 结果和中缀表达式的求值完全相同
+        let mut ctx = Context::with_seed(0);
+        for (line, expr) in [
+            ("1 3 / 3 *", "1/3*3"),
+            ("2 0.5 ^ neg", "-(2^0.5)"),
+            ("-8 1 3 / ^", "(-8)^(1/3)"),
+            ("1 2 [2]", "[1, 2]"),
+            ("1 2 [2] 3 4 [2] [2] det", "det([[1, 2], [3, 4]])"),
+            ("12 18 8 gcd:3 pi *", "gcd(12, 18, 8)*pi"),
+        ] {
+            let mut stack = Stack::new();
+            stack.execute(line, &mut ctx).unwrap();
+            let expected = Parser::parse(expr).unwrap().eval(&mut ctx).unwrap();
+            assert_eq!(stack.items, [expected], "{line}");
+        }
+        // 逆波兰式没有优先级，-2 是底数
+        assert_eq!(
+            run(&["-2 0.5 ^"]),
+            Err("Cannot raise negative number '-2' to power '0.5'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stack_operations() {
+        assert_eq!(run(&["1 2 swap"]), Ok("2: 2\n1: 1".to_string()));
+        assert_eq!(run(&["1 dup +"]), Ok("1: 2".to_string()));
+        assert_eq!(run(&["1 2 drop"]), Ok("1: 1".to_string()));
+        assert_eq!(run(&["1 2 3 roll"]), Ok("3: 3\n2: 1\n1: 2".to_string()));
+        assert_eq!(run(&["roll"]), Ok("Empty stack".to_string()));
+        assert_eq!(run(&["1 2 clear"]), Ok("Empty stack".to_string()));
+    }
+
+    #[test]
+    fn test_undo_and_errors() {
+        assert_eq!(run(&["1 2", "+", "undo"]), Ok("2: 1\n1: 2".to_string()));
+        assert_eq!(
+            run(&["1", "2", "undo", "undo"]),
+            Ok("Empty stack".to_string())
+        );
+        assert_eq!(run(&["undo"]), Err("Nothing to undo".to_string()));
+        // 出错的行不生效，也不会记录到撤销的历史中
+        let mut stack = Stack::new();
+        let mut ctx = Context::with_seed(0);
+        stack.execute("1 2", &mut ctx).unwrap();
+        assert_eq!(
+            stack.execute("+ + +", &mut ctx),
+            Err("'+' needs 2 item(s) on the stack, found 1".to_string())
+        );
+        assert_eq!(stack.to_string(), "2: 1\n1: 2");
+        assert_eq!(
+            stack.execute("0 /", &mut ctx),
+            Err("Division by zero".to_string())
+        );
+        assert_eq!(stack.to_string(), "2: 1\n1: 2");
+        stack.execute("undo", &mut ctx).unwrap();
+        assert_eq!(stack.to_string(), "Empty stack");
+        assert_eq!(run(&["x"]), Err("Unknown variable: 'x'".to_string()));
+        assert_eq!(
+            run(&["1 2 diff:2"]),
+            Err("Function 'diff' can't be applied to values on the stack".to_string())
+        );
+        assert_eq!(
+            run(&["1 sqrt:2"]),
+            Err("Function 'sqrt' expects 1 argument(s), found 2".to_string())
+        );
+        assert_eq!(
+            run(&["1 1 ="]),
+            Err("Equations are not supported in stack mode".to_string())
+        );
+    }
+}
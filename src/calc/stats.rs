@@ -0,0 +1,123 @@
+use rust_decimal::{Decimal, MathematicalOps};
+
+use crate::calc::{
+    context::Rng,
+    integer::to_decimal,
+    token::Operator,
+    value::{checked, checked_div},
+};
+
+// [a, b] 之间均匀分布的整数
+pub fn randint(rng: &mut Rng, a: i128, b: i128) -> Result<Decimal, String> {
+    if a > b {
+        return Err(format!(
+            "Expected a <= b in randint(a, b), found a = '{a}', b = '{b}'"
+        ));
+    }
+    let range = (b - a) as u128 + 1;
+    to_decimal(a + rng.below(range) as i128)
+}
+
+fn math_err(func: &str) -> String {
+    format!("Failed to calculate '{func}'")
+}
+
+// 正态分布，使用 Box-Muller 变换
+pub fn normal(rng: &mut Rng, mu: Decimal, sigma: Decimal) -> Result<Decimal, String> {
+    if sigma.is_sign_negative() && !sigma.is_zero() {
+        return Err(format!(
+            "Standard deviation must not be negative, found '{sigma}'"
+        ));
+    }
+    // 1 - unit() 落在 (0, 1]，避免对 0 取对数
+    let u1 = Decimal::ONE - rng.unit();
+    let u2 = rng.unit();
+    let r =
+        (Decimal::NEGATIVE_ONE * Decimal::TWO * u1.checked_ln().ok_or_else(|| math_err("ln"))?)
+            .sqrt()
+            .ok_or_else(|| math_err("sqrt"))?;
+    let z = r
+        * (Decimal::TWO_PI * u2)
+            .checked_cos()
+            .ok_or_else(|| math_err("cos"))?;
+    checked(
+        mu.checked_add(checked(sigma.checked_mul(z), Operator::Mul)?),
+        Operator::Add,
+    )
+}
+
+pub fn mean(xs: &[Decimal]) -> Result<Decimal, String> {
+    let mut sum = Decimal::ZERO;
+    for x in xs {
+        sum = checked(sum.checked_add(*x), Operator::Add)?;
+    }
+    checked_div(sum, Decimal::from(xs.len()))
+}
+
+// 样本标准差（除以 n - 1），只有一个样本时为 0
+pub fn std(xs: &[Decimal]) -> Result<Decimal, String> {
+    if xs.len() < 2 {
+        return Ok(Decimal::ZERO);
+    }
+    let mean = mean(xs)?;
+    let mut sum = Decimal::ZERO;
+    for x in xs {
+        let d = checked(x.checked_sub(mean), Operator::Sub)?;
+        sum = checked(
+            sum.checked_add(checked(d.checked_mul(d), Operator::Mul)?),
+            Operator::Add,
+        )?;
+    }
+    checked_div(sum, Decimal::from(xs.len() - 1))?
+        .sqrt()
+        .ok_or_else(|| math_err("sqrt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_mean_std() {
+        let xs = [
+            dec!(2),
+            dec!(4),
+            dec!(4),
+            dec!(4),
+            dec!(5),
+            dec!(5),
+            dec!(7),
+            dec!(9),
+        ];
+        assert_eq!(mean(&xs), Ok(dec!(5)));
+        assert!((std(&xs).unwrap() - dec!(2.1380899352993950)).abs() < dec!(0.0000000001));
+        assert_eq!(std(&[dec!(3)]), Ok(dec!(0)));
+    }
+
+    #[test]
+    fn test_randint() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let x = randint(&mut rng, -2, 3).unwrap();
+            assert!(x >= dec!(-2) && x <= dec!(3));
+        }
+        assert_eq!(randint(&mut rng, 5, 5), Ok(dec!(5)));
+        assert_eq!(
+            randint(&mut rng, 3, 2),
+            Err("Expected a <= b in randint(a, b), found a = '3', b = '2'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normal() {
+        let mut rng = Rng::new(2024);
+        let xs: Vec<Decimal> = (0..2000)
+            .map(|_| normal(&mut rng, dec!(10), dec!(2)).unwrap())
+            .collect();
+        // 2000 个样本的均值和标准差应该接近参数
+        assert!((mean(&xs).unwrap() - dec!(10)).abs() < dec!(0.2));
+        assert!((std(&xs).unwrap() - dec!(2)).abs() < dec!(0.2));
+        assert_eq!(normal(&mut rng, dec!(1), dec!(0)), Ok(dec!(1)));
+    }
+}
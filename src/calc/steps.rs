@@ -0,0 +1,162 @@
+use crate::calc::{
+    ast::{Ast, Node},
+    context::{Context, deep},
+    value::Value,
+};
+
+// 已经是值的节点：数和元素都是值的列表
+fn is_value(node: &Node) -> bool {
+    match node {
+        Node::Num(_) => true,
+        Node::Matrix(elems) => deep(|| elems.iter().all(is_value)),
+        _ => false,
+    }
+}
+
+// 把中间结果写回表达式，表达式的值（例如 diff 的结果）不能写回时返回 None
+fn value_node(value: Value) -> Option<Node> {
+    let row =
+        |row: &[rust_decimal::Decimal]| Node::Matrix(row.iter().map(|x| Node::Num(*x)).collect());
+    match value {
+        Value::Num(num) | Value::Approx(num, _) => Some(Node::Num(num)),
+        Value::Matrix(mat) if mat.rows() == 1 => Some(row(mat.row(0))),
+        Value::Matrix(mat) => Some(Node::Matrix(
+            (0..mat.rows()).map(|i| row(mat.row(i))).collect(),
+        )),
+        Value::Expr(_) => None,
+    }
+}
+
+// 按照求值的顺序先求的运算数，只有运算数都求值之后才求值的节点才有；
+// 惰性求值的函数、sum 和 prod 的参数不是按顺序求值的，整个节点作为一步
+fn operands(node: &mut Node) -> Vec<&mut Node> {
+    use Node::*;
+    match node {
+        Add(lhs, rhs) | Sub(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) | Pow(lhs, rhs) => {
+            vec![lhs, rhs]
+        }
+        Neg(val) => vec![val],
+        Matrix(elems) => elems.iter_mut().collect(),
+        Call(func, args) if !func.is_lazy() => args.iter_mut().collect(),
+        _ => vec![],
+    }
+}
+
+// 化简最左边的一个还没有求值、运算数都已经求值的子表达式，和 Node::eval 的求值顺序相同。
+// 子表达式直接在 node 中替换，整个 node 都求出值的时候不修改 node，返回它的值。
+// 在原地替换而不是复制整个表达式，很深的表达式每一步也只需要线性的时间
+fn reduce(node: &mut Node, ctx: &mut Context) -> Result<Option<Value>, String> {
+    let Some(operand) = operands(node)
+        .into_iter()
+        .find(|operand| !is_value(operand))
+    else {
+        return Ok(Some(node.eval(ctx)?));
+    };
+    if let Some(value) = deep(|| reduce(operand, ctx))? {
+        match value_node(value) {
+            Some(reduced) => *operand = reduced,
+            // 符号结果只能交给整个节点一起求值
+            None => return Ok(Some(node.eval(ctx)?)),
+        }
+    }
+    Ok(None)
+}
+
+impl Ast {
+    // 逐步求值，每一步化简一个子表达式，返回每一步之后的表达式（第一项是原来的表达式，最后一项是结果）和最终的值。
+    // 中途出错时也返回出错之前的各步，错误放在最终的值里
+    pub fn trace(&self, ctx: &mut Context) -> (Vec<String>, Result<Value, String>) {
+        let mut node = self.0.clone();
+        let mut steps = vec![node.to_string()];
+        loop {
+            match reduce(&mut node, ctx) {
+                Ok(None) => steps.push(node.to_string()),
+                Ok(Some(value)) => {
+                    let text = value.to_string();
+                    if steps.last() != Some(&text) {
+                        steps.push(text);
+                    }
+                    return (steps, Ok(value));
+                }
+                Err(err) => return (steps, Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::parser::Parser;
+
+    fn steps(expr: &str) -> Result<Vec<String>, String> {
+        let ast = Parser::parse(expr)?;
+        let (steps, value) = ast.trace(&mut Context::with_seed(0));
+        value.map(|_| steps)
+    }
+
+    #[test]
+    fn test_steps() {
+        assert_eq!(
+            steps("(3.5 + 2.5)*(4 - 1)/2^2").unwrap(),
+            [
+                "(3.5 + 2.5)*(4 - 1)/2^2",
+                "6.0*(4 - 1)/2^2",
+                "6.0*3/2^2",
+                "18.0/2^2",
+                "18.0/4",
+                "4.5"
+            ]
+        );
+        assert_eq!(steps("2*x").unwrap_err(), "Unknown variable: 'x'");
+        assert_eq!(steps("7").unwrap(), ["7"]);
+        // 中间结果和最终结果一样保留小数的位数
+        assert_eq!(steps("0.5*4 + 1").unwrap(), ["0.5*4 + 1", "2.0 + 1", "3.0"]);
+        // 负的中间结果按优先级加上括号
+        assert_eq!(steps("2^(1 - 3)").unwrap(), ["2^(1 - 3)", "2^-2", "0.25"]);
+        // 列表的元素依次求值，sum 作为一步
+        assert_eq!(
+            steps("[1 + 1, sum(k, 1, 3, k)]*2").unwrap(),
+            [
+                "[1 + 1, sum(k, 1, 3, k)]*2",
+                "[2, sum(k, 1, 3, k)]*2",
+                "[2, 6]*2",
+                "[4, 12]"
+            ]
+        );
+        assert_eq!(
+            steps("sqrt(4) + gcd(-3, 6)").unwrap(),
+            ["sqrt(4) + gcd(-3, 6)", "2 + gcd(-3, 6)", "2 + 3", "5"]
+        );
+    }
+
+    #[test]
+    fn test_symbolic_steps() {
+        // diff 的结果是表达式，不能写回，和外层一起求值
+        let (steps, value) = Parser::parse("diff(x^2, x)")
+            .unwrap()
+            .trace(&mut Context::with_seed(0));
+        assert_eq!(steps, ["diff(x^2, x)", "2*x"]);
+        assert_eq!(value.unwrap().to_string(), "2*x");
+        let (steps, _) = Parser::parse("1 + 2*integrate(x, x, 0, 1)")
+            .unwrap()
+            .trace(&mut Context::with_seed(0));
+        assert_eq!(
+            steps,
+            ["1 + 2*integrate(x, x, 0, 1)", "1 + 2*0.5", "1 + 1.0", "2.0"]
+        );
+    }
+
+    #[test]
+    fn test_partial_steps() {
+        // 出错之前的各步仍然保留
+        let (steps, value) = Parser::parse("1/(2 - 2) + 3")
+            .unwrap()
+            .trace(&mut Context::with_seed(0));
+        assert_eq!(steps, ["1/(2 - 2) + 3", "1/0 + 3"]);
+        assert_eq!(value.unwrap_err(), "Division by zero");
+        let (steps, value) = Parser::parse("2*x").unwrap().trace(&mut Context::with_seed(0));
+        assert_eq!(steps, ["2*x"]);
+        assert_eq!(value.unwrap_err(), "Unknown variable: 'x'");
+    }
+}
@@ -2,27 +2,45 @@ use std::fmt::Display;
 
 use rust_decimal::Decimal;
 
+use crate::calc::function::Function;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum Operator {
     Add,
     Sub,
     Mul,
     Div,
     Pow,
+    // 取负，一元运算符，只会由 basic_check 在标识符或括号前的负号处产生，分词器不会产生
+    Neg,
+    // 开平方，一元的前缀运算符，由 Unicode 模式下的 √ 产生，和取负一样只作用于紧跟的操作数，√x^2 是 √(x^2)
+    Sqrt,
+    // 方程的等号，优先级最低，只能出现在 solve 的参数里
+    Eq,
 }
 
 impl Operator {
     pub fn priority(&self) -> u8 {
         use Operator::*;
         match self {
+            Eq => 0,
             Add | Sub => 1,
             Mul | Div => 2,
-            Pow => 3,
+            Pow | Neg | Sqrt => 3,
         }
     }
 
     pub fn is_left_associative(self) -> bool {
-        if self == Operator::Pow { false } else { true }
+        !matches!(self, Operator::Pow | Operator::Neg | Operator::Sqrt)
+    }
+
+    pub fn is_unary(self) -> bool {
+        matches!(self, Operator::Neg | Operator::Sqrt)
     }
 }
 
@@ -37,19 +55,36 @@ impl Display for Operator {
         use Operator::*;
         match self {
             Add => write!(f, "+"),
-            Sub => write!(f, "-"),
+            Sub | Neg => write!(f, "-"),
             Mul => write!(f, "*"),
             Div => write!(f, "/"),
             Pow => write!(f, "^"),
+            Eq => write!(f, "="),
+            Sqrt => write!(f, "√"),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
 pub enum Token {
     Op(Operator),
+    // 圆括号 '(' ')' 和方括号 '[' ']'
     Paren(char),
+    #[cfg_attr(feature = "serde", serde(with = "rust_decimal::serde::str"))]
     Num(Decimal),
+    Ident(String),
+    Comma,
+    // 函数调用，由 basic_check 从 “标识符 左括号” 的序列合并而来，参数个数由 get_rpn 填写
+    Func(Function, usize),
+    // 方括号列表（矩阵的一行或者多行），只会出现在逆波兰式中，参数为元素个数
+    List(usize),
+    #[cfg_attr(feature = "serde", serde(rename = "eof"))]
     EOF,
 }
 
@@ -60,6 +95,10 @@ impl Display for Token {
             Op(op) => op.fmt(f),
             Paren(lr) => write!(f, "{lr}"),
             Num(num) => write!(f, "{num}"),
+            Ident(name) => write!(f, "{name}"),
+            Comma => write!(f, ","),
+            Func(func, _) => func.fmt(f),
+            List(len) => write!(f, "[{len}]"),
             EOF => write!(f, "EOF"),
         }
     }
@@ -1,115 +1,406 @@
-use std::{iter::Peekable, str::Chars};
+use rust_decimal::Decimal;
 
 use crate::calc::token::Operator;
 use crate::calc::token::Token;
 
+// Decimal 的尾数是 96 位无符号整数
+const MAX_MANTISSA: u128 = (1 << 96) - 1;
+const MAX_SCALE: u32 = 28;
+
+// 输入中的一段，start..end 是字节下标
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn text(self, expr: &str) -> &str {
+        &expr[self.start..self.end]
+    }
+
+    // 错误信息中的列号，按字符计数，从 1 开始
+    pub fn column(self, expr: &str) -> usize {
+        expr[..self.start].chars().count() + 1
+    }
+
+    // 从 self 的开头到 other 的结尾，例如合并了负号的数
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+
+    // 带有列号的错误信息，分词、basic_check 和 get_rpn 的错误都用这个格式
+    pub fn error(self, expr: &str, message: &str) -> String {
+        format!("{message} at column {}", self.column(expr))
+    }
+}
+
+// 分词的结果：标识符引用输入中的切片，数字已经解析成 Decimal
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Lexeme<'a> {
+    Num(Decimal),
+    Ident(&'a str),
+    Op(Operator),
+    Paren(char),
+    Comma,
+    EOF,
+}
+
+impl From<Lexeme<'_>> for Token {
+    fn from(lexeme: Lexeme<'_>) -> Self {
+        match lexeme {
+            Lexeme::Num(num) => Token::Num(num),
+            Lexeme::Ident(name) => Token::Ident(name.to_string()),
+            Lexeme::Op(op) => Token::Op(op),
+            Lexeme::Paren(paren) => Token::Paren(paren),
+            Lexeme::Comma => Token::Comma,
+            Lexeme::EOF => Token::EOF,
+        }
+    }
+}
+
+// 接受的数字和运算符。Ascii 只接受 ASCII 数字；Unicode 另外接受全角数字和阿拉伯-印度数字，
+// 以及 × ÷ − √ ² 这几个运算符，分别等价于 * / - sqrt 和 ^2，其中 √ 是前缀运算符
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Numerals {
+    Ascii,
+    Unicode,
+}
+
+// 全角数字、阿拉伯-印度数字和波斯语等使用的扩展阿拉伯-印度数字的值
+fn unicode_digit(c: char) -> Option<u32> {
+    match c {
+        '０'..='９' => Some(c as u32 - '０' as u32),
+        '٠'..='٩' => Some(c as u32 - '٠' as u32),
+        '۰'..='۹' => Some(c as u32 - '۰' as u32),
+        _ => None,
+    }
+}
+
+// 标识符中的字母，不包括 Ⅻ 这样同时也是数字的字母
+fn is_letter(c: char) -> bool {
+    c == '_' || (c.is_alphabetic() && !c.is_numeric())
+}
+
+// 整个字符串是否是一个标识符，规则和 Tokenizer::ident 相同
+pub fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(is_letter) && chars.all(|c| c.is_ascii_digit() || is_letter(c))
+}
+
+// 按字节扫描输入，只有遇到非 ASCII 字符时才解码成 char。
+// 最后一个结果是 EOF 或者一个带有列号的错误，之后不再返回任何结果
 pub struct Tokenizer<'a> {
-    expr: Peekable<Chars<'a>>,
-    error: Option<String>,
-    reached_end: bool,
+    expr: &'a str,
+    numerals: Numerals,
+    pos: usize,
+    // ² 展开成 ^ 和 2 两个结果，第二个暂存在这里
+    pending: Option<(Lexeme<'a>, Span)>,
+    done: bool,
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn from(expr: &'a str) -> Self {
+    pub fn new(expr: &'a str, numerals: Numerals) -> Self {
         Tokenizer {
-            expr: expr.chars().peekable(),
-            error: None,
-            reached_end: false,
+            expr,
+            numerals,
+            pos: 0,
+            pending: None,
+            done: false,
         }
     }
-}
 
-impl<'a> Iterator for Tokenizer<'a> {
-    type Item = Token;
+    fn peek(&self) -> Option<u8> {
+        self.expr.as_bytes().get(self.pos).copied()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // 如果到了文件结束，那么返回
-        if self.reached_end {
-            return None;
+    // 当前位置的字符，pos 总是在字符的边界上
+    fn peek_char(&self) -> Option<char> {
+        self.expr[self.pos..].chars().next()
+    }
+
+    // 当前位置的数字的值和它占用的字节数
+    fn digit(&self) -> Option<(u32, usize)> {
+        match self.peek()? {
+            byte @ b'0'..=b'9' => Some((u32::from(byte - b'0'), 1)),
+            byte if byte.is_ascii() || self.numerals == Numerals::Ascii => None,
+            _ => {
+                let c = self.peek_char()?;
+                Some((unicode_digit(c)?, c.len_utf8()))
+            }
         }
+    }
+
+    fn error(&self, span: Span, message: &str) -> String {
+        span.error(self.expr, message)
+    }
 
-        // 消耗所有的空格
-        while let Some(_) = self.expr.next_if(|c| c.is_whitespace()) {}
-        // 查看是否有下一个字符，并以此为依据判断应该走到哪一个分支，此时消耗这个字符
-        if let Some(char) = self.expr.next() {
-            // 查看是否是数字
-            if char.is_numeric() {
-                let mut num = String::from(char);
-                let mut found_point = false;
-
-                // 尽量耗尽这一次的字符
-                while let Some(c) = self.expr.next_if(|c| {
-                    // 如果是数字字符或者小数点，那么被返回，然后匹配
-                    c.is_numeric() || *c == '.'
-                }) {
-                    // 当是小数点的时候，要检查是否已经有一个小数点，如果有，说明输入错误，将这个字符加入错误中
-                    if c == '.' {
-                        if found_point {
-                            self.error = Some("Multiple decimal point.".to_string());
-                            return None;
-                        }
-                        found_point = true;
+    fn skip_whitespace(&mut self) {
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii() {
+                if !(byte as char).is_whitespace() {
+                    break;
+                }
+                self.pos += 1;
+            } else {
+                match self.peek_char() {
+                    Some(c) if c.is_whitespace() => self.pos += c.len_utf8(),
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    // 数字由数字字符和最多一个小数点组成，逐位累加到尾数上，不构造中间的字符串。
+    // 超出 Decimal 的精度时和 Decimal::from_str 一样，按舍去的第一位四舍五入，整数部分太大时报错
+    fn number(&mut self, start: usize) -> Result<Decimal, String> {
+        let mut mantissa: u128 = 0;
+        let mut scale = 0;
+        let mut point = false;
+        // 舍去的第一位小数，之后的数字都被忽略
+        let mut dropped = None;
+        let mut overflow = false;
+        loop {
+            if let Some((digit, len)) = self.digit() {
+                if dropped.is_none() && !overflow {
+                    let next = mantissa * 10 + u128::from(digit);
+                    if next <= MAX_MANTISSA && scale + u32::from(point) <= MAX_SCALE {
+                        mantissa = next;
+                        scale += u32::from(point);
+                    } else if point {
+                        dropped = Some(digit);
+                    } else {
+                        overflow = true;
                     }
-                    num.push(c);
                 }
+                self.pos += len;
+            } else if self.peek() == Some(b'.') {
+                if point {
+                    let span = Span {
+                        start: self.pos,
+                        end: self.pos + 1,
+                    };
+                    return Err(self.error(span, "Multiple decimal points in number"));
+                }
+                point = true;
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if dropped >= Some(5) {
+            mantissa += 1;
+            if mantissa > MAX_MANTISSA {
+                // 进位之后尾数溢出，再舍去一位
+                overflow = scale == 0;
+                mantissa = (mantissa + 4) / 10;
+                scale = scale.saturating_sub(1);
+            }
+        }
+        if overflow {
+            let span = Span {
+                start,
+                end: self.pos,
+            };
+            let message = format!("Number '{}' is out of range", span.text(self.expr));
+            return Err(self.error(span, &message));
+        }
+        Ok(Decimal::from_i128_with_scale(mantissa as i128, scale))
+    }
 
-                Some(Token::Num(num.parse().unwrap()))
+    // 标识符：以字母或下划线开头，后面可以跟字母、ASCII 数字和下划线
+    fn ident(&mut self, start: usize) -> &'a str {
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_alphanumeric() || byte == b'_' {
+                self.pos += 1;
+            } else if byte.is_ascii() {
+                break;
             } else {
-                // 如果是非数字的符号
-                match char {
-                    '+' => Some(Token::Op(Operator::Add)),
-                    '-' => Some(Token::Op(Operator::Sub)),
-                    '*' => Some(Token::Op(Operator::Mul)),
-                    '/' => Some(Token::Op(Operator::Div)),
-                    '^' => Some(Token::Op(Operator::Pow)),
-
-                    '(' => Some(Token::Paren('(')),
-                    ')' => Some(Token::Paren(')')),
-
-                    err => {
-                        let mut base = "error character: ".to_string();
-                        base.push(err);
-                        self.error = Some(base);
-                        None
+                match self.peek_char() {
+                    Some(c) if is_letter(c) => self.pos += c.len_utf8(),
+                    _ => break,
+                }
+            }
+        }
+        let expr = self.expr;
+        &expr[start..self.pos]
+    }
+
+    fn lex(&mut self) -> Result<(Lexeme<'a>, Span), String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let lexeme = match self.peek_char() {
+            None => Lexeme::EOF,
+            Some(_) if self.digit().is_some() => Lexeme::Num(self.number(start)?),
+            Some(c) if is_letter(c) => Lexeme::Ident(self.ident(start)),
+            Some(c) => {
+                self.pos += c.len_utf8();
+                match (c, self.numerals) {
+                    ('+', _) => Lexeme::Op(Operator::Add),
+                    ('-', _) => Lexeme::Op(Operator::Sub),
+                    ('*', _) => Lexeme::Op(Operator::Mul),
+                    ('/', _) => Lexeme::Op(Operator::Div),
+                    ('^', _) => Lexeme::Op(Operator::Pow),
+                    ('=', _) => Lexeme::Op(Operator::Eq),
+
+                    ('(' | ')' | '[' | ']', _) => Lexeme::Paren(c),
+                    (',', _) => Lexeme::Comma,
+
+                    ('×', Numerals::Unicode) => Lexeme::Op(Operator::Mul),
+                    ('÷', Numerals::Unicode) => Lexeme::Op(Operator::Div),
+                    ('−', Numerals::Unicode) => Lexeme::Op(Operator::Sub),
+                    ('√', Numerals::Unicode) => Lexeme::Op(Operator::Sqrt),
+                    ('²', Numerals::Unicode) => {
+                        let span = Span {
+                            start,
+                            end: self.pos,
+                        };
+                        self.pending = Some((Lexeme::Num(Decimal::TWO), span));
+                        Lexeme::Op(Operator::Pow)
+                    }
+
+                    _ => {
+                        let span = Span {
+                            start,
+                            end: self.pos,
+                        };
+                        return Err(self.error(span, &format!("Unexpected character '{c}'")));
                     }
                 }
             }
-        } else {
-            // 没有下一个字符，说明到了末尾，已经没有任意一个字符了
-            self.reached_end = true;
-            Some(Token::EOF)
+        };
+        let span = Span {
+            start,
+            end: self.pos,
+        };
+        Ok((lexeme, span))
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<(Lexeme<'a>, Span), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
+        if let Some(pending) = self.pending.take() {
+            return Some(Ok(pending));
+        }
+        let res = self.lex();
+        self.done = matches!(res, Ok((Lexeme::EOF, _)) | Err(_));
+        Some(res)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::str::FromStr;
+
     use rust_decimal::dec;
 
     use crate::calc::token::*;
     use crate::calc::tokenizer::*;
 
+    fn tokenize(expr: &str, numerals: Numerals) -> Result<Vec<Token>, String> {
+        Tokenizer::new(expr, numerals)
+            .map(|res| res.map(|(lexeme, _)| Token::from(lexeme)))
+            .collect()
+    }
+
+    fn tokens(expr: &str) -> Result<Vec<Token>, String> {
+        tokenize(expr, Numerals::Ascii)
+    }
+
+    // 出错之前的所有结果
+    fn prefix(expr: &str) -> Vec<Token> {
+        Tokenizer::new(expr, Numerals::Ascii)
+            .map_while(Result::ok)
+            .map(|(lexeme, _)| Token::from(lexeme))
+            .collect()
+    }
+
     #[test]
     fn number_parsing() {
-        let tknz = Tokenizer::from("   +   -   ;   ");
-        let res = tknz.collect::<Vec<Token>>();
+        let res = prefix("   +   -   ;   ");
         assert_eq!(
             res,
             vec![Token::Op(Operator::Add), Token::Op(Operator::Sub)]
         );
     }
 
+    #[test]
+    fn unexpected_character() {
+        assert_eq!(
+            tokens("   +   -   ;   "),
+            Err("Unexpected character ';' at column 12".to_string())
+        );
+    }
+
+    #[test]
+    fn decimal_conversion() {
+        // 和 Decimal::from_str 的结果完全相同，包括小数位数
+        for text in [
+            "0",
+            "007",
+            "1.",
+            "12.000",
+            "0.5",
+            "79228162514264337593543950335",
+            "7.9228162514264337593543950335",
+            "0.0000000000000000000000000001",
+            "0.00000000000000000000000000001",
+            "1.00000000000000000000000000005",
+            "1.00000000000000000000000000004999",
+            "7.92281625142643375935439503355",
+            "79228162514264337593543950335.4",
+        ] {
+            let expected = Decimal::from_str(text).unwrap();
+            let res = tokens(text).unwrap();
+            assert_eq!(res, vec![Token::Num(expected), Token::EOF], "{text}");
+            let Token::Num(num) = res[0] else {
+                unreachable!()
+            };
+            assert_eq!(num.scale(), expected.scale(), "{text}");
+        }
+        assert_eq!(
+            tokens("1 + 79228162514264337593543950336"),
+            Err("Number '79228162514264337593543950336' is out of range at column 5".to_string())
+        );
+        // 进位之后整数部分溢出
+        assert_eq!(
+            tokens("79228162514264337593543950335.5"),
+            Err("Number '79228162514264337593543950335.5' is out of range at column 1".to_string())
+        );
+        assert!(Decimal::from_str("79228162514264337593543950335.5").is_err());
+    }
+
     #[test]
     fn whitespace_skipping() {
-        let tknz = Tokenizer::from("   12.3  12..3   ");
-        let res = tknz.collect::<Vec<Token>>();
+        let res = prefix("   12.3  12..3   ");
         assert_eq!(res, vec![Token::Num(dec!(12.3))]);
+        // 非 ASCII 的空白也会被跳过
+        assert_eq!(
+            tokens("\t12.3\u{3000}\n"),
+            Ok(vec![Token::Num(dec!(12.3)), Token::EOF])
+        );
+    }
+
+    #[test]
+    fn multiple_decimal_points() {
+        assert_eq!(
+            tokens("   12.3  12..3   "),
+            Err("Multiple decimal points in number at column 13".to_string())
+        );
     }
 
     #[test]
     fn total_legal_condition() {
-        let tknz = Tokenizer::from(" + 12.000 -");
-        let res = tknz.collect::<Vec<Token>>();
+        let res = tokens(" + 12.000 -").unwrap();
         assert_eq!(
             res,
             vec![
@@ -120,4 +411,108 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn ident_and_brackets() {
+        let res = tokens("det([[1, x_1]])").unwrap();
+        assert_eq!(
+            res,
+            vec![
+                Token::Ident("det".to_string()),
+                Token::Paren('('),
+                Token::Paren('['),
+                Token::Paren('['),
+                Token::Num(dec!(1)),
+                Token::Comma,
+                Token::Ident("x_1".to_string()),
+                Token::Paren(']'),
+                Token::Paren(']'),
+                Token::Paren(')'),
+                Token::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let expr = "αβ_2 * 3.5";
+        let res: Vec<(Lexeme, Span)> = Tokenizer::new(expr, Numerals::Ascii)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            res,
+            vec![
+                (Lexeme::Ident("αβ_2"), Span { start: 0, end: 6 }),
+                (Lexeme::Op(Operator::Mul), Span { start: 7, end: 8 }),
+                (Lexeme::Num(dec!(3.5)), Span { start: 9, end: 12 }),
+                (Lexeme::EOF, Span { start: 12, end: 12 }),
+            ]
+        );
+        // 标识符直接引用输入中的切片
+        let Lexeme::Ident(name) = res[0].0 else {
+            unreachable!()
+        };
+        assert!(std::ptr::eq(name, &expr[..6]));
+        assert_eq!(res[2].1.text(expr), "3.5");
+        assert_eq!(res[2].1.column(expr), 8);
+    }
+
+    #[test]
+    fn errors() {
+        // 列号按字符计数，非 ASCII 的数字不会导致 panic
+        assert_eq!(
+            tokens("αβ + ٣"),
+            Err("Unexpected character '٣' at column 6".to_string())
+        );
+        assert_eq!(
+            tokens("1 # 2"),
+            Err("Unexpected character '#' at column 3".to_string())
+        );
+        assert_eq!(
+            tokens(".5"),
+            Err("Unexpected character '.' at column 1".to_string())
+        );
+        // 出错之后不再返回任何结果
+        let mut tknz = Tokenizer::new("1 $ 2", Numerals::Ascii);
+        assert!(tknz.next().unwrap().is_ok());
+        assert!(tknz.next().unwrap().is_err());
+        assert_eq!(tknz.next(), None);
+    }
+
+    #[test]
+    fn unicode_numerals() {
+        let unicode = |expr: &str| tokenize(expr, Numerals::Unicode);
+        assert_eq!(unicode("１２３"), tokens("123"));
+        assert_eq!(unicode("٣.١٤ + ۲"), tokens("3.14 + 2"));
+        assert_eq!(unicode("２×３÷４−５"), tokens("2*3/4-5"));
+        // √ 是前缀的开方运算符，不是 sqrt 这个标识符，后面不需要括号
+        let mut sqrt = tokens("(x^2)").unwrap();
+        sqrt.insert(0, Token::Op(Operator::Sqrt));
+        assert_eq!(unicode("√(x²)"), Ok(sqrt));
+        // ² 展开成的两个结果指向同一个字符
+        let spans: Vec<Span> = Tokenizer::new("x²", Numerals::Unicode)
+            .map(|res| res.unwrap().1)
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span { start: 0, end: 1 },
+                Span { start: 1, end: 3 },
+                Span { start: 1, end: 3 },
+                Span { start: 3, end: 3 },
+            ]
+        );
+        // 默认只接受 ASCII 数字，其它的数字字符都是错误，不会 panic
+        for (expr, c) in [("１２３", '１'), ("2×3", '×'), ("x²", '²'), ("√(2)", '√')] {
+            let col = expr.chars().position(|x| x == c).unwrap() + 1;
+            assert_eq!(
+                tokens(expr),
+                Err(format!("Unexpected character '{c}' at column {col}"))
+            );
+        }
+        for expr in ["Ⅻ", "³", "½", "x⁴", "߃", "१२"] {
+            assert!(tokens(expr).is_err(), "{expr}");
+            assert!(unicode(expr).is_err(), "{expr}");
+        }
+    }
 }
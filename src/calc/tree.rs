@@ -0,0 +1,145 @@
+use crate::calc::{
+    ast::{Ast, Node},
+    context::deep,
+    token::Operator,
+};
+
+// 树状图中节点的标签，一元的负号写成 neg 以区别于减号
+fn label(node: &Node) -> String {
+    use Node::*;
+    match node {
+        Add(..) => Operator::Add.to_string(),
+        Sub(..) => Operator::Sub.to_string(),
+        Mul(..) => Operator::Mul.to_string(),
+        Div(..) => Operator::Div.to_string(),
+        Pow(..) => Operator::Pow.to_string(),
+        Eq(..) => Operator::Eq.to_string(),
+        Neg(_) => "neg".to_string(),
+        Num(num) => num.to_string(),
+        Var(name) => name.clone(),
+        Matrix(_) => "[]".to_string(),
+        Call(func, _) => func.to_string(),
+        Sum(var, ..) => format!("sum {var}"),
+        Prod(var, ..) => format!("prod {var}"),
+    }
+}
+
+// 按从左到右的顺序返回子节点，sum 和 prod 的子节点是下限、上限和求和的表达式
+fn children(node: &Node) -> Vec<&Node> {
+    use Node::*;
+    match node {
+        Add(lhs, rhs)
+        | Sub(lhs, rhs)
+        | Mul(lhs, rhs)
+        | Div(lhs, rhs)
+        | Pow(lhs, rhs)
+        | Eq(lhs, rhs) => {
+            vec![lhs, rhs]
+        }
+        Neg(val) => vec![val],
+        Num(_) | Var(_) => vec![],
+        Matrix(elems) | Call(_, elems) => elems.iter().collect(),
+        Sum(_, from, to, body) | Prod(_, from, to, body) => vec![from, to, body],
+    }
+}
+
+// prefix 是这一层之前的竖线和空格，last 表示是否为父节点的最后一个子节点
+fn ascii(node: &Node, prefix: &str, last: bool, out: &mut Vec<String>) {
+    let (branch, indent) = if last {
+        ("`-- ", "    ")
+    } else {
+        ("+-- ", "|   ")
+    };
+    out.push(format!("{prefix}{branch}{}", label(node)));
+    let prefix = format!("{prefix}{indent}");
+    let children = children(node);
+    for (i, child) in children.iter().enumerate() {
+        deep(|| ascii(child, &prefix, i + 1 == children.len(), out));
+    }
+}
+
+// DOT 的字符串中需要转义的字符
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// 先序遍历给节点编号，返回这个节点的编号
+fn dot(node: &Node, out: &mut Vec<String>, next: &mut usize) -> usize {
+    let id = *next;
+    *next += 1;
+    out.push(format!("    n{id} [label=\"{}\"];", escape(&label(node))));
+    for child in children(node) {
+        let child = deep(|| dot(child, out, next));
+        out.push(format!("    n{id} -> n{child};"));
+    }
+    id
+}
+
+impl Ast {
+    // 缩进的树状图，每个子节点比父节点多缩进一层，例如 1 + 2*x：
+    // +
+    // +-- 1
+    // `-- *
+    //     +-- 2
+    //     `-- x
+    pub fn to_tree(&self) -> String {
+        let mut out = vec![label(&self.0)];
+        let children = children(&self.0);
+        for (i, child) in children.iter().enumerate() {
+            ascii(child, "", i + 1 == children.len(), &mut out);
+        }
+        out.join("\n")
+    }
+
+    // Graphviz 的 DOT 格式，ordering=out 让子节点按运算数的顺序从左到右排列
+    pub fn to_dot(&self) -> String {
+        let mut out = vec!["digraph ast {".to_string(), "    ordering=out;".to_string()];
+        dot(&self.0, &mut out, &mut 0);
+        out.push("}".to_string());
+        out.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calc::parser::Parser;
+
+    #[test]
+    fn test_tree() {
+        let tree = Parser::parse("(3.5 + 2.5)*(4 - 1)/2^2").unwrap().to_tree();
+        let expected = [
+            "/",
+            "+-- *",
+            "|   +-- +",
+            "|   |   +-- 3.5",
+            "|   |   `-- 2.5",
+            "|   `-- -",
+            "|       +-- 4",
+            "|       `-- 1",
+            "`-- ^",
+            "    +-- 2",
+            "    `-- 2",
+        ];
+        assert_eq!(tree, expected.join("\n"));
+        let tree = Parser::parse("-sum(k, 1, n, k)").unwrap().to_tree();
+        let expected = ["neg", "`-- sum k", "    +-- 1", "    +-- n", "    `-- k"];
+        assert_eq!(tree, expected.join("\n"));
+        assert_eq!(Parser::parse("x").unwrap().to_tree(), "x");
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = Parser::parse("gcd(x, 2)").unwrap().to_dot();
+        let expected = [
+            "digraph ast {",
+            "    ordering=out;",
+            "    n0 [label=\"gcd\"];",
+            "    n1 [label=\"x\"];",
+            "    n0 -> n1;",
+            "    n2 [label=\"2\"];",
+            "    n0 -> n2;",
+            "}",
+        ];
+        assert_eq!(dot, expected.join("\n"));
+    }
+}
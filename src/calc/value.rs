@@ -0,0 +1,216 @@
+use std::fmt::Display;
+
+use rust_decimal::{Decimal, MathematicalOps};
+
+use crate::calc::{ast::Ast, function::Function, matrix::Matrix, token::Operator};
+
+// 表达式求值的结果：一个数、一个矩阵，或者一个符号表达式（例如 diff 求出的导数）
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Num(Decimal),
+    Matrix(Matrix),
+    Expr(Ast),
+    // 数值方法得到的近似值和误差估计（例如 integrate 的结果），参与运算时当作普通的数，误差估计被丢弃
+    Approx(Decimal, Decimal),
+}
+
+impl From<Decimal> for Value {
+    fn from(num: Decimal) -> Self {
+        Value::Num(num)
+    }
+}
+
+impl From<Matrix> for Value {
+    fn from(mat: Matrix) -> Self {
+        Value::Matrix(mat)
+    }
+}
+
+// 检查 Decimal 的 checked_* 运算结果，溢出时返回错误而不是 panic
+pub fn checked(res: Option<Decimal>, op: Operator) -> Result<Decimal, String> {
+    res.ok_or_else(|| format!("Overflow when calculating '{op}'"))
+}
+
+pub fn checked_div(lhs: Decimal, rhs: Decimal) -> Result<Decimal, String> {
+    if rhs.is_zero() {
+        Err("Division by zero".to_string())
+    } else {
+        checked(lhs.checked_div(rhs), Operator::Div)
+    }
+}
+
+// 负数的非整数次幂只有在指数是分母为奇数的分数时才是实数，例如 (-8)^(1/3) = -2。
+// 1/3 这样的指数只能近似地表示，所以找一个不超过 999 的奇数 q，使得 exp * q 几乎是整数
+fn odd_denominator(exp: Decimal) -> bool {
+    (1..1000).step_by(2).any(|q| {
+        exp.checked_mul(Decimal::from(q))
+            .is_some_and(|p| (p - p.round()).abs() < Decimal::new(1, 20))
+    })
+}
+
+// 能精确表示的商，除不尽（例如 4 / 3）的时候返回 None。除不尽的商会用满 Decimal 的 28 位有效数字，
+// 这时乘回去可能被舍入成被除数，所以不能只靠乘回去来判断
+pub fn exact_div(lhs: Decimal, rhs: Decimal) -> Option<Decimal> {
+    let quot = lhs.checked_div(rhs)?.normalize();
+    let short = quot.mantissa().unsigned_abs() < 10u128.pow(27);
+    (short && quot.checked_mul(rhs) == Some(lhs)).then_some(quot)
+}
+
+impl Value {
+    // 把近似值当作普通的数
+    pub fn exact(self) -> Value {
+        match self {
+            Value::Approx(num, _) => Value::Num(num),
+            val => val,
+        }
+    }
+
+    pub fn into_num(self, func: Function) -> Result<Decimal, String> {
+        match self.exact() {
+            Value::Num(num) => Ok(num),
+            Value::Matrix(mat) => Err(format!(
+                "Function '{func}' expects a number, found {} matrix",
+                mat.shape()
+            )),
+            Value::Expr(expr) => Err(format!(
+                "Function '{func}' expects a number, found expression: '{expr}'"
+            )),
+            Value::Approx(..) => unreachable!(),
+        }
+    }
+
+    pub fn into_matrix(self, func: Function) -> Result<Matrix, String> {
+        match self.exact() {
+            Value::Matrix(mat) => Ok(mat),
+            Value::Num(num) => Err(format!(
+                "Function '{func}' expects a matrix, found number: '{num}'"
+            )),
+            Value::Expr(expr) => Err(format!(
+                "Function '{func}' expects a matrix, found expression: '{expr}'"
+            )),
+            Value::Approx(..) => unreachable!(),
+        }
+    }
+
+    // 由方括号列表构建矩阵：全是数的列表是一个行向量，全是行向量的列表按行拼成矩阵
+    pub fn list(elems: Vec<Value>) -> Result<Value, String> {
+        if elems.is_empty() {
+            return Err("Empty matrix".to_string());
+        }
+        let elems: Vec<Value> = elems.into_iter().map(Value::exact).collect();
+        if elems.iter().all(|elem| matches!(elem, Value::Num(_))) {
+            let row = elems
+                .into_iter()
+                .map(|elem| match elem {
+                    Value::Num(num) => num,
+                    _ => unreachable!(),
+                })
+                .collect();
+            return Ok(Value::Matrix(Matrix::from_rows(vec![row])?));
+        }
+        let mut rows = Vec::new();
+        for elem in elems {
+            match elem {
+                Value::Matrix(mat) if mat.rows() == 1 => rows.push(mat.row(0).to_vec()),
+                Value::Matrix(mat) => {
+                    return Err(format!(
+                        "Expected a row inside matrix literal, found {} matrix",
+                        mat.shape()
+                    ));
+                }
+                Value::Num(num) => {
+                    return Err(format!(
+                        "Expected a row inside matrix literal, found number: '{num}'"
+                    ));
+                }
+                Value::Expr(expr) => {
+                    return Err(format!(
+                        "Expected a row inside matrix literal, found expression: '{expr}'"
+                    ));
+                }
+                Value::Approx(..) => unreachable!(),
+            }
+        }
+        Ok(Value::Matrix(Matrix::from_rows(rows)?))
+    }
+
+    pub fn neg(self) -> Result<Value, String> {
+        match self {
+            Value::Num(num) => Ok(Value::Num(-num)),
+            Value::Approx(num, error) => Ok(Value::Approx(-num, error)),
+            Value::Matrix(mat) => Ok(Value::Matrix(mat.scale(Decimal::NEGATIVE_ONE)?)),
+            Value::Expr(expr) => Err(format!("Cannot apply '-' to expression '{expr}'")),
+        }
+    }
+
+    // 二元运算，数与数之间按普通的算术，涉及矩阵的时候检查形状
+    pub fn operate(op: Operator, lhs: Value, rhs: Value) -> Result<Value, String> {
+        use Operator::*;
+        use Value::*;
+        match (lhs.exact(), rhs.exact()) {
+            (Approx(..), _) | (_, Approx(..)) => unreachable!(),
+            (Expr(expr), _) | (_, Expr(expr)) => {
+                Err(format!("Cannot apply '{op}' to expression '{expr}'"))
+            }
+            (Num(lhs), Num(rhs)) => {
+                let res = match op {
+                    Add => lhs.checked_add(rhs),
+                    Sub => lhs.checked_sub(rhs),
+                    Mul => lhs.checked_mul(rhs),
+                    Div => return checked_div(lhs, rhs).map(Num),
+                    // 零的负数次幂相当于除以零，checked_powd 会得到 0
+                    Pow if lhs.is_zero() && rhs < Decimal::ZERO => {
+                        return Err("Division by zero".to_string());
+                    }
+                    // checked_powd 会把 (-2)^0.5 算成 -sqrt(2)
+                    Pow if lhs < Decimal::ZERO && !odd_denominator(rhs) => {
+                        return Err(format!("Cannot raise negative number '{lhs}' to power '{rhs}'"));
+                    }
+                    Pow => lhs.checked_powd(rhs),
+                    Neg | Sqrt | Eq => unreachable!(),
+                };
+                checked(res, op).map(Num)
+            }
+            (Matrix(lhs), Matrix(rhs)) => match op {
+                Add | Sub => lhs.elementwise(&rhs, op).map(Matrix),
+                Mul => lhs.mul(&rhs).map(Matrix),
+                _ => Err(format!(
+                    "Cannot apply '{op}' to {} matrix and {} matrix",
+                    lhs.shape(),
+                    rhs.shape()
+                )),
+            },
+            (Num(lhs), Matrix(rhs)) => match op {
+                Mul => rhs.scale(lhs).map(Matrix),
+                _ => Err(format!(
+                    "Cannot apply '{op}' to number '{lhs}' and {} matrix",
+                    rhs.shape()
+                )),
+            },
+            (Matrix(lhs), Num(rhs)) => match op {
+                Mul => lhs.scale(rhs).map(Matrix),
+                Div => lhs.map(|x| checked_div(x, rhs)).map(Matrix),
+                Pow => lhs.pow(rhs).map(Matrix),
+                _ => Err(format!(
+                    "Cannot apply '{op}' to {} matrix and number '{rhs}'",
+                    lhs.shape()
+                )),
+            },
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Num(num) => write!(f, "{num}"),
+            Value::Matrix(mat) => mat.fmt(f),
+            Value::Expr(expr) => expr.fmt(f),
+            // 误差估计保留两位有效数字，用科学计数法显示
+            Value::Approx(num, error) if error.is_zero() => write!(f, "{num} ± 0"),
+            Value::Approx(num, error) => {
+                write!(f, "{num} ± {:e}", error.round_sf(2).unwrap_or(*error))
+            }
+        }
+    }
+}
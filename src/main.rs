@@ -1,8 +1,155 @@
 mod calc;
 
+// 求值之前回显解析后的表达式的方式
+#[derive(PartialEq, Clone, Copy)]
+enum Echo {
+    Off,
+    // 最少的括号
+    On,
+    // 每一个复合的运算数都加上括号
+    Full,
+}
+
+// 输入的模式
+#[derive(PartialEq, Clone, Copy)]
+enum Mode {
+    // 每一行是一个独立的表达式
+    Expr(calc::Notation),
+    // HP 计算器风格的栈，每一行对同一个栈压栈或者运算
+    Stack,
+}
+
+struct Repl {
+    ctx: calc::Context,
+    echo: Echo,
+    mode: Mode,
+    stack: calc::Stack,
+}
+
+impl Repl {
+    // 以 ':' 开头的 REPL 命令
+    fn command(&mut self, cmd: &str) -> Result<String, String> {
+        let (name, arg) = cmd.split_once(' ').unwrap_or((cmd, ""));
+        let arg = arg.trim();
+        match name {
+            "seed" => {
+                let seed = arg
+                    .parse()
+                    .map_err(|_| format!("Expected a non-negative integer seed, found '{arg}'"))?;
+                self.ctx.seed(seed);
+                Ok(format!("Random seed set to {seed}"))
+            }
+            "limit" => {
+                let limit = arg
+                    .parse()
+                    .map_err(|_| format!("Expected a non-negative integer limit, found '{arg}'"))?;
+                self.ctx.max_iterations = limit;
+                Ok(format!("Iteration limit set to {limit}"))
+            }
+            "bench" => {
+                let (count, expr) = arg.split_once(' ').unwrap_or((arg, ""));
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("Expected a positive evaluation count, found '{count}'"))?;
+                calc::bench(expr.trim(), count, &self.ctx)
+            }
+            "depth" => {
+                let depth = arg
+                    .parse()
+                    .ok()
+                    .filter(|depth| *depth > 0)
+                    .ok_or_else(|| format!("Expected a positive integer depth, found '{arg}'"))?;
+                self.ctx.max_depth = depth;
+                Ok(format!("Maximum nesting depth set to {depth}"))
+            }
+            "simplify" => calc::simplify(arg, &self.ctx),
+            "rpn" => calc::rpn(arg, &self.ctx),
+            "sexpr" => calc::sexpr(arg, &self.ctx),
+            "bytes" => calc::bytes(arg, &self.ctx),
+            #[cfg(feature = "serde")]
+            "json" => calc::json(arg, &self.ctx),
+            "mode" => {
+                self.mode = match arg {
+                    "infix" => Mode::Expr(calc::Notation::Infix),
+                    "rpn" => Mode::Expr(calc::Notation::Rpn),
+                    "sexpr" => Mode::Expr(calc::Notation::Sexpr),
+                    "bytes" => Mode::Expr(calc::Notation::Bytes),
+                    #[cfg(feature = "serde")]
+                    "json" => Mode::Expr(calc::Notation::Json),
+                    "stack" => Mode::Stack,
+                    _ => {
+                        let modes = if cfg!(feature = "serde") {
+                            "'infix', 'rpn', 'sexpr', 'bytes', 'json' or 'stack'"
+                        } else {
+                            "'infix', 'rpn', 'sexpr', 'bytes' or 'stack'"
+                        };
+                        return Err(format!("Expected {modes}, found '{arg}'"));
+                    }
+                };
+                match self.mode {
+                    Mode::Stack => Ok(format!("Input mode set to {arg}\n{}", self.stack)),
+                    Mode::Expr(_) => Ok(format!("Input mode set to {arg}")),
+                }
+            }
+            "steps" => calc::steps(arg, &mut self.ctx),
+            "latex" => calc::latex(arg, &self.ctx),
+            "mathml" => calc::mathml(arg, &self.ctx),
+            "tree" => calc::tree(arg, false, &self.ctx),
+            "dot" => calc::tree(arg, true, &self.ctx),
+            "unicode" => {
+                self.ctx.numerals = match arg {
+                    "on" => calc::Numerals::Unicode,
+                    "off" => calc::Numerals::Ascii,
+                    _ => return Err(format!("Expected 'on' or 'off', found '{arg}'")),
+                };
+                Ok(format!("Unicode numerals set to {arg}"))
+            }
+            "echo" => {
+                self.echo = match arg {
+                    "on" => Echo::On,
+                    "full" => Echo::Full,
+                    "off" => Echo::Off,
+                    _ => return Err(format!("Expected 'on', 'full' or 'off', found '{arg}'")),
+                };
+                Ok(format!("Echo set to {arg}"))
+            }
+            _ => Err(format!("Unknown command: ':{name}'")),
+        }
+    }
+
+    // 回显放在求值之前，求值出错的时候也能看到表达式是怎样解析的
+    fn eval(&mut self, input: &str) -> Result<String, String> {
+        let notation = match self.mode {
+            Mode::Expr(notation) => notation,
+            Mode::Stack => {
+                self.stack.execute(input, &mut self.ctx)?;
+                return Ok(self.stack.to_string());
+            }
+        };
+        let echo = match self.echo {
+            Echo::Off => String::new(),
+            echo => {
+                let normalized =
+                    calc::normalize(input, notation, echo == Echo::Full, &self.ctx)?;
+                format!("Parsed: {normalized}\n")
+            }
+        };
+        match calc::expr(input, notation, &mut self.ctx) {
+            Ok(res) => Ok(format!("{echo}Result: {res}")),
+            Err(err) => Err(format!("{echo}{err}")),
+        }
+    }
+}
+
 fn main() {
     println!("Calculator");
     println!("Type in 'q' to quit.");
+    let mut repl = Repl {
+        ctx: calc::Context::new(),
+        echo: Echo::Off,
+        mode: Mode::Expr(calc::Notation::Infix),
+        stack: calc::Stack::new(),
+    };
     loop {
         println!("Please enter an expr below");
         let mut input = String::new();
@@ -13,11 +160,14 @@ fn main() {
             break;
         } else if input.is_empty() {
             continue;
-        } else {
-            match calc::expr(&input) {
-                Ok(res) => println!("Result: {res}"),
-                Err(err_lint) => println!("{err_lint}")
-            }
+        }
+        let res = match input.strip_prefix(':') {
+            Some(cmd) => repl.command(cmd),
+            None => repl.eval(input),
+        };
+        match res {
+            Ok(msg) => println!("{msg}"),
+            Err(err_lint) => println!("{err_lint}")
         }
     }
 }